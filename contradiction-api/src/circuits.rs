@@ -0,0 +1,86 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+#[cfg(feature = "risc0")]
+use contradiction_risc0_methods as methods;
+
+/// Inputs of a circuit, tagged with the circuit's name: `{"Hypotenuse": {"x": 3, "y": 4}}`.
+#[derive(Debug, Deserialize, Serialize,)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub enum CircuitInputs {
+    Hypotenuse(Hypotenuse),
+    LinearPolynomial(LinearPolynomial),
+}
+
+impl CircuitInputs {
+    pub fn name(&self) -> &'static str {
+        match self {
+            CircuitInputs::Hypotenuse(_) => "Hypotenuse",
+            CircuitInputs::LinearPolynomial(_) => "LinearPolynomial",
+        }
+    }
+
+    /// Recorded as the receipt's `input_hash`.
+    pub fn hash(&self) -> serde_json::Result<String> {
+        Ok(hex::encode(Sha256::digest(serde_json::to_vec(self)?)))
+    }
+
+    /// Guest ELF and image ID of the circuit the inputs are for.
+    #[cfg(feature = "risc0")]
+    pub fn image(&self) -> (&'static [u8], [u32; 8]) {
+        match self {
+            CircuitInputs::Hypotenuse(_) => (methods::HYPOTENUSE_ELF, methods::HYPOTENUSE_ID),
+            CircuitInputs::LinearPolynomial(_) => (methods::LINEAR_POLYNOMIAL_ELF, methods::LINEAR_POLYNOMIAL_ID),
+        }
+    }
+}
+
+/// Every circuit receipts can be verified against, by name.
+#[cfg(feature = "risc0")]
+pub const CIRCUITS: &[(&str, [u32; 8])] = &[
+    ("Hypotenuse", methods::HYPOTENUSE_ID),
+    ("LinearPolynomial", methods::LINEAR_POLYNOMIAL_ID),
+];
+
+/// Guest ELF of a circuit by name.
+#[cfg(feature = "risc0")]
+pub fn circuit_elf(circuit: &str) -> Option<&'static [u8]> {
+    match circuit {
+        "Hypotenuse" => Some(methods::HYPOTENUSE_ELF),
+        "LinearPolynomial" => Some(methods::LINEAR_POLYNOMIAL_ELF),
+        _ => None,
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Hypotenuse {
+    pub x: u32,
+    pub y: u32,
+}
+
+/// `a * x + b` evaluated at `x = c`, the journal holds the value.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct LinearPolynomial {
+    pub a: u32,
+    pub b: u32,
+    pub c: u32,
+}
+
+/// Hex encoding of an image ID, in the same byte order risc0 uses for digests.
+pub fn image_id_hex(id: [u32; 8]) -> String {
+    hex::encode(id.iter().flat_map(|word| word.to_le_bytes()).collect::<Vec<u8>>())
+}
+
+/// Image ID from its hex encoding, as found in receipt metadata.
+pub fn parse_image_id(image_id: &str) -> Option<[u32; 8]> {
+    let bytes = hex::decode(image_id).ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    let mut id = [0u32; 8];
+    for (word, chunk) in id.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]);
+    }
+    Some(id)
+}
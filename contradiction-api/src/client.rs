@@ -0,0 +1,436 @@
+use std::time::Duration;
+use bytes::Bytes;
+use reqwest::{header, RequestBuilder, Response, StatusCode};
+use serde::{de::DeserializeOwned, Serialize};
+use crate::{circuits::CircuitInputs, error::{Error, Result}, models};
+
+#[cfg(feature = "risc0")]
+use risc0_zkvm::Receipt;
+
+#[cfg(feature = "risc0")]
+const OCTET_STREAM: &str = "application/octet-stream";
+const DEFAULT_RETRIES: u32 = 3;
+const DEFAULT_BACKOFF: Duration = Duration::from_millis(250);
+
+/// Async client of a single node.
+///
+/// Failed requests are retried with exponential backoff when trying again may help. Requests that are not
+/// safe to repeat, like starting a computation, are only retried if they never reached the node.
+#[derive(Clone, Debug)]
+pub struct Client {
+    base: String,
+    http: reqwest::Client,
+    retries: u32,
+    backoff: Duration,
+    timeout: Option<Duration>,
+    headers: header::HeaderMap,
+}
+
+/// A receipt as served by a node, with who vouches for it.
+#[cfg(feature = "risc0")]
+#[derive(Debug)]
+pub struct Fetched {
+    pub receipt: Receipt,
+    /// Node the receipt was read from
+    pub served_by: String,
+    /// Nodes known to have verified the receipt
+    pub attested_by: Vec<String>,
+    pub quorum: bool,
+}
+
+/// Successful responses pass, anything else becomes an error carrying the node's message.
+async fn check(resp: Response) -> Result<Response> {
+    let status = resp.status();
+    if status.is_success() {
+        return Ok(resp);
+    }
+    let body = resp.bytes().await.unwrap_or_default();
+    // older nodes answer with a DefaultResponse or plain text
+    let (code, message) = if let Ok(problem) = serde_json::from_slice::<models::Problem>(&body) {
+        (Some(problem.code), problem.detail)
+    } else if let Ok(payload) = serde_json::from_slice::<models::DefaultResponse>(&body) {
+        (None, payload.text)
+    } else {
+        (None, String::from_utf8_lossy(&body).to_string())
+    };
+    Err(Error::Status { status, code, message })
+}
+
+/// 404 means there is nothing to return.
+fn found<T>(result: Result<T>) -> Result<Option<T>> {
+    match result {
+        Ok(value) => Ok(Some(value)),
+        Err(e) if e.is_not_found() => Ok(None),
+        Err(e) => Err(e),
+    }
+}
+
+impl Client {
+    /// `base` is the node's URL, e.g. `http://127.0.0.1:8080`.
+    pub fn new(base: &str) -> Self {
+        Self::with_http(reqwest::Client::new(), base)
+    }
+
+    /// Share a connection pool between clients of several nodes.
+    pub fn with_http(http: reqwest::Client, base: &str) -> Self {
+        Client {
+            base: base.trim_end_matches('/').to_string(),
+            http,
+            retries: DEFAULT_RETRIES,
+            backoff: DEFAULT_BACKOFF,
+            timeout: None,
+            headers: header::HeaderMap::new(),
+        }
+    }
+
+    /// How often a failed request is tried again, 0 to never retry.
+    pub fn retries(mut self, retries: u32) -> Self {
+        self.retries = retries;
+        self
+    }
+
+    /// Delay before the first retry, doubling with each one after it.
+    pub fn backoff(mut self, backoff: Duration) -> Self {
+        self.backoff = backoff;
+        self
+    }
+
+    /// Time limit of each attempt.
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    /// Headers sent along with every request, e.g. to propagate a trace.
+    pub fn headers(mut self, headers: header::HeaderMap) -> Self {
+        self.headers.extend(headers);
+        self
+    }
+
+    pub fn base(&self) -> &str {
+        &self.base
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.base, path)
+    }
+
+    async fn send(&self, req: RequestBuilder, idempotent: bool) -> Result<Response> {
+        let req = match self.timeout {
+            Some(timeout) => req.timeout(timeout),
+            None => req,
+        };
+        let req = req.headers(self.headers.clone());
+        let mut attempt = 0;
+        loop {
+            // only streamed bodies can't be cloned, and those are never sent
+            let Some(current) = req.try_clone() else {
+                return check(req.send().await?).await;
+            };
+            let result = match current.send().await {
+                Ok(resp) => check(resp).await,
+                Err(e) => Err(Error::from(e)),
+            };
+            let unsent = matches!(&result, Err(Error::Transport(e)) if e.is_connect());
+            match result {
+                Err(e) if attempt < self.retries && (unsent || (idempotent && e.is_retryable())) => {
+                    log::debug!("Request to {} failed, retrying: {}", self.base, e);
+                    tokio::time::sleep(self.backoff.saturating_mul(1 << attempt.min(16))).await;
+                    attempt += 1;
+                },
+                result => return result,
+            }
+        }
+    }
+
+    /// GET a JSON response from any path of the node.
+    pub async fn get<T: DeserializeOwned>(&self, path: &str, query: &[(&str, &str)]) -> Result<T> {
+        let resp = self.send(self.http.get(self.url(path)).query(query), true).await?;
+        Ok(serde_json::from_slice(&resp.bytes().await?)?)
+    }
+
+    /// POST a JSON body to any path of the node, only retried if it never reached the node.
+    pub async fn post<Req: Serialize, Resp: DeserializeOwned>(&self, path: &str, body: &Req) -> Result<Resp> {
+        let resp = self.post_raw(path, serde_json::to_vec(body)?).await?;
+        Ok(serde_json::from_slice(&resp)?)
+    }
+
+    /// POST an already serialized JSON body, returning the response body as is.
+    pub async fn post_raw(&self, path: &str, body: Vec<u8>) -> Result<Bytes> {
+        let req = self.http.post(self.url(path))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(body);
+        Ok(self.send(req, false).await?.bytes().await?)
+    }
+
+    pub async fn ping(&self) -> Result<models::NodeStatus> {
+        self.get("/v1/ping", &[]).await
+    }
+
+    pub async fn nodes(&self) -> Result<Vec<models::Node>> {
+        self.get("/v1/nodes", &[]).await
+    }
+
+    /// Introduce a node, false if it was already known.
+    pub async fn register_node(&self, address: &str, port: u16) -> Result<bool> {
+        let node = models::Node { address: address.to_string(), port, last_ping_at: None };
+        let req = self.http.post(self.url("/v1/nodes")).body(serde_json::to_vec(&node)?);
+        match self.send(req, true).await {
+            Ok(_) => Ok(true),
+            Err(e) if e.status() == Some(StatusCode::CONFLICT) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Prove a circuit while waiting, returning the receipt's UUID.
+    pub async fn compute(&self, inputs: &CircuitInputs) -> Result<String> {
+        let payload: models::DefaultResponse = self.post("/v1/compute", inputs).await?;
+        Ok(payload.text)
+    }
+
+    /// Queue a circuit to be proven, its receipt will be stored under the job's UUID.
+    pub async fn submit_job(&self, inputs: &CircuitInputs) -> Result<models::Job> {
+        self.post("/v1/jobs", inputs).await
+    }
+
+    pub async fn job(&self, uuid: &str) -> Result<Option<models::Job>> {
+        found(self.get(&format!("/v1/jobs/{}", uuid), &[]).await)
+    }
+
+    /// Poll a job until it is done, a failed job is returned as an error.
+    pub async fn wait_for_job(&self, uuid: &str, interval: Duration) -> Result<models::Job> {
+        loop {
+            let job: models::Job = self.get(&format!("/v1/jobs/{}", uuid), &[]).await?;
+            match job.status.as_str() {
+                "done" => return Ok(job),
+                "failed" => return Err(Error::JobFailed { uuid: job.uuid, message: job.error.unwrap_or_default() }),
+                _ => tokio::time::sleep(interval).await,
+            }
+        }
+    }
+
+    /// Fetch a receipt, the node asks its peers if it doesn't hold it.
+    #[cfg(feature = "risc0")]
+    pub async fn fetch(&self, uuid: &str) -> Result<Option<Fetched>> {
+        self.fetch_receipt(uuid, &[]).await
+    }
+
+    /// Fetch a receipt only if the node holds it itself.
+    #[cfg(feature = "risc0")]
+    pub async fn fetch_local(&self, uuid: &str) -> Result<Option<Fetched>> {
+        self.fetch_receipt(uuid, &[("local", "true")]).await
+    }
+
+    #[cfg(feature = "risc0")]
+    async fn fetch_receipt(&self, uuid: &str, query: &[(&str, &str)]) -> Result<Option<Fetched>> {
+        let req = self.http.get(self.url(&format!("/v1/receipts/{}/proof", uuid)))
+            .query(query)
+            .header(header::ACCEPT, OCTET_STREAM);
+        let Some(resp) = found(self.send(req, true).await)? else {
+            return Ok(None);
+        };
+        let header = |name: &str| resp.headers()
+            .get(name)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let binary = header(header::CONTENT_TYPE.as_str()) == OCTET_STREAM;
+        let served_by = header("X-Served-By");
+        let attested_by: Vec<String> = header("X-Attested-By")
+            .split(',')
+            .filter(|node| !node.is_empty())
+            .map(str::to_string)
+            .collect();
+        let quorum = header("X-Quorum") == "true";
+        let body = resp.bytes().await?;
+
+        if binary {
+            return Ok(Some(Fetched { receipt: bincode::deserialize(&body)?, served_by, attested_by, quorum }));
+        }
+        // nodes that predate binary responses answer with JSON, the oldest without who vouches for it
+        let payload: models::DefaultResponse = serde_json::from_slice(&body)?;
+        let receipt = serde_json::from_str(&payload.text)?;
+        Ok(Some(match serde_json::from_slice::<models::FetchResponse>(&body) {
+            Ok(r) => Fetched { receipt, served_by: r.served_by, attested_by: r.attested_by, quorum: r.quorum },
+            Err(_) => Fetched { receipt, served_by: self.base.clone(), attested_by: Vec::new(), quorum: false },
+        }))
+    }
+
+    /// Have the node verify and store a receipt, it answers with its signed attestation. Only retried if it
+    /// never reached the node, a failed attempt may have stored the receipt and the next would get a 409.
+    #[cfg(feature = "risc0")]
+    pub async fn verify(&self, receipt: &models::IncomingReceipt) -> Result<models::AttestedResponse> {
+        let req = self.http.post(self.url("/v1/receipts"))
+            .header(header::CONTENT_TYPE, "application/json")
+            .body(serde_json::to_vec(receipt)?);
+        Ok(serde_json::from_slice(&self.send(req, false).await?.bytes().await?)?)
+    }
+
+    pub async fn receipts(&self, query: &models::ReceiptQuery) -> Result<models::ReceiptPage> {
+        let resp = self.send(self.http.get(self.url("/v1/receipts")).query(query), true).await?;
+        Ok(serde_json::from_slice(&resp.bytes().await?)?)
+    }
+
+    /// Metadata of a single stored receipt, without the proof.
+    pub async fn receipt(&self, uuid: &str) -> Result<Option<models::ReceiptSummary>> {
+        found(self.get(&format!("/v1/receipts/{}", uuid), &[]).await)
+    }
+
+    pub async fn delivery_status(&self, uuid: &str) -> Result<Vec<models::Delivery>> {
+        self.get(&format!("/v1/deliveries/{}", uuid), &[]).await
+    }
+
+    /// Hashes of the ranges one hex digit longer than `prefix`, every UUID when it is empty.
+    pub async fn sync_summary(&self, prefix: &str) -> Result<Vec<models::SyncRange>> {
+        self.get("/v1/sync/summary", &[("prefix", prefix)]).await
+    }
+
+    pub async fn sync_range(&self, prefix: &str) -> Result<Vec<String>> {
+        self.get(&format!("/v1/sync/ranges/{}", prefix), &[]).await
+    }
+
+    pub async fn pins(&self) -> Result<Vec<models::Pin>> {
+        self.get("/v1/pins", &[]).await
+    }
+
+    /// Exempt a receipt from retention, false if it already was.
+    pub async fn pin(&self, uuid: &str) -> Result<bool> {
+        let resp = self.send(self.http.post(self.url(&format!("/v1/pins/{}", uuid))), true).await?;
+        Ok(resp.status() == StatusCode::CREATED)
+    }
+
+    /// False if the receipt wasn't pinned.
+    pub async fn unpin(&self, uuid: &str) -> Result<bool> {
+        let req = self.http.delete(self.url(&format!("/v1/pins/{}", uuid)));
+        Ok(found(self.send(req, true).await)?.is_some())
+    }
+
+    pub async fn ledger_head(&self) -> Result<Option<models::LedgerEntry>> {
+        found(self.get("/v1/ledger/head", &[]).await)
+    }
+
+    pub async fn ledger_proof(&self, uuid: &str) -> Result<Option<models::InclusionProof>> {
+        found(self.get(&format!("/v1/ledger/proofs/{}", uuid), &[]).await)
+    }
+
+    /// Bundle of the listed receipts, or else of every one matching the filters.
+    pub async fn export(&self, uuids: &[String], circuit: Option<&str>, from: Option<i64>, to: Option<i64>) -> Result<Bytes> {
+        let mut query = Vec::new();
+        if !uuids.is_empty() {
+            query.push(("uuid", uuids.join(",")));
+        }
+        if let Some(circuit) = circuit {
+            query.push(("circuit", circuit.to_string()));
+        }
+        query.extend(from.map(|from| ("from", from.to_string())));
+        query.extend(to.map(|to| ("to", to.to_string())));
+        Ok(self.send(self.http.get(self.url("/v1/bundles")).query(&query), true).await?.bytes().await?)
+    }
+
+    /// Import a bundle, receipts the node already has are skipped so it is safe to send again.
+    pub async fn import(&self, bundle: Vec<u8>) -> Result<models::ImportReport> {
+        let resp = self.send(self.http.post(self.url("/v1/bundles")).body(bundle), true).await?;
+        Ok(serde_json::from_slice(&resp.bytes().await?)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{atomic::{AtomicUsize, Ordering}, Arc};
+    use tokio::{io::{AsyncReadExt, AsyncWriteExt}, net::{TcpListener, TcpStream}};
+    use super::*;
+
+    /// Read a request whole, so closing the connection doesn't reset it before the client reads the answer.
+    async fn read_request(stream: &mut TcpStream) {
+        let mut request = Vec::new();
+        let mut buf = [0u8; 1024];
+        loop {
+            let Ok(n @ 1..) = stream.read(&mut buf).await else { return };
+            request.extend_from_slice(&buf[..n]);
+            let text = String::from_utf8_lossy(&request);
+            let Some(end) = text.find("\r\n\r\n") else { continue };
+            let length = text[..end]
+                .lines()
+                .find_map(|line| line.to_lowercase().strip_prefix("content-length:").map(|v| v.trim().parse::<usize>().unwrap()))
+                .unwrap_or(0);
+            if request.len() >= end + 4 + length {
+                return;
+            }
+        }
+    }
+
+    /// A node answering with the given statuses in turn and the last one from then on, 0 hangs up without an
+    /// answer. Returns a client of it and the number of requests it got.
+    async fn stub(statuses: &'static [u16]) -> (Client, Arc<AtomicUsize>) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = Client::new(&format!("http://{}", listener.local_addr().unwrap())).backoff(Duration::from_millis(1));
+        let requests = Arc::new(AtomicUsize::new(0));
+        let counted = requests.clone();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let n = counted.fetch_add(1, Ordering::SeqCst);
+                read_request(&mut stream).await;
+                let status = statuses[n.min(statuses.len() - 1)];
+                if status == 0 {
+                    continue;
+                }
+                let response = format!("HTTP/1.1 {} Stub\r\nContent-Type: application/json\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{{}}", status);
+                let _ = stream.write_all(response.as_bytes()).await;
+            }
+        });
+        (client, requests)
+    }
+
+    #[tokio::test]
+    async fn idempotent_requests_are_retried_until_they_succeed() {
+        let (client, requests) = stub(&[503, 0, 200]).await;
+        client.get::<serde_json::Value>("/v1/ping", &[]).await.unwrap();
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        // but not past the retries, nor when the node refuses the request
+        let (client, requests) = stub(&[503]).await;
+        assert_eq!(client.retries(2).get::<serde_json::Value>("/v1/ping", &[]).await.unwrap_err().status(), Some(StatusCode::SERVICE_UNAVAILABLE));
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+        let (client, requests) = stub(&[400]).await;
+        assert!(client.get::<serde_json::Value>("/v1/ping", &[]).await.is_err());
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn posts_that_reached_the_node_are_not_retried() {
+        for statuses in [&[503, 200][..], &[0, 200]] {
+            let (client, requests) = stub(statuses).await;
+            assert!(client.post_raw("/v1/compute", b"{}".to_vec()).await.is_err());
+            assert_eq!(requests.load(Ordering::SeqCst), 1, "after {}", statuses[0]);
+        }
+    }
+
+    #[tokio::test]
+    async fn posts_are_retried_when_the_node_could_not_be_reached() {
+        // nothing listens on the port until after the first attempt
+        let port = TcpListener::bind("127.0.0.1:0").await.unwrap().local_addr().unwrap().port();
+        let client = Client::new(&format!("http://127.0.0.1:{}", port)).backoff(Duration::from_millis(200));
+        let node = tokio::spawn(async move {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            let listener = TcpListener::bind(("127.0.0.1", port)).await.unwrap();
+            let (mut stream, _) = listener.accept().await.unwrap();
+            read_request(&mut stream).await;
+            stream.write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 2\r\nConnection: close\r\n\r\n{}").await.unwrap();
+        });
+        assert_eq!(client.post_raw("/v1/compute", b"{}".to_vec()).await.unwrap(), "{}");
+        node.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn not_found_is_none() {
+        let (client, requests) = stub(&[404]).await;
+        assert!(client.receipt("missing").await.unwrap().is_none());
+        assert!(client.job("missing").await.unwrap().is_none());
+        assert!(!client.unpin("missing").await.unwrap());
+        // a 404 is an answer, not retried
+        assert_eq!(requests.load(Ordering::SeqCst), 3);
+
+        let (client, _) = stub(&[500]).await;
+        assert_eq!(client.retries(0).receipt("broken").await.unwrap_err().status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+}
@@ -0,0 +1,53 @@
+use reqwest::StatusCode;
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// The node could not be reached, or the connection broke off or timed out
+    #[error("request failed: {0}")]
+    Transport(#[from] reqwest::Error),
+    /// The node answered, but not with success
+    #[error("node responded with status {status}: {message}")]
+    Status { status: StatusCode, code: Option<String>, message: String },
+    #[error("job {uuid} failed: {message}")]
+    JobFailed { uuid: String, message: String },
+    #[error("malformed response: {0}")]
+    Json(#[from] serde_json::Error),
+    #[cfg(feature = "risc0")]
+    #[error("malformed receipt: {0}")]
+    Bincode(#[from] bincode::Error),
+}
+
+impl Error {
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            Error::Status { status, .. } => Some(*status),
+            Error::Transport(e) => e.status(),
+            _ => None,
+        }
+    }
+
+    /// The node's machine-readable error code, see `models::Problem`.
+    pub fn code(&self) -> Option<&str> {
+        match self {
+            Error::Status { code, .. } => code.as_deref(),
+            _ => None,
+        }
+    }
+
+    pub fn is_not_found(&self) -> bool {
+        self.status() == Some(StatusCode::NOT_FOUND)
+    }
+
+    /// Whether trying again later may succeed, a node refusing the request won't change its mind.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Error::Transport(e) => e.is_connect() || e.is_timeout() || e.is_request(),
+            Error::Status { status, .. } => {
+                status.is_server_error() || *status == StatusCode::REQUEST_TIMEOUT || *status == StatusCode::TOO_MANY_REQUESTS
+            },
+            _ => false,
+        }
+    }
+}
@@ -0,0 +1,14 @@
+//! Wire types of the contradiction node API and an async client for it.
+//!
+//! The `risc0` feature (default) adds receipts and the endpoints carrying them, `sqlx` derives `FromRow`
+//! for the types nodes store as they are.
+
+pub mod circuits;
+pub mod client;
+pub mod error;
+pub mod models;
+
+pub use client::Client;
+#[cfg(feature = "risc0")]
+pub use client::Fetched;
+pub use error::{Error, Result};
@@ -0,0 +1,231 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct DefaultResponse {
+    pub status_code: u16,
+    pub text: String,
+}
+
+/// Body of every error response, served as `application/problem+json`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct Problem {
+    /// Stable, machine-readable kind of error: not_found, method_not_allowed, validation_failed, conflict,
+    /// verification_failed, unauthorized, payload_too_large, rate_limited or internal_error
+    pub code: String,
+    pub title: String,
+    pub status: u16,
+    pub detail: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct FetchResponse {
+    pub status_code: u16,
+    pub text: String,
+    /// Node the receipt was read from
+    pub served_by: String,
+    /// Nodes known to have verified the receipt
+    pub attested_by: Vec<String>,
+    pub quorum: bool,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct AttestedResponse {
+    pub status_code: u16,
+    pub text: String,
+    pub attestation: Attestation,
+}
+
+/// A node's signed statement that it verified a receipt.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Attestation {
+    pub uuid: String,
+    pub node: String,
+    pub image_id: String,
+    pub journal_hash: String,
+    pub public_key: String,
+    pub signature: String,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Node {
+    pub address: String,
+    pub port: u16,
+    #[cfg_attr(feature = "sqlx", sqlx(default))]
+    pub last_ping_at: Option<chrono::NaiveDateTime>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct NodeStatus {
+    pub status: String,
+    pub timestamp: chrono::NaiveDateTime,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Delivery {
+    pub address: String,
+    pub port: u16,
+    pub status: String,
+    pub attempts: u32,
+    pub next_retry_at: i64,
+    pub last_error: Option<String>,
+    pub updated_at: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct SyncRange {
+    pub prefix: String,
+    pub count: usize,
+    pub hash: String,
+}
+
+/// What is known about a stored receipt besides the receipt itself.
+/// `prover_node`, `proving_ms` and `cycles` are only known on the node that proved it and travel with replicas.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct ReceiptMetadata {
+    pub circuit: Option<String>,
+    pub image_id: Option<String>,
+    pub journal: Option<Vec<u8>>,
+    pub proof_kind: Option<String>,
+    pub prover_node: Option<String>,
+    pub submitter: Option<String>,
+    pub input_hash: Option<String>,
+    pub created_at: Option<i64>,
+    pub proving_ms: Option<i64>,
+    pub cycles: Option<i64>,
+    pub size: Option<i64>,
+}
+
+/// A receipt in a listing, its metadata without the proof. The journal is hex encoded.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct ReceiptSummary {
+    pub uuid: String,
+    pub circuit: Option<String>,
+    pub image_id: Option<String>,
+    pub journal: Option<String>,
+    pub proof_kind: Option<String>,
+    pub prover_node: Option<String>,
+    pub submitter: Option<String>,
+    pub input_hash: Option<String>,
+    pub created_at: Option<i64>,
+    pub proving_ms: Option<i64>,
+    pub cycles: Option<i64>,
+    pub size: Option<i64>,
+}
+
+/// Filters of a receipt listing, see `/api/receipts`. Unset ones are left to the node's defaults.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema, utoipa::IntoParams), into_params(parameter_in = Query))]
+pub struct ReceiptQuery {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub circuit: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub submitter: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prover_node: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub from: Option<i64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub to: Option<i64>,
+    /// created_at, size, cycles or proving_ms
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub sort: Option<String>,
+    /// asc or desc
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub order: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub limit: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ReceiptPage {
+    pub receipts: Vec<ReceiptSummary>,
+    // pass as `cursor` to get the next page, missing on the last one
+    pub next_cursor: Option<String>,
+}
+
+/// A proving job submitted through `/api/jobs`, its receipt is stored under the job's UUID once done.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Job {
+    pub uuid: String,
+    pub circuit: String,
+    /// queued, running, done or failed
+    pub status: String,
+    pub error: Option<String>,
+    pub submitter: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+}
+
+/// Outcome of importing a bundle. Receipts already stored or pruned here are skipped.
+#[derive(Debug, Default, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct ImportReport {
+    pub imported: usize,
+    pub skipped: usize,
+}
+
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct Pin {
+    pub uuid: String,
+    pub created_at: i64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+#[cfg_attr(feature = "sqlx", derive(sqlx::FromRow))]
+pub struct LedgerEntry {
+    pub seq: i64,
+    pub uuid: String,
+    pub receipt_hash: String,
+    pub prev_hash: String,
+    pub entry_hash: String,
+    pub created_at: i64,
+}
+
+/// A ledger entry and the entries following it up to the current head.
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct InclusionProof {
+    pub entry: LedgerEntry,
+    pub following: Vec<LedgerEntry>,
+    pub head: String,
+}
+
+/// A receipt sent to a peer to verify and store, see `POST /v1/receipts`.
+#[cfg(feature = "risc0")]
+#[derive(Debug, Deserialize, Serialize)]
+#[cfg_attr(feature = "openapi", derive(utoipa::ToSchema))]
+pub struct IncomingReceipt {
+    pub uuid: String,
+    pub circuit: crate::circuits::CircuitInputs,
+    #[cfg_attr(feature = "openapi", schema(value_type = Object))]
+    pub receipt: risc0_zkvm::Receipt,
+    // left out by nodes that predate it
+    #[serde(default)]
+    pub metadata: Option<ReceiptMetadata>,
+}
@@ -0,0 +1,145 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use clap::{Parser, Subcommand};
+use contradiction_api::{circuits::{parse_image_id, CircuitInputs, CIRCUITS}, models, Client};
+use risc0_zkvm::Receipt;
+use anyhow::Result;
+
+#[derive(Parser)]
+#[command(version, about = "Submit proving jobs to a contradiction node and verify receipts")]
+struct Cli {
+    /// Node to talk to
+    #[arg(short, long, global = true, env = "CONTRADICTION_NODE", default_value = "http://127.0.0.1:8080")]
+    node: String,
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Queue a circuit to be proven, e.g. `submit Hypotenuse '{"x":3,"y":4}'`
+    Submit {
+        circuit: String,
+        /// Circuit inputs as JSON, `-` reads them from stdin
+        inputs: String,
+        /// Wait for the job to finish and download the receipt
+        #[arg(short, long)]
+        wait: bool,
+        #[arg(short, long, default_value = "receipt.bin")]
+        output: PathBuf,
+        /// Seconds between status checks while waiting
+        #[arg(long, default_value_t = 2)]
+        interval: u64,
+    },
+    /// Show the status of a job
+    Status { uuid: String },
+    /// Wait for a job to finish and download the receipt
+    Wait {
+        uuid: String,
+        #[arg(short, long, default_value = "receipt.bin")]
+        output: PathBuf,
+        #[arg(long, default_value_t = 2)]
+        interval: u64,
+    },
+    /// Download a receipt
+    Fetch {
+        uuid: String,
+        #[arg(short, long, default_value = "receipt.bin")]
+        output: PathBuf,
+    },
+    /// Verify a saved receipt without a node, against a known circuit or any image ID
+    Verify {
+        file: PathBuf,
+        #[arg(long, conflicts_with = "image_id", required_unless_present = "image_id")]
+        circuit: Option<String>,
+        /// Hex encoded, as listed in receipt metadata
+        #[arg(long)]
+        image_id: Option<String>,
+    },
+}
+
+async fn submit(client: &Client, circuit: &str, inputs: &str) -> Result<models::Job> {
+    let inputs: serde_json::Value = match inputs {
+        "-" => serde_json::from_reader(std::io::stdin())?,
+        inputs => serde_json::from_str(inputs)?,
+    };
+    // circuit inputs are tagged with the circuit's name
+    let inputs: CircuitInputs = serde_json::from_value(serde_json::json!({ circuit: inputs }))
+        .map_err(|e| anyhow::anyhow!("Invalid inputs for circuit {}: {}", circuit, e))?;
+    Ok(client.submit_job(&inputs).await?)
+}
+
+async fn fetch(client: &Client, uuid: &str, output: &Path) -> Result<()> {
+    let fetched = client.fetch(uuid)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Receipt {} not found", uuid))?;
+    eprintln!("Served by {}, attested by {}, quorum {}.", fetched.served_by, fetched.attested_by.join(","), fetched.quorum);
+
+    std::fs::write(output, bincode::serialize(&fetched.receipt)?)?;
+    println!("Wrote receipt {} to {}", uuid, output.display());
+    Ok(())
+}
+
+/// Receipts are saved bincode encoded, JSON ones written by the node CLI work too.
+fn read_receipt(path: &Path) -> Result<Receipt> {
+    let bytes = std::fs::read(path)?;
+    match bytes.iter().find(|b| !b.is_ascii_whitespace()) {
+        Some(b'{') => Ok(serde_json::from_slice(&bytes)?),
+        _ => Ok(bincode::deserialize(&bytes)?),
+    }
+}
+
+fn verify(file: &Path, circuit: Option<String>, image_id: Option<String>) -> Result<()> {
+    let receipt = read_receipt(file)?;
+    let (name, id) = match (circuit, image_id) {
+        (Some(circuit), _) => {
+            let (_, id) = CIRCUITS
+                .iter()
+                .find(|(name, _)| *name == circuit)
+                .ok_or_else(|| anyhow::anyhow!("Unknown circuit {}, pass its --image-id instead", circuit))?;
+            (circuit, *id)
+        },
+        (None, Some(image_id)) => {
+            let id = parse_image_id(&image_id).ok_or_else(|| anyhow::anyhow!("Image ID {} is not 32 hex encoded bytes", image_id))?;
+            (image_id, id)
+        },
+        (None, None) => return Err(anyhow::anyhow!("Pass a --circuit or an --image-id to verify against")),
+    };
+    receipt.verify(id).map_err(|e| anyhow::anyhow!("Receipt does not verify against {}: {}", name, e))?;
+    println!("Receipt verifies against {}.", name);
+    println!("Journal: {}", hex::encode(&receipt.journal.bytes));
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let client = Client::new(&cli.node);
+
+    match cli.command {
+        Command::Submit { circuit, inputs, wait, output, interval } => {
+            let job = submit(&client, &circuit, &inputs).await?;
+            println!("Submitted {} job {}", job.circuit, job.uuid);
+            if wait {
+                client.wait_for_job(&job.uuid, Duration::from_secs(interval)).await?;
+                fetch(&client, &job.uuid, &output).await?;
+            }
+        },
+        Command::Status { uuid } => {
+            let job = client.job(&uuid)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Job {} not found", uuid))?;
+            match job.error {
+                Some(error) => println!("{} {}: {}", job.circuit, job.status, error),
+                None => println!("{} {}", job.circuit, job.status),
+            }
+        },
+        Command::Wait { uuid, output, interval } => {
+            client.wait_for_job(&uuid, Duration::from_secs(interval)).await?;
+            fetch(&client, &uuid, &output).await?;
+        },
+        Command::Fetch { uuid, output } => fetch(&client, &uuid, &output).await?,
+        Command::Verify { file, circuit, image_id } => verify(&file, circuit, image_id)?,
+    }
+    Ok(())
+}
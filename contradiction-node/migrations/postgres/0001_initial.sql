@@ -0,0 +1,85 @@
+-- Same schema as the SQLite migrations up to 0004, Postgres databases start out at this point.
+
+CREATE TABLE nodes (
+    address TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    PRIMARY KEY (address, port)
+);
+
+CREATE TABLE receipts (
+    uuid TEXT PRIMARY KEY,
+    receipt BYTEA NOT NULL,
+    encoding TEXT NOT NULL DEFAULT 'json',
+    circuit TEXT,
+    image_id TEXT,
+    journal BYTEA,
+    proof_kind TEXT,
+    prover_node TEXT,
+    input_hash TEXT,
+    created_at BIGINT,
+    proving_ms BIGINT,
+    cycles BIGINT,
+    size BIGINT,
+    submitter TEXT
+);
+
+CREATE INDEX receipts_circuit ON receipts (circuit);
+CREATE INDEX receipts_created_at ON receipts (created_at);
+CREATE INDEX receipts_image_id ON receipts (image_id);
+CREATE INDEX receipts_prover_node ON receipts (prover_node);
+CREATE INDEX receipts_submitter ON receipts (submitter);
+CREATE INDEX receipts_encoding ON receipts (encoding);
+
+CREATE TABLE outbox_payloads (
+    uuid TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    payload TEXT NOT NULL
+);
+
+CREATE TABLE outbox (
+    uuid TEXT NOT NULL,
+    address TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_retry_at BIGINT NOT NULL,
+    last_error TEXT,
+    updated_at BIGINT NOT NULL,
+    PRIMARY KEY (uuid, address, port)
+);
+
+CREATE TABLE attestations (
+    uuid TEXT NOT NULL,
+    node TEXT NOT NULL,
+    image_id TEXT NOT NULL,
+    journal_hash TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at BIGINT NOT NULL,
+    PRIMARY KEY (uuid, node)
+);
+
+CREATE TABLE consensus_state (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    term BIGINT NOT NULL,
+    voted_for TEXT,
+    commit_index BIGINT NOT NULL
+);
+
+CREATE TABLE consensus_log (
+    log_index BIGINT PRIMARY KEY,
+    term BIGINT NOT NULL,
+    uuid TEXT NOT NULL,
+    image_id TEXT NOT NULL,
+    journal_hash TEXT NOT NULL,
+    submitter TEXT NOT NULL
+);
+
+CREATE TABLE ledger (
+    seq BIGINT PRIMARY KEY,
+    uuid TEXT NOT NULL UNIQUE,
+    receipt_hash TEXT NOT NULL,
+    prev_hash TEXT NOT NULL,
+    entry_hash TEXT NOT NULL,
+    created_at BIGINT NOT NULL
+);
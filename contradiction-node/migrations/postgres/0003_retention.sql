@@ -0,0 +1,13 @@
+-- Pinned receipts are never pruned. Pruned receipts leave a tombstone so they are not pulled back in from peers,
+-- their ledger entries stay.
+
+CREATE TABLE pins (
+    uuid TEXT PRIMARY KEY,
+    created_at BIGINT NOT NULL
+);
+
+CREATE TABLE tombstones (
+    uuid TEXT PRIMARY KEY,
+    circuit TEXT,
+    pruned_at BIGINT NOT NULL
+);
@@ -0,0 +1,8 @@
+-- Guest ELFs of circuits this node was not built with, kept from imported bundles so their receipts can be
+-- exported again.
+
+CREATE TABLE elfs (
+    image_id TEXT PRIMARY KEY,
+    circuit TEXT NOT NULL,
+    elf BYTEA NOT NULL
+);
@@ -0,0 +1,14 @@
+-- Proving jobs submitted through /api/jobs, the receipt of a finished job is stored under the job's UUID.
+
+CREATE TABLE jobs (
+    uuid TEXT PRIMARY KEY,
+    circuit TEXT NOT NULL,
+    inputs TEXT NOT NULL,
+    status TEXT NOT NULL,
+    error TEXT,
+    submitter TEXT,
+    created_at BIGINT NOT NULL,
+    updated_at BIGINT NOT NULL
+);
+
+CREATE INDEX jobs_status ON jobs (status, created_at);
@@ -0,0 +1,7 @@
+-- Public keys of peers, pinned the first time they attest a receipt unless set in the config.
+
+CREATE TABLE peer_keys (
+    node TEXT PRIMARY KEY,
+    public_key TEXT NOT NULL,
+    created_at BIGINT NOT NULL
+);
@@ -0,0 +1,69 @@
+-- Databases created before migrations existed already have some of these tables,
+-- so everything here is created only if missing.
+
+CREATE TABLE IF NOT EXISTS nodes (
+    address TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    last_ping_at INTEGER,
+    PRIMARY KEY (address, port)
+);
+
+CREATE TABLE IF NOT EXISTS receipts (
+    uuid TEXT PRIMARY KEY,
+    receipt TEXT NOT NULL
+);
+
+-- payloads are stored once per receipt and shared by every delivery
+CREATE TABLE IF NOT EXISTS outbox_payloads (
+    uuid TEXT PRIMARY KEY,
+    path TEXT NOT NULL,
+    payload TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS outbox (
+    uuid TEXT NOT NULL,
+    address TEXT NOT NULL,
+    port INTEGER NOT NULL,
+    status TEXT NOT NULL DEFAULT 'pending',
+    attempts INTEGER NOT NULL DEFAULT 0,
+    next_retry_at INTEGER NOT NULL,
+    last_error TEXT,
+    updated_at INTEGER NOT NULL,
+    PRIMARY KEY (uuid, address, port)
+);
+
+CREATE TABLE IF NOT EXISTS attestations (
+    uuid TEXT NOT NULL,
+    node TEXT NOT NULL,
+    image_id TEXT NOT NULL,
+    journal_hash TEXT NOT NULL,
+    public_key TEXT NOT NULL,
+    signature TEXT NOT NULL,
+    created_at INTEGER NOT NULL,
+    PRIMARY KEY (uuid, node)
+);
+
+CREATE TABLE IF NOT EXISTS consensus_state (
+    id INTEGER PRIMARY KEY CHECK (id = 1),
+    term INTEGER NOT NULL,
+    voted_for TEXT,
+    commit_index INTEGER NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS consensus_log (
+    log_index INTEGER PRIMARY KEY,
+    term INTEGER NOT NULL,
+    uuid TEXT NOT NULL,
+    image_id TEXT NOT NULL,
+    journal_hash TEXT NOT NULL,
+    submitter TEXT NOT NULL
+);
+
+CREATE TABLE IF NOT EXISTS ledger (
+    seq INTEGER PRIMARY KEY,
+    uuid TEXT NOT NULL UNIQUE,
+    receipt_hash TEXT NOT NULL,
+    prev_hash TEXT NOT NULL,
+    entry_hash TEXT NOT NULL,
+    created_at INTEGER NOT NULL
+);
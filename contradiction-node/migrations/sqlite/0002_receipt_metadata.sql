@@ -0,0 +1,18 @@
+-- Metadata about each receipt, so receipts can be filtered and audited without deserializing them.
+-- Receipts stored before this migration only get their size, the rest stays NULL.
+
+ALTER TABLE receipts ADD COLUMN circuit TEXT;
+ALTER TABLE receipts ADD COLUMN image_id TEXT;
+ALTER TABLE receipts ADD COLUMN journal BLOB;
+ALTER TABLE receipts ADD COLUMN proof_kind TEXT;
+ALTER TABLE receipts ADD COLUMN prover_node TEXT;
+ALTER TABLE receipts ADD COLUMN input_hash TEXT;
+ALTER TABLE receipts ADD COLUMN created_at INTEGER;
+ALTER TABLE receipts ADD COLUMN proving_ms INTEGER;
+ALTER TABLE receipts ADD COLUMN cycles INTEGER;
+ALTER TABLE receipts ADD COLUMN size INTEGER;
+
+UPDATE receipts SET size = length(CAST(receipt AS BLOB));
+
+CREATE INDEX IF NOT EXISTS receipts_circuit ON receipts (circuit);
+CREATE INDEX IF NOT EXISTS receipts_created_at ON receipts (created_at);
@@ -0,0 +1,7 @@
+-- Who asked for the proof, and indexes for the receipt listing filters.
+
+ALTER TABLE receipts ADD COLUMN submitter TEXT;
+
+CREATE INDEX IF NOT EXISTS receipts_image_id ON receipts (image_id);
+CREATE INDEX IF NOT EXISTS receipts_prover_node ON receipts (prover_node);
+CREATE INDEX IF NOT EXISTS receipts_submitter ON receipts (submitter);
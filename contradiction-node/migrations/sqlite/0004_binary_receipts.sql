@@ -0,0 +1,33 @@
+-- Receipts are stored as BLOBs in the encoding named by `encoding`. Existing rows keep their JSON
+-- text as bytes and are re-encoded in the background once the node is running.
+
+CREATE TABLE receipts_new (
+    uuid TEXT PRIMARY KEY,
+    receipt BLOB NOT NULL,
+    encoding TEXT NOT NULL DEFAULT 'json',
+    circuit TEXT,
+    image_id TEXT,
+    journal BLOB,
+    proof_kind TEXT,
+    prover_node TEXT,
+    input_hash TEXT,
+    created_at INTEGER,
+    proving_ms INTEGER,
+    cycles INTEGER,
+    size INTEGER,
+    submitter TEXT
+);
+
+INSERT INTO receipts_new (uuid, receipt, encoding, circuit, image_id, journal, proof_kind, prover_node, input_hash, created_at, proving_ms, cycles, size, submitter)
+SELECT uuid, CAST(receipt AS BLOB), 'json', circuit, image_id, journal, proof_kind, prover_node, input_hash, created_at, proving_ms, cycles, size, submitter
+FROM receipts;
+
+DROP TABLE receipts;
+ALTER TABLE receipts_new RENAME TO receipts;
+
+CREATE INDEX receipts_circuit ON receipts (circuit);
+CREATE INDEX receipts_created_at ON receipts (created_at);
+CREATE INDEX receipts_image_id ON receipts (image_id);
+CREATE INDEX receipts_prover_node ON receipts (prover_node);
+CREATE INDEX receipts_submitter ON receipts (submitter);
+CREATE INDEX receipts_encoding ON receipts (encoding);
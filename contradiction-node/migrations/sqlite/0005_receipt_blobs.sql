@@ -0,0 +1,5 @@
+-- Receipts moved to a blob store keep an empty `receipt` and the key of their blob in `blob_hash`.
+
+ALTER TABLE receipts ADD COLUMN blob_hash TEXT;
+
+CREATE INDEX receipts_blob_hash ON receipts (blob_hash);
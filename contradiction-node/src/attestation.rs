@@ -0,0 +1,111 @@
+use ed25519_dalek::{Signature, SigningKey, Verifier, VerifyingKey};
+#[cfg(any(feature="risc0", test))]
+use ed25519_dalek::Signer;
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use crate::models;
+
+/// Load the node's signing key, generating and saving a new one if the file doesn't exist.
+pub fn load_or_generate(path: &str) -> Result<SigningKey> {
+    if !std::path::Path::new(path).exists() {
+        let key = SigningKey::generate(&mut rand::rngs::OsRng);
+        save(path, &key)?;
+        log::info!("Generated a new node key in {}.", path);
+        return Ok(key);
+    }
+
+    let bytes = hex::decode(std::fs::read_to_string(path)?.trim())?;
+    let secret: [u8; 32] = bytes
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Node key in {} is not 32 bytes long", path))?;
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+pub fn save(path: &str, key: &SigningKey) -> Result<()> {
+    std::fs::write(path, hex::encode(key.to_bytes()))?;
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        std::fs::set_permissions(path, std::fs::Permissions::from_mode(0o600))?;
+    }
+    Ok(())
+}
+
+/// What is signed, the node id is part of it so an attestation can't be passed off as another node's.
+fn message(node: &str, uuid: &str, image_id: &str, journal_hash: &str) -> String {
+    format!("contradiction-attestation:{}:{}:{}:{}", node, uuid, image_id, journal_hash)
+}
+
+/// Hex encoded SHA-256 of a receipt's journal, as attested.
+pub fn journal_hash(journal: &[u8]) -> String {
+    hex::encode(Sha256::digest(journal))
+}
+
+/// Sign a statement that `node` verified receipt `uuid` against `image_id`.
+#[cfg(any(feature="risc0", test))]
+pub fn attest(key: &SigningKey, node: &str, uuid: &str, image_id: &str, journal_hash: &str) -> models::Attestation {
+    let signature = key.sign(message(node, uuid, image_id, journal_hash).as_bytes());
+    models::Attestation {
+        uuid: uuid.to_string(),
+        node: node.to_string(),
+        image_id: image_id.to_string(),
+        journal_hash: journal_hash.to_string(),
+        public_key: hex::encode(key.verifying_key().to_bytes()),
+        signature: hex::encode(signature.to_bytes()),
+    }
+}
+
+/// Parse a hex encoded ed25519 public key.
+pub fn public_key(hex_key: &str) -> Result<VerifyingKey> {
+    let bytes: [u8; 32] = hex::decode(hex_key)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Public key is not 32 bytes long"))?;
+    Ok(VerifyingKey::from_bytes(&bytes)?)
+}
+
+/// Check an attestation was signed by its node with `pinned`, the public key we know the node by.
+pub fn verify(attestation: &models::Attestation, pinned: &str) -> Result<()> {
+    if attestation.public_key != pinned {
+        return Err(anyhow::anyhow!("Signed with {} instead of the key pinned for {}", attestation.public_key, attestation.node));
+    }
+    let signature: [u8; 64] = hex::decode(&attestation.signature)?
+        .try_into()
+        .map_err(|_| anyhow::anyhow!("Attestation signature is not 64 bytes long"))?;
+
+    let message = message(&attestation.node, &attestation.uuid, &attestation.image_id, &attestation.journal_hash);
+    public_key(pinned)?.verify(message.as_bytes(), &Signature::from_bytes(&signature))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "6f1c2a9e-3b4d-4c8e-9f10-2a3b4c5d6e7f";
+
+    fn signed() -> (SigningKey, models::Attestation) {
+        let key = SigningKey::from_bytes(&[7; 32]);
+        let attestation = attest(&key, "10.0.0.1:8080", UUID, "image", "journal");
+        (key, attestation)
+    }
+
+    #[test]
+    fn attestations_verify_against_the_pinned_key() {
+        let (key, attestation) = signed();
+        assert!(verify(&attestation, &hex::encode(key.verifying_key().to_bytes())).is_ok());
+    }
+
+    #[test]
+    fn attestations_signed_with_another_key_are_rejected() {
+        let (_, attestation) = signed();
+        let other = SigningKey::from_bytes(&[8; 32]);
+        assert!(verify(&attestation, &hex::encode(other.verifying_key().to_bytes())).is_err());
+    }
+
+    #[test]
+    fn attestations_can_not_be_passed_off_as_another_nodes() {
+        let (key, mut attestation) = signed();
+        attestation.node = "10.0.0.2:8080".to_string();
+        assert!(verify(&attestation, &hex::encode(key.verifying_key().to_bytes())).is_err());
+    }
+}
@@ -0,0 +1,75 @@
+use std::path::PathBuf;
+use async_trait::async_trait;
+use anyhow::Result;
+use super::BlobStore;
+
+/// Blobs as files under `root`, fanned out into directories by the first two hex digits of their key.
+pub struct FsBlobStore {
+    root: PathBuf,
+}
+
+impl FsBlobStore {
+    pub fn open(path: &str) -> Result<FsBlobStore> {
+        std::fs::create_dir_all(path)?;
+        Ok(FsBlobStore { root: PathBuf::from(path) })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        if key.len() < 3 || !key.chars().all(|c| c.is_ascii_hexdigit()) {
+            return Err(anyhow::anyhow!("Invalid blob key {}", key));
+        }
+        Ok(self.root.join(&key[..2]).join(key))
+    }
+}
+
+#[async_trait]
+impl BlobStore for FsBlobStore {
+    #[cfg(any(feature="risc0", test))]
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.path(key)?;
+        // rewritten even if it exists, the fresh modification time keeps garbage collection off it
+        tokio::fs::create_dir_all(path.parent().expect("blob paths have a parent")).await?;
+        // write next to it and rename, readers never see a partial blob
+        let partial = path.with_extension(format!("{}.partial", std::process::id()));
+        tokio::fs::write(&partial, bytes).await?;
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(bytes) => Ok(Some(bytes)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<(String, i64)>> {
+        let mut blobs = Vec::new();
+        let mut dirs = tokio::fs::read_dir(&self.root).await?;
+        while let Some(dir) = dirs.next_entry().await? {
+            if !dir.file_type().await?.is_dir() {
+                continue;
+            }
+            let mut files = tokio::fs::read_dir(dir.path()).await?;
+            while let Some(file) = files.next_entry().await? {
+                let name = file.file_name().to_string_lossy().to_string();
+                // leftovers of interrupted writes are not blobs
+                if !super::is_key(&name) {
+                    continue;
+                }
+                let modified = file.metadata().await?.modified()?;
+                let written = chrono::DateTime::<chrono::Utc>::from(modified).timestamp();
+                blobs.push((name, written));
+            }
+        }
+        Ok(blobs)
+    }
+}
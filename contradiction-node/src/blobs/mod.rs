@@ -0,0 +1,96 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use async_trait::async_trait;
+use sha2::{Digest, Sha256};
+use anyhow::Result;
+use crate::{config, storage::Storage};
+
+pub mod fs;
+#[cfg(feature="s3")]
+pub mod s3;
+
+/// Content addressed storage for receipt payloads, keyed by the hex SHA-256 of the bytes.
+#[async_trait]
+pub trait BlobStore: Send + Sync {
+    #[cfg(any(feature="risc0", test))]
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<()>;
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+    async fn delete(&self, key: &str) -> Result<()>;
+    /// Every stored key with the unix time it was last written, objects that aren't blobs left out.
+    async fn list(&self) -> Result<Vec<(String, i64)>>;
+}
+
+pub fn key(bytes: &[u8]) -> String {
+    hex::encode(Sha256::digest(bytes))
+}
+
+/// Whether a name is a blob key, the lowercase hex SHA-256 `key` makes.
+pub fn is_key(name: &str) -> bool {
+    name.len() == 64 && name.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f'))
+}
+
+/// The configured blob store, none when receipts stay in the database.
+pub fn open(cfg: &config::Blobs) -> Result<Option<Arc<dyn BlobStore>>> {
+    match cfg.backend {
+        config::BlobBackend::Database => Ok(None),
+        config::BlobBackend::Filesystem => Ok(Some(Arc::new(fs::FsBlobStore::open(&cfg.path)?))),
+        #[cfg(feature="s3")]
+        config::BlobBackend::S3 => Ok(Some(Arc::new(s3::S3BlobStore::open(cfg)?))),
+        #[cfg(not(feature="s3"))]
+        config::BlobBackend::S3 => Err(anyhow::anyhow!("This node was built without the s3 feature")),
+    }
+}
+
+/// Read a blob back, refusing one whose content no longer matches its key.
+pub async fn load(blobs: &dyn BlobStore, key: &str) -> Result<Vec<u8>> {
+    let bytes = blobs.get(key)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Blob {} is missing", key))?;
+    if self::key(&bytes) != key {
+        return Err(anyhow::anyhow!("Blob {} does not match its hash", key));
+    }
+    Ok(bytes)
+}
+
+/// Delete blobs no receipt refers to anymore, returning how many were removed.
+pub async fn collect_garbage(db: &dyn Storage, blobs: &dyn BlobStore, grace: u64) -> Result<usize> {
+    // list before reading the references, a blob stored in between is then either referenced or too young
+    let stored = blobs.list().await?;
+    let referenced: HashSet<String> = db.blob_keys().await?.into_iter().collect();
+    let cutoff = chrono::Utc::now().timestamp() - grace as i64;
+
+    let mut removed = 0;
+    for (key, written) in stored {
+        // whatever else shares the store is none of our business
+        if !is_key(&key) || referenced.contains(&key) || written > cutoff {
+            continue;
+        }
+        blobs.delete(&key).await?;
+        removed += 1;
+    }
+    Ok(removed)
+}
+
+pub async fn run_gc(db: Arc<dyn Storage>, blobs: Arc<dyn BlobStore>, cfg: config::Blobs) {
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(cfg.gc_interval)).await;
+        match collect_garbage(db.as_ref(), blobs.as_ref(), cfg.gc_grace).await {
+            Ok(0) => log::debug!("No unreferenced blobs to collect."),
+            Ok(removed) => log::info!("Removed {} unreferenced blobs.", removed),
+            Err(e) => log::error!("Blob garbage collection failed: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_are_lowercase_hex_hashes() {
+        assert!(is_key(&key(b"receipt")));
+        assert!(!is_key(&key(b"receipt").to_uppercase()));
+        assert!(!is_key(&key(b"receipt")[..63]));
+        assert!(!is_key("notes.txt"));
+    }
+}
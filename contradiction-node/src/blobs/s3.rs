@@ -0,0 +1,123 @@
+use async_trait::async_trait;
+use futures::TryStreamExt;
+use object_store::{aws::{AmazonS3, AmazonS3Builder}, path::Path, ObjectStore};
+use anyhow::Result;
+use crate::config;
+use super::BlobStore;
+
+/// Blobs as objects in an S3 compatible bucket, MinIO works for local setups.
+pub struct S3BlobStore {
+    store: AmazonS3,
+    /// Blobs are only read, written and listed under it, the rest of the bucket may hold anything
+    prefix: Path,
+}
+
+impl S3BlobStore {
+    pub fn open(cfg: &config::Blobs) -> Result<S3BlobStore> {
+        let bucket = cfg.bucket.as_deref().ok_or_else(|| anyhow::anyhow!("The s3 blob backend needs blobs.bucket to be set"))?;
+        let mut builder = AmazonS3Builder::from_env().with_bucket_name(bucket);
+        if let Some(endpoint) = &cfg.endpoint {
+            builder = builder.with_endpoint(endpoint).with_allow_http(endpoint.starts_with("http://"));
+        }
+        if let Some(region) = &cfg.region {
+            builder = builder.with_region(region);
+        }
+        if let (Some(access_key), Some(secret_key)) = (&cfg.access_key, &cfg.secret_key) {
+            builder = builder.with_access_key_id(access_key).with_secret_access_key(secret_key);
+        }
+        Ok(S3BlobStore { store: builder.build()?, prefix: Path::parse(&cfg.prefix)? })
+    }
+
+    fn path(&self, key: &str) -> Path {
+        self.prefix.child(key)
+    }
+}
+
+#[async_trait]
+impl BlobStore for S3BlobStore {
+    #[cfg(any(feature="risc0", test))]
+    async fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        self.store.put(&self.path(key), bytes.to_vec().into()).await?;
+        Ok(())
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.store.get(&self.path(key)).await {
+            Ok(result) => Ok(Some(result.bytes().await?.to_vec())),
+            Err(object_store::Error::NotFound { .. }) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match self.store.delete(&self.path(key)).await {
+            Err(e) if !matches!(e, object_store::Error::NotFound { .. }) => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn list(&self) -> Result<Vec<(String, i64)>> {
+        let objects: Vec<_> = self.store.list(Some(&self.prefix)).try_collect().await?;
+        Ok(objects
+            .into_iter()
+            .filter_map(|o| {
+                // directly under the prefix, nested objects are someone else's
+                let mut parts = o.location.prefix_match(&self.prefix)?;
+                let key = parts.next()?.as_ref().to_string();
+                (parts.next().is_none() && super::is_key(&key)).then(|| (key, o.last_modified.timestamp()))
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Needs an S3 compatible bucket, e.g. the MinIO one from the README:
+    /// `S3_TEST_ENDPOINT=http://localhost:9000 S3_TEST_BUCKET=receipts AWS_ACCESS_KEY_ID=minio
+    /// AWS_SECRET_ACCESS_KEY=minio123 cargo test --no-default-features --features s3 -- --ignored`
+    #[tokio::test]
+    #[ignore]
+    async fn only_blobs_under_the_prefix_are_listed_and_collected() {
+        let prefix = format!("test-{}", uuid::Uuid::new_v4());
+        let cfg = config::Blobs {
+            backend: config::BlobBackend::S3,
+            bucket: Some(std::env::var("S3_TEST_BUCKET").expect("S3_TEST_BUCKET is not set")),
+            endpoint: Some(std::env::var("S3_TEST_ENDPOINT").expect("S3_TEST_ENDPOINT is not set")),
+            region: Some("us-east-1".to_string()),
+            prefix: prefix.clone(),
+            ..Default::default()
+        };
+        let blobs = S3BlobStore::open(&cfg).unwrap();
+        let key = super::super::key(b"receipt");
+        blobs.put(&key, b"receipt").await.unwrap();
+
+        // the rest of the bucket isn't ours
+        let foreign = [
+            Path::from(format!("{}-other/{}", prefix, key).as_str()),
+            blobs.prefix.child("notes.txt"),
+            blobs.prefix.child("nested").child(key.as_str()),
+        ];
+        for path in &foreign {
+            blobs.store.put(path, b"not a blob".to_vec().into()).await.unwrap();
+        }
+        let listed: Vec<String> = blobs.list().await.unwrap().into_iter().map(|(key, _)| key).collect();
+        assert_eq!(listed, std::slice::from_ref(&key));
+
+        // nothing refers to the blob, the grace period just has to pass
+        let dir = std::env::temp_dir().join(&prefix);
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_cfg = config::DB { path: dir.join("node.db").display().to_string(), ..config::Config::default().db };
+        let db = crate::storage::open(&db_cfg, None).await.unwrap();
+        tokio::time::sleep(std::time::Duration::from_secs(2)).await;
+        assert_eq!(super::super::collect_garbage(db.as_ref(), &blobs, 1).await.unwrap(), 1);
+        assert_eq!(blobs.get(&key).await.unwrap(), None);
+
+        for path in &foreign {
+            assert!(blobs.store.head(path).await.is_ok(), "{} was deleted", path);
+            blobs.store.delete(path).await.unwrap();
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,299 @@
+use std::path::{Path, PathBuf};
+use clap::{Args, Parser, Subcommand};
+use log::LevelFilter;
+use anyhow::Result;
+use crate::{attestation, blobs, config, storage::{self, Storage}};
+
+#[cfg(feature="risc0")]
+use crate::{ledger, risc0::{bundle, models as circuits}};
+
+#[derive(Parser)]
+#[command(version, about = "A contradiction node", args_conflicts_with_subcommands = true)]
+pub struct Cli {
+    /// Config file, create one with `init-config`. Every setting can be overridden by a `CONTRADICTION_NODE__*`
+    /// environment variable, e.g. CONTRADICTION_NODE__DB_POOL_MAX.
+    #[arg(short, long, global = true, env = "CONTRADICTION_CONFIG", default_value = "config.toml")]
+    pub config: PathBuf,
+    #[command(subcommand)]
+    pub command: Option<Command>,
+    /// Without a command the node is served
+    #[command(flatten)]
+    pub serve: ServeArgs,
+}
+
+/// Settings that can be overridden for a single run of the node, taking precedence over the environment.
+#[derive(Args, Clone, Default)]
+pub struct ServeArgs {
+    #[arg(long)]
+    pub address: Option<String>,
+    #[arg(long)]
+    pub port: Option<u16>,
+    #[arg(long)]
+    pub log_level: Option<LevelFilter>,
+}
+
+impl ServeArgs {
+    pub fn apply(&self, cfg: &mut config::Config) {
+        if let Some(address) = &self.address {
+            cfg.api.address = address.clone();
+        }
+        if let Some(port) = self.port {
+            cfg.api.port = port;
+        }
+        if let Some(level) = self.log_level {
+            cfg.log.level = level;
+        }
+    }
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Run the node
+    Serve(ServeArgs),
+    /// Write a config file with the default settings
+    InitConfig {
+        /// Replace an existing config file
+        #[arg(long)]
+        force: bool,
+    },
+    /// Validate the config file and environment overrides, printing the settings in effect
+    CheckConfig,
+    /// Create the database or bring its schema up to date
+    Migrate,
+    /// Manage the known peers
+    #[command(subcommand)]
+    Peers(PeersCommand),
+    /// Inspect, verify, export and import stored receipts
+    #[command(subcommand)]
+    Receipts(ReceiptsCommand),
+    /// Show or replace the node key attestations are signed with
+    #[command(subcommand)]
+    Keys(KeysCommand),
+}
+
+#[derive(Subcommand)]
+pub enum PeersCommand {
+    List,
+    Add { address: String, port: u16 },
+    Remove { address: String, port: u16 },
+    /// Forget the key pinned for a peer, the next one it attests with is pinned instead
+    ForgetKey { node: String },
+}
+
+#[derive(Subcommand)]
+pub enum ReceiptsCommand {
+    /// Print the metadata of a receipt
+    Get {
+        uuid: String,
+        /// Also write the receipt itself to this file, as JSON
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+    /// Verify a receipt against its circuit and its ledger entry
+    #[cfg(feature="risc0")]
+    Verify { uuid: String },
+    /// Write receipts to a bundle, by UUID or else every one matching the filters
+    #[cfg(feature="risc0")]
+    Export {
+        #[arg(long, value_delimiter = ',')]
+        uuid: Vec<String>,
+        #[arg(long)]
+        circuit: Option<String>,
+        /// Unix timestamp, receipts created at or after it
+        #[arg(long)]
+        from: Option<i64>,
+        /// Unix timestamp, receipts created before it
+        #[arg(long)]
+        to: Option<i64>,
+        #[arg(short, long, default_value = "receipts.tar.zst")]
+        output: PathBuf,
+    },
+    /// Verify every receipt of a bundle and store them
+    #[cfg(feature="risc0")]
+    Import { bundle: PathBuf },
+}
+
+#[derive(Subcommand)]
+pub enum KeysCommand {
+    Show,
+    /// Generate a new key, the old one is kept next to it with a `.old` suffix
+    Rotate,
+}
+
+/// Load and validate the config.
+fn load(path: &Path) -> Result<config::Config> {
+    let cfg = config::Config::load(path)?;
+    cfg.validate()?;
+    Ok(cfg)
+}
+
+async fn open(cfg: &config::Config) -> Result<std::sync::Arc<dyn Storage>> {
+    storage::open(&cfg.db, blobs::open(&cfg.blobs)?).await
+}
+
+/// Run every command but `serve`.
+pub async fn run(command: Command, path: &Path) -> Result<()> {
+    match command {
+        Command::Serve(_) => Err(anyhow::anyhow!("The node is not served through cli::run")),
+        Command::InitConfig { force } => {
+            if path.exists() && !force {
+                return Err(anyhow::anyhow!("{} already exists, pass --force to replace it", path.display()));
+            }
+            // the default binds every interface, which needs an advertised address, so start out on loopback
+            let mut cfg = config::Config::default();
+            cfg.api.address = "127.0.0.1".to_string();
+            cfg.write(path)?;
+            println!("Wrote default config to {}", path.display());
+            println!("It serves on 127.0.0.1 only, for peers bind api.address and set api.advertise to the host:port they reach it on.");
+            Ok(())
+        },
+        Command::CheckConfig => {
+            let cfg = load(path)?;
+            println!("{}", toml::to_string_pretty(&cfg.redacted())?);
+            eprintln!("{} is valid.", path.display());
+            Ok(())
+        },
+        Command::Migrate => {
+            let db = open(&load(path)?).await?;
+            println!("Database schema is at version {}.", db.schema_version().await?);
+            Ok(())
+        },
+        Command::Peers(command) => peers(command, &load(path)?).await,
+        Command::Receipts(command) => receipts(command, &load(path)?).await,
+        Command::Keys(command) => keys(command, &load(path)?),
+    }
+}
+
+async fn peers(command: PeersCommand, cfg: &config::Config) -> Result<()> {
+    let db = open(cfg).await?;
+    match command {
+        PeersCommand::List => {
+            for node in db.nodes().await? {
+                println!("{}:{}", node.address, node.port);
+            }
+        },
+        PeersCommand::Add { address, port } => {
+            match db.add_node(&address, port).await? {
+                true => println!("Added {}:{}.", address, port),
+                false => println!("{}:{} is already known.", address, port),
+            }
+        },
+        PeersCommand::Remove { address, port } => {
+            db.remove_node(&address, port).await?;
+            println!("Removed {}:{}.", address, port);
+        },
+        PeersCommand::ForgetKey { node } => {
+            match db.unpin_peer_key(&node).await? {
+                true => println!("Forgot the key pinned for {}.", node),
+                false => println!("No key is pinned for {}.", node),
+            }
+        },
+    }
+    Ok(())
+}
+
+async fn receipts(command: ReceiptsCommand, cfg: &config::Config) -> Result<()> {
+    let db = open(cfg).await?;
+    let db = db.as_ref();
+    match command {
+        ReceiptsCommand::Get { uuid, output } => {
+            let summary = crate::receipts::summary(db, &uuid)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Receipt {} not found", uuid))?;
+            println!("{}", serde_json::to_string_pretty(&summary)?);
+            if let Some(output) = output {
+                let receipt: Option<crate::db::StoredReceipt> = crate::db::retrieve_receipt(db, &uuid).await?;
+                std::fs::write(&output, serde_json::to_vec(&receipt)?)?;
+                eprintln!("Wrote receipt to {}", output.display());
+            }
+        },
+        #[cfg(feature="risc0")]
+        ReceiptsCommand::Verify { uuid } => {
+            let receipt: risc0_zkvm::Receipt = crate::db::retrieve_receipt(db, &uuid)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Receipt {} not found", uuid))?;
+            let (circuit, _) = circuits::identify_circuit(&receipt)?;
+            let entry = db.ledger_entry(&uuid)
+                .await?
+                .ok_or_else(|| anyhow::anyhow!("Receipt {} has no ledger entry", uuid))?;
+            if ledger::receipt_hash(&serde_json::to_vec(&receipt)?) != entry.receipt_hash {
+                return Err(anyhow::anyhow!("Receipt {} does not match ledger entry {}", uuid, entry.seq));
+            }
+            println!("Receipt {} verifies against {} and matches ledger entry {}.", uuid, circuit, entry.seq);
+        },
+        #[cfg(feature="risc0")]
+        ReceiptsCommand::Export { uuid, circuit, from, to, output } => {
+            let selection = bundle::Selection { uuids: uuid, circuit, from, to };
+            let payload = bundle::export(db, &selection, &cfg.node_id()).await?;
+            std::fs::write(&output, payload)?;
+            println!("Wrote bundle {}", output.display());
+        },
+        #[cfg(feature="risc0")]
+        ReceiptsCommand::Import { bundle: path } => {
+            let report = bundle::import(db, &std::fs::read(&path)?, &cfg.db, cfg.limits.max_bundle).await?;
+            println!("Imported {} receipts, skipped {} already stored or pruned.", report.imported, report.skipped);
+        },
+    }
+    Ok(())
+}
+
+fn keys(command: KeysCommand, cfg: &config::Config) -> Result<()> {
+    let path = &cfg.keys.path;
+    match command {
+        KeysCommand::Show => {
+            let key = attestation::load_or_generate(path)?;
+            println!("{}", hex::encode(key.verifying_key().to_bytes()));
+        },
+        KeysCommand::Rotate => {
+            if Path::new(path).exists() {
+                std::fs::rename(path, format!("{}.old", path))?;
+            }
+            let key = attestation::load_or_generate(path)?;
+            println!("{}", hex::encode(key.verifying_key().to_bytes()));
+            eprintln!("Restart the node to sign with the new key, earlier attestations still verify against the old one.");
+            eprintln!("Peers that pinned the old key ignore new attestations until they run `peers forget-key` or update keys.peers.");
+        },
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing;
+
+    #[tokio::test]
+    async fn init_config_writes_a_config_that_validates() {
+        let dir = testing::temp_dir("cli");
+        let path = dir.join("config.toml");
+        run(Command::InitConfig { force: false }, &path).await.unwrap();
+        let cfg = load(&path).unwrap();
+        assert_eq!(cfg.api.address, "127.0.0.1");
+        run(Command::CheckConfig, &path).await.unwrap();
+
+        // an existing file is only replaced with --force
+        std::fs::write(&path, "# mine").unwrap();
+        assert!(run(Command::InitConfig { force: false }, &path).await.is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "# mine");
+        run(Command::InitConfig { force: true }, &path).await.unwrap();
+        assert!(load(&path).is_ok());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn keys_rotate_keeps_the_old_key_aside() {
+        let dir = testing::temp_dir("cli");
+        let mut cfg = config::Config::default();
+        cfg.keys.path = dir.join("node.key").display().to_string();
+
+        keys(KeysCommand::Show, &cfg).unwrap();
+        let old = std::fs::read(&cfg.keys.path).unwrap();
+        keys(KeysCommand::Show, &cfg).unwrap();
+        assert_eq!(std::fs::read(&cfg.keys.path).unwrap(), old);
+
+        keys(KeysCommand::Rotate, &cfg).unwrap();
+        assert_eq!(std::fs::read(format!("{}.old", cfg.keys.path)).unwrap(), old);
+        assert_ne!(std::fs::read(&cfg.keys.path).unwrap(), old);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,723 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+use tokio::time::Instant;
+use anyhow::Result;
+use async_trait::async_trait;
+use crate::{config, storage::Storage};
+
+/// Entries are capped per AppendEntries request so a lagging follower catches up in steps.
+const MAX_BATCH: usize = 100;
+
+/// A receipt accepted by some node, to be agreed on by the cluster.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct Proposal {
+    pub uuid: String,
+    pub image_id: String,
+    pub journal_hash: String,
+    pub submitter: String,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, sqlx::FromRow, utoipa::ToSchema)]
+pub struct Entry {
+    #[sqlx(rename = "log_index")]
+    pub index: i64,
+    pub term: i64,
+    pub uuid: String,
+    pub image_id: String,
+    pub journal_hash: String,
+    pub submitter: String,
+}
+
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct VoteRequest {
+    pub term: i64,
+    pub candidate: String,
+    pub last_log_index: i64,
+    pub last_log_term: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct VoteResponse {
+    pub term: i64,
+    pub granted: bool,
+}
+
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct AppendRequest {
+    pub term: i64,
+    pub leader: String,
+    pub prev_log_index: i64,
+    pub prev_log_term: i64,
+    pub entries: Vec<Entry>,
+    pub leader_commit: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct AppendResponse {
+    pub term: i64,
+    pub success: bool,
+    /// Last index of the follower's log, lets the leader skip back quickly on a mismatch
+    pub last_index: i64,
+}
+
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct ProposeResponse {
+    pub index: i64,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize, utoipa::ToSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    Follower,
+    Candidate,
+    Leader,
+}
+
+#[derive(Debug, Deserialize, Serialize, utoipa::ToSchema)]
+pub struct Status {
+    pub node: String,
+    pub role: Role,
+    pub term: i64,
+    pub leader: Option<String>,
+    pub commit_index: i64,
+    pub last_index: i64,
+}
+
+struct State {
+    term: i64,
+    voted_for: Option<String>,
+    /// log[i] holds the entry with index i + 1
+    log: Vec<Entry>,
+    commit_index: i64,
+    role: Role,
+    leader: Option<String>,
+    deadline: Instant,
+    next_index: HashMap<String, i64>,
+    match_index: HashMap<String, i64>,
+}
+
+impl State {
+    fn last_index(&self) -> i64 {
+        self.log.len() as i64
+    }
+
+    fn last_term(&self) -> i64 {
+        self.log.last().map_or(0, |e| e.term)
+    }
+
+    fn term_at(&self, index: i64) -> Option<i64> {
+        match index {
+            0 => Some(0),
+            i if i < 0 => None,
+            i => self.log.get(i as usize - 1).map(|e| e.term),
+        }
+    }
+}
+
+/// How RPCs reach the other members.
+#[async_trait]
+pub trait Transport: Send + Sync {
+    async fn request_vote(&self, peer: &str, req: &VoteRequest) -> Result<VoteResponse>;
+    async fn append_entries(&self, peer: &str, req: &AppendRequest) -> Result<AppendResponse>;
+    async fn propose(&self, peer: &str, proposal: &Proposal) -> Result<ProposeResponse>;
+}
+
+/// RPCs through the API of the members.
+pub struct Http {
+    timeout: Duration,
+}
+
+impl Http {
+    pub fn new(cfg: &config::Consensus) -> Http {
+        Http { timeout: Duration::from_millis(cfg.rpc_timeout) }
+    }
+
+    async fn call<Req: Serialize + Sync, Resp: for<'a> Deserialize<'a>>(&self, peer: &str, path: &str, body: &Req) -> Result<Resp> {
+        // elections time out on their own, retrying would only outlive them
+        let client = crate::peer(peer)
+            .retries(0)
+            .timeout(self.timeout);
+        client.post(path, body).await.map_err(|e| anyhow::anyhow!("{}: {}", peer, e))
+    }
+}
+
+#[async_trait]
+impl Transport for Http {
+    async fn request_vote(&self, peer: &str, req: &VoteRequest) -> Result<VoteResponse> {
+        self.call(peer, "/v1/raft/request-vote", req).await
+    }
+
+    async fn append_entries(&self, peer: &str, req: &AppendRequest) -> Result<AppendResponse> {
+        self.call(peer, "/v1/raft/append-entries", req).await
+    }
+
+    async fn propose(&self, peer: &str, proposal: &Proposal) -> Result<ProposeResponse> {
+        self.call(peer, "/v1/raft/propose", proposal).await
+    }
+}
+
+pub struct Consensus {
+    id: String,
+    members: Vec<String>,
+    cfg: config::Consensus,
+    db: Arc<dyn Storage>,
+    transport: Arc<dyn Transport>,
+    state: Mutex<State>,
+}
+
+impl Consensus {
+    /// Restore term, vote and log from the database.
+    pub async fn load(db: Arc<dyn Storage>, cfg: config::Consensus, id: String, transport: Arc<dyn Transport>) -> Result<Arc<Consensus>> {
+        let (term, voted_for, commit_index) = db.consensus_state().await?.unwrap_or((0, None, 0));
+        let log = db.consensus_log().await?;
+        log::info!("Loaded consensus log with {} entries at term {}.", log.len(), term);
+
+        let members = cfg.members.iter().filter(|m| **m != id).cloned().collect();
+        let consensus = Consensus {
+            id,
+            members,
+            state: Mutex::new(State {
+                term,
+                voted_for,
+                log,
+                commit_index,
+                role: Role::Follower,
+                leader: None,
+                deadline: Instant::now() + election_timeout(&cfg),
+                next_index: HashMap::new(),
+                match_index: HashMap::new(),
+            }),
+            cfg,
+            db,
+            transport,
+        };
+        Ok(Arc::new(consensus))
+    }
+
+    fn majority(&self) -> usize {
+        let cluster = self.members.len() + 1;
+        cluster / 2 + 1
+    }
+
+    async fn persist_state(&self, state: &State) -> Result<()> {
+        self.db.save_consensus_state(state.term, state.voted_for.as_deref(), state.commit_index).await
+    }
+
+    async fn persist_entries(&self, entries: &[Entry]) -> Result<()> {
+        self.db.append_consensus_log(entries).await
+    }
+
+    async fn truncate(&self, state: &mut State, from: i64) -> Result<()> {
+        self.db.truncate_consensus_log(from).await?;
+        state.log.truncate(from as usize - 1);
+        Ok(())
+    }
+
+    async fn step_down(&self, state: &mut State, term: i64) -> Result<()> {
+        if term > state.term {
+            state.term = term;
+            state.voted_for = None;
+            state.leader = None;
+        }
+        if state.role != Role::Follower {
+            log::info!("Stepping down to follower at term {}.", state.term);
+        }
+        state.role = Role::Follower;
+        self.persist_state(state).await
+    }
+
+    pub async fn handle_vote(&self, req: VoteRequest) -> Result<VoteResponse> {
+        let mut state = self.state.lock().await;
+        if req.term > state.term {
+            self.step_down(&mut state, req.term).await?;
+        }
+
+        let up_to_date = req.last_log_term > state.last_term()
+            || (req.last_log_term == state.last_term() && req.last_log_index >= state.last_index());
+        let free = state.voted_for.as_ref().is_none_or(|v| *v == req.candidate);
+        let granted = req.term == state.term && free && up_to_date;
+
+        if granted {
+            state.voted_for = Some(req.candidate.clone());
+            state.deadline = Instant::now() + election_timeout(&self.cfg);
+            self.persist_state(&state).await?;
+            log::debug!("Voted for {} at term {}.", req.candidate, req.term);
+        }
+        Ok(VoteResponse { term: state.term, granted })
+    }
+
+    pub async fn handle_append(&self, req: AppendRequest) -> Result<AppendResponse> {
+        let mut state = self.state.lock().await;
+        if req.term < state.term {
+            return Ok(AppendResponse { term: state.term, success: false, last_index: state.last_index() });
+        }
+        if req.term > state.term || state.role != Role::Follower {
+            self.step_down(&mut state, req.term).await?;
+        }
+        state.leader = Some(req.leader.clone());
+        state.deadline = Instant::now() + election_timeout(&self.cfg);
+
+        // Our log has to contain the entry the new ones follow
+        if state.term_at(req.prev_log_index) != Some(req.prev_log_term) {
+            return Ok(AppendResponse { term: state.term, success: false, last_index: state.last_index().min(req.prev_log_index - 1) });
+        }
+
+        let last_new = req.prev_log_index + req.entries.len() as i64;
+        let mut new_entries = Vec::new();
+        for entry in req.entries {
+            match state.term_at(entry.index) {
+                Some(term) if term == entry.term => continue,
+                Some(_) => {
+                    log::warn!("Dropping conflicting consensus entries from index {}.", entry.index);
+                    self.truncate(&mut state, entry.index).await?;
+                },
+                None => {},
+            }
+            new_entries.push(entry);
+        }
+        self.persist_entries(&new_entries).await?;
+        state.log.extend(new_entries);
+
+        // a stale or reordered request may vouch for fewer entries than are committed already
+        let commit_index = state.commit_index.max(req.leader_commit.min(last_new));
+        if commit_index > state.commit_index {
+            state.commit_index = commit_index;
+            self.persist_state(&state).await?;
+        }
+        Ok(AppendResponse { term: state.term, success: true, last_index: state.last_index() })
+    }
+
+    /// Append a proposal to the log, forwarding it to the leader if we aren't one.
+    pub async fn propose(&self, proposal: Proposal) -> Result<i64> {
+        let leader = {
+            let mut state = self.state.lock().await;
+            if state.role == Role::Leader {
+                if let Some(existing) = state.log.iter().find(|e| e.uuid == proposal.uuid) {
+                    return Ok(existing.index);
+                }
+                let entry = Entry {
+                    index: state.last_index() + 1,
+                    term: state.term,
+                    uuid: proposal.uuid,
+                    image_id: proposal.image_id,
+                    journal_hash: proposal.journal_hash,
+                    submitter: proposal.submitter,
+                };
+                self.persist_entries(std::slice::from_ref(&entry)).await?;
+                let index = entry.index;
+                state.log.push(entry);
+                return Ok(index);
+            }
+            state.leader.clone()
+        };
+
+        match leader {
+            Some(leader) => {
+                let resp = self.transport.propose(&leader, &proposal).await?;
+                Ok(resp.index)
+            },
+            None => Err(anyhow::anyhow!("No consensus leader elected")),
+        }
+    }
+
+    /// Keep proposing until a leader accepts, elections may be in progress.
+    #[cfg(feature="risc0")]
+    pub async fn submit(self: Arc<Self>, proposal: Proposal) {
+        for attempt in 1..=10 {
+            match self.propose(proposal.clone()).await {
+                Ok(index) => {
+                    log::debug!("Receipt {} proposed at consensus index {}.", proposal.uuid, index);
+                    return;
+                },
+                Err(e) => {
+                    log::debug!("Proposing {} failed, attempt {}: {}", proposal.uuid, attempt, e);
+                    tokio::time::sleep(Duration::from_millis(self.cfg.election_timeout_max)).await;
+                }
+            }
+        }
+        log::warn!("Giving up on proposing receipt {} to the consensus log.", proposal.uuid);
+    }
+
+    pub async fn status(&self) -> Status {
+        let state = self.state.lock().await;
+        Status {
+            node: self.id.clone(),
+            role: state.role,
+            term: state.term,
+            leader: state.leader.clone(),
+            commit_index: state.commit_index,
+            last_index: state.last_index(),
+        }
+    }
+
+    /// Committed entries starting at index `from`, this history is the same on every member.
+    pub async fn committed(&self, from: i64, limit: usize) -> Vec<Entry> {
+        let state = self.state.lock().await;
+        let start = from.max(1) as usize - 1;
+        state.log
+            .iter()
+            .take(state.commit_index as usize)
+            .skip(start)
+            .take(limit)
+            .cloned()
+            .collect()
+    }
+
+    async fn start_election(&self) -> Result<()> {
+        let req = {
+            let mut state = self.state.lock().await;
+            state.term += 1;
+            state.role = Role::Candidate;
+            state.voted_for = Some(self.id.clone());
+            state.leader = None;
+            state.deadline = Instant::now() + election_timeout(&self.cfg);
+            self.persist_state(&state).await?;
+            log::info!("Starting election for term {}.", state.term);
+            VoteRequest {
+                term: state.term,
+                candidate: self.id.clone(),
+                last_log_index: state.last_index(),
+                last_log_term: state.last_term(),
+            }
+        };
+
+        let responses = futures::future::join_all(
+            self.members.iter().map(|peer| self.transport.request_vote(peer, &req))
+        ).await;
+
+        let mut state = self.state.lock().await;
+        if state.role != Role::Candidate || state.term != req.term {
+            return Ok(());
+        }
+        let mut votes = 1;
+        for resp in responses.into_iter().flatten() {
+            if resp.term > state.term {
+                return self.step_down(&mut state, resp.term).await;
+            }
+            if resp.granted {
+                votes += 1;
+            }
+        }
+
+        if votes >= self.majority() {
+            log::info!("Elected leader for term {} with {} votes.", state.term, votes);
+            state.role = Role::Leader;
+            state.leader = Some(self.id.clone());
+            let next = state.last_index() + 1;
+            state.next_index = self.members.iter().map(|m| (m.clone(), next)).collect();
+            state.match_index = self.members.iter().map(|m| (m.clone(), 0)).collect();
+        }
+        Ok(())
+    }
+
+    async fn replicate(&self) -> Result<()> {
+        let requests: Vec<(String, AppendRequest)> = {
+            let state = self.state.lock().await;
+            if state.role != Role::Leader {
+                return Ok(());
+            }
+            self.members
+                .iter()
+                .map(|peer| {
+                    let next = state.next_index.get(peer).copied().unwrap_or(1);
+                    let prev = next - 1;
+                    let req = AppendRequest {
+                        term: state.term,
+                        leader: self.id.clone(),
+                        prev_log_index: prev,
+                        prev_log_term: state.term_at(prev).unwrap_or(0),
+                        entries: state.log.iter().skip(prev as usize).take(MAX_BATCH).cloned().collect(),
+                        leader_commit: state.commit_index,
+                    };
+                    (peer.clone(), req)
+                })
+                .collect()
+        };
+        let term = requests.first().map(|(_, r)| r.term);
+
+        let responses = futures::future::join_all(requests.iter().map(|(peer, req)| async move {
+            (peer, req, self.transport.append_entries(peer, req).await)
+        })).await;
+
+        let mut state = self.state.lock().await;
+        if state.role != Role::Leader || term.is_some_and(|t| t != state.term) {
+            return Ok(());
+        }
+        for (peer, req, resp) in responses {
+            let resp = match resp {
+                Ok(resp) => resp,
+                Err(e) => {
+                    log::debug!("AppendEntries to {} failed: {}", peer, e);
+                    continue;
+                }
+            };
+            if resp.term > state.term {
+                return self.step_down(&mut state, resp.term).await;
+            }
+            if resp.success {
+                let matched = req.prev_log_index + req.entries.len() as i64;
+                state.match_index.insert(peer.clone(), matched);
+                state.next_index.insert(peer.clone(), matched + 1);
+            } else {
+                let next = (req.prev_log_index).min(resp.last_index + 1).max(1);
+                state.next_index.insert(peer.clone(), next);
+            }
+        }
+
+        // Entries from the current term replicated on a majority are committed, and everything before them
+        let mut commit = state.commit_index;
+        for index in (state.commit_index + 1)..=state.last_index() {
+            let replicated = 1 + state.match_index.values().filter(|m| **m >= index).count();
+            if replicated >= self.majority() && state.term_at(index) == Some(state.term) {
+                commit = index;
+            }
+        }
+        if commit > state.commit_index {
+            state.commit_index = commit;
+            self.persist_state(&state).await?;
+            log::debug!("Consensus commit index advanced to {}.", commit);
+        }
+        Ok(())
+    }
+
+    /// Drive elections and replication every heartbeat.
+    pub async fn run(self: Arc<Self>) {
+        loop {
+            tokio::time::sleep(Duration::from_millis(self.cfg.heartbeat)).await;
+            let (role, deadline) = {
+                let state = self.state.lock().await;
+                (state.role, state.deadline)
+            };
+            let result = match role {
+                Role::Leader => self.replicate().await,
+                _ if Instant::now() >= deadline => self.start_election().await,
+                _ => Ok(()),
+            };
+            if let Err(e) = result {
+                log::error!("Consensus round failed: {}", e);
+            }
+        }
+    }
+}
+
+fn election_timeout(cfg: &config::Consensus) -> Duration {
+    let max = cfg.election_timeout_max.max(cfg.election_timeout_min + 1);
+    Duration::from_millis(rand::thread_rng().gen_range(cfg.election_timeout_min..max))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashSet;
+
+    /// Members connected in memory, nodes in different groups can't reach each other.
+    #[derive(Default)]
+    struct Network {
+        nodes: std::sync::Mutex<HashMap<String, Arc<Consensus>>>,
+        groups: std::sync::Mutex<HashMap<String, usize>>,
+    }
+
+    impl Network {
+        fn reach(&self, from: &str, to: &str) -> Result<Arc<Consensus>> {
+            let groups = self.groups.lock().unwrap();
+            if groups.get(from) != groups.get(to) {
+                return Err(anyhow::anyhow!("{} can't reach {}", from, to));
+            }
+            self.nodes.lock().unwrap().get(to).cloned().ok_or_else(|| anyhow::anyhow!("{} is unknown", to))
+        }
+
+        fn partition(&self, minority: &[&str]) {
+            let mut groups = self.groups.lock().unwrap();
+            for (node, group) in groups.iter_mut() {
+                *group = minority.contains(&node.as_str()) as usize;
+            }
+        }
+
+        fn heal(&self) {
+            self.partition(&[]);
+        }
+    }
+
+    /// The end of the network a single member sends from.
+    struct Link {
+        from: String,
+        network: Arc<Network>,
+    }
+
+    #[async_trait]
+    impl Transport for Link {
+        async fn request_vote(&self, peer: &str, req: &VoteRequest) -> Result<VoteResponse> {
+            self.network.reach(&self.from, peer)?.handle_vote(VoteRequest { candidate: req.candidate.clone(), ..*req }).await
+        }
+
+        async fn append_entries(&self, peer: &str, req: &AppendRequest) -> Result<AppendResponse> {
+            let req = AppendRequest { leader: req.leader.clone(), entries: req.entries.clone(), ..*req };
+            self.network.reach(&self.from, peer)?.handle_append(req).await
+        }
+
+        async fn propose(&self, peer: &str, proposal: &Proposal) -> Result<ProposeResponse> {
+            let index = self.network.reach(&self.from, peer)?.propose(proposal.clone()).await?;
+            Ok(ProposeResponse { index })
+        }
+    }
+
+    struct Cluster {
+        network: Arc<Network>,
+        nodes: Vec<Arc<Consensus>>,
+        tasks: Vec<tokio::task::JoinHandle<()>>,
+        dir: std::path::PathBuf,
+    }
+
+    impl Cluster {
+        async fn start(size: usize) -> Cluster {
+            let dir = std::env::temp_dir().join(format!("contradiction-consensus-{}", uuid::Uuid::new_v4()));
+            std::fs::create_dir_all(&dir).unwrap();
+            let members: Vec<String> = (1..=size).map(|i| format!("10.0.0.{}:8080", i)).collect();
+            let cfg = config::Consensus {
+                enabled: true,
+                members: members.clone(),
+                heartbeat: 20,
+                election_timeout_min: 150,
+                election_timeout_max: 300,
+                rpc_timeout: 50,
+            };
+
+            let network = Arc::new(Network::default());
+            let mut nodes = Vec::new();
+            for (i, id) in members.iter().enumerate() {
+                let db_cfg = config::DB { path: dir.join(format!("{}.db", i)).display().to_string(), ..config::Config::default().db };
+                let db = crate::storage::open(&db_cfg, None).await.unwrap();
+                let link = Arc::new(Link { from: id.clone(), network: network.clone() });
+                nodes.push(Consensus::load(db, cfg.clone(), id.clone(), link).await.unwrap());
+            }
+            for node in &nodes {
+                network.nodes.lock().unwrap().insert(node.id.clone(), node.clone());
+                network.groups.lock().unwrap().insert(node.id.clone(), 0);
+            }
+            let tasks = nodes.iter().map(|node| tokio::spawn(node.clone().run())).collect();
+            Cluster { network, nodes, tasks, dir }
+        }
+
+        fn node(&self, id: &str) -> &Arc<Consensus> {
+            self.nodes.iter().find(|n| n.id == id).unwrap()
+        }
+
+        /// Poll every member until `done` holds for their statuses, failing after a few seconds.
+        async fn until(&self, what: &str, done: impl Fn(&[Status]) -> bool) -> Vec<Status> {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            loop {
+                let statuses = futures::future::join_all(self.nodes.iter().map(|n| n.status())).await;
+                if done(&statuses) {
+                    return statuses;
+                }
+                assert!(Instant::now() < deadline, "timed out waiting for {}: {:?}", what, statuses);
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        }
+
+        /// The leader a majority of `members` follows.
+        async fn leader(&self, members: &[String]) -> String {
+            let statuses = self.until("a leader", |statuses| {
+                let group: Vec<&Status> = statuses.iter().filter(|s| members.contains(&s.node)).collect();
+                let leaders: Vec<&&Status> = group.iter().filter(|s| s.role == Role::Leader).collect();
+                let term = leaders.iter().map(|s| s.term).max();
+                let followers = group.iter().filter(|s| Some(s.term) == term && s.leader.is_some()).count();
+                leaders.iter().filter(|s| Some(s.term) == term).count() == 1 && followers > members.len() / 2
+            }).await;
+            let term = statuses.iter().filter(|s| members.contains(&s.node) && s.role == Role::Leader).map(|s| s.term).max();
+            statuses.into_iter().find(|s| s.role == Role::Leader && Some(s.term) == term).unwrap().node
+        }
+
+        fn ids(&self) -> Vec<String> {
+            self.nodes.iter().map(|n| n.id.clone()).collect()
+        }
+    }
+
+    impl Drop for Cluster {
+        fn drop(&mut self) {
+            for task in &self.tasks {
+                task.abort();
+            }
+            let _ = std::fs::remove_dir_all(&self.dir);
+        }
+    }
+
+    fn proposal(uuid: &str) -> Proposal {
+        Proposal {
+            uuid: uuid.to_string(),
+            image_id: "image".to_string(),
+            journal_hash: "journal".to_string(),
+            submitter: "10.0.0.9".to_string(),
+        }
+    }
+
+    #[tokio::test]
+    async fn elects_a_single_leader() {
+        let cluster = Cluster::start(3).await;
+        let leader = cluster.leader(&cluster.ids()).await;
+
+        let statuses = cluster.until("every member to follow the leader", |statuses| {
+            statuses.iter().all(|s| s.leader.as_deref() == Some(leader.as_str()))
+        }).await;
+        assert_eq!(statuses.iter().filter(|s| s.role == Role::Leader).count(), 1);
+    }
+
+    #[tokio::test]
+    async fn replicates_proposals_to_every_member() {
+        let cluster = Cluster::start(3).await;
+        let leader = cluster.leader(&cluster.ids()).await;
+        let follower = cluster.ids().into_iter().find(|id| *id != leader).unwrap();
+
+        // followers forward proposals to the leader
+        assert_eq!(cluster.node(&follower).propose(proposal("a")).await.unwrap(), 1);
+        assert_eq!(cluster.node(&leader).propose(proposal("b")).await.unwrap(), 2);
+        assert_eq!(cluster.node(&leader).propose(proposal("a")).await.unwrap(), 1);
+
+        cluster.until("both entries to commit everywhere", |statuses| statuses.iter().all(|s| s.commit_index == 2)).await;
+        for node in &cluster.nodes {
+            let uuids: Vec<String> = node.committed(1, 10).await.into_iter().map(|e| e.uuid).collect();
+            assert_eq!(uuids, ["a", "b"]);
+        }
+    }
+
+    #[tokio::test]
+    async fn a_minority_partition_does_not_commit_and_heals() {
+        let cluster = Cluster::start(5).await;
+        let leader = cluster.leader(&cluster.ids()).await;
+        cluster.node(&leader).propose(proposal("before")).await.unwrap();
+        cluster.until("the first entry to commit", |statuses| statuses.iter().all(|s| s.commit_index == 1)).await;
+
+        // cut the leader off with one follower
+        let follower = cluster.ids().into_iter().find(|id| *id != leader).unwrap();
+        cluster.network.partition(&[&leader, &follower]);
+        let majority: Vec<String> = cluster.ids().into_iter().filter(|id| *id != leader && *id != follower).collect();
+
+        cluster.node(&leader).propose(proposal("lost")).await.unwrap();
+        let new_leader = cluster.leader(&majority).await;
+        cluster.node(&new_leader).propose(proposal("kept")).await.unwrap();
+        cluster.until("the majority to commit", |statuses| {
+            statuses.iter().filter(|s| majority.contains(&s.node)).all(|s| s.commit_index == 2)
+        }).await;
+
+        let minority: HashSet<&String> = [&leader, &follower].into();
+        for status in futures::future::join_all(cluster.nodes.iter().map(|n| n.status())).await {
+            if minority.contains(&status.node) {
+                assert_eq!(status.commit_index, 1, "{} committed in the minority", status.node);
+            }
+        }
+
+        // the old leader drops what it couldn't commit and catches up
+        cluster.network.heal();
+        cluster.until("every member to commit the majority's log", |statuses| {
+            statuses.iter().all(|s| s.commit_index == 2 && s.last_index == 2)
+        }).await;
+        for node in &cluster.nodes {
+            let uuids: Vec<String> = node.committed(1, 10).await.into_iter().map(|e| e.uuid).collect();
+            assert_eq!(uuids, ["before", "kept"], "log of {}", node.id);
+        }
+    }
+}
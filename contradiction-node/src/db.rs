@@ -1,77 +1,307 @@
-use sqlx::{Sqlite, migrate::MigrateDatabase, sqlite::SqlitePool, Row};
 use anyhow::Result;
+use serde::de::DeserializeOwned;
+use crate::{blobs, ledger, storage::{Payload, Storage}};
+#[cfg(any(feature="risc0", test))]
+use std::sync::Arc;
+#[cfg(any(feature="risc0", test))]
 use serde::Serialize;
+#[cfg(any(feature="risc0", test))]
 use uuid::Uuid;
-use crate::config;
-
-pub async fn setup_database(cfg: &config::DB) -> SqlitePool {
-    if !Sqlite::database_exists(&cfg.path).await.unwrap_or(false) {
-        println!("Creating database {}", &cfg.path);
-        Sqlite::create_database(&cfg.path).await.expect("Failed to create database.");
-        let pool = SqlitePool::connect(&cfg.path).await.expect("Failed to create database connection.");
-
-        // create nodes table
-        sqlx::query("CREATE TABLE IF NOT EXISTS nodes (
-            address TEXT NOT NULL,
-            port INTEGER NOT NULL,
-            last_ping_at INTEGER,
-            PRIMARY KEY (address, port)
-        );").execute(&pool).await.expect("Failed to create nodes table.");
-
-        // create receipts table
-        sqlx::query("CREATE TABLE IF NOT EXISTS receipts (
-            uuid TEXT PRIMARY KEY,
-            receipt TEXT NOT NULL
-        );").execute(&pool).await.expect("Failed to create receipts table.");
+#[cfg(any(feature="risc0", test))]
+use crate::{config, error::ApiError, models, storage::NewReceipt};
 
+/// The type stored receipts decode to, needed to hash binary rows for the ledger.
+#[cfg(feature="risc0")]
+pub type StoredReceipt = risc0_zkvm::Receipt;
+#[cfg(not(feature="risc0"))]
+pub type StoredReceipt = serde_json::Value;
+
+pub const JSON: &str = "json";
+pub const BINCODE: &str = "bincode";
+pub const BINCODE_ZSTD: &str = "bincode+zstd";
+
+#[cfg(any(feature="risc0", test))]
+const ZSTD_LEVEL: i32 = 3;
+
+/// Encode a receipt for storage, returning the bytes and the name of their encoding.
+#[cfg(any(feature="risc0", test))]
+pub fn encode_receipt<T: Serialize>(receipt: &T, cfg: &config::DB) -> Result<(Vec<u8>, &'static str)> {
+    let encoded = bincode::serialize(receipt)?;
+    if cfg.compress {
+        Ok((zstd::encode_all(encoded.as_slice(), ZSTD_LEVEL)?, BINCODE_ZSTD))
+    } else {
+        Ok((encoded, BINCODE))
+    }
+}
+
+pub fn decode_receipt<T: DeserializeOwned>(bytes: &[u8], encoding: &str) -> Result<T> {
+    match encoding {
+        JSON => Ok(serde_json::from_slice(bytes)?),
+        BINCODE => Ok(bincode::deserialize(bytes)?),
+        BINCODE_ZSTD => Ok(bincode::deserialize(&zstd::decode_all(bytes)?)?),
+        _ => Err(anyhow::anyhow!("Unknown receipt encoding {}", encoding)),
+    }
+}
+
+/// Ledger hash of a stored receipt, always taken over its JSON serialization so converting it doesn't change it.
+/// Binary receipts only decode to that with risc0, builds without it can't hash them and return None.
+pub fn content_hash(bytes: &[u8], encoding: &str) -> Result<Option<String>> {
+    match encoding {
+        JSON => Ok(Some(ledger::receipt_hash(bytes))),
+        BINCODE | BINCODE_ZSTD => binary_hash(bytes, encoding),
+        _ => Err(anyhow::anyhow!("Unknown receipt encoding {}", encoding)),
     }
-    SqlitePool::connect(&cfg.path).await.expect("Failed to create database connection")
 }
 
-pub async fn insert_receipt<T: Serialize>(pool: &SqlitePool, receipt: T, uuid: Option<String>) -> Result<Uuid> {
+#[cfg(feature="risc0")]
+fn binary_hash(bytes: &[u8], encoding: &str) -> Result<Option<String>> {
+    let receipt: StoredReceipt = decode_receipt(bytes, encoding)?;
+    Ok(Some(ledger::receipt_hash(&serde_json::to_vec(&receipt)?)))
+}
+
+#[cfg(not(feature="risc0"))]
+fn binary_hash(_bytes: &[u8], _encoding: &str) -> Result<Option<String>> {
+    Ok(None)
+}
+
+#[cfg(any(feature="risc0", test))]
+pub async fn insert_receipt<T: Serialize>(db: &dyn Storage, receipt: T, uuid: Option<String>, meta: &models::ReceiptMetadata, cfg: &config::DB) -> Result<Uuid> {
     let uuid = match uuid {
         Some(u) => {
-            let exists = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM receipts WHERE uuid = ?)")
-                .bind(&u)
-                .fetch_one(pool)
-                .await?;
-
-            if exists {
+            if db.receipt_exists(&u).await? {
                 // If the UUID exists, return an error
-                return Err(anyhow::anyhow!("UUID already exists"));
+                return Err(ApiError::Conflict("UUID already exists".to_string()).into());
+            }
+            if db.is_tombstoned(&u).await? {
+                return Err(ApiError::Conflict(format!("Receipt {} has been pruned", u)).into());
             }
-            Uuid::parse_str(&u).map_err(|_| anyhow::anyhow!("Invalid UUID format"))?
+            Uuid::parse_str(&u).map_err(|_| ApiError::Validation("Invalid UUID format".to_string()))?
         },
         None => {
             // Generate a new unique UUID
             loop {
                 let new_uuid = Uuid::new_v4();
-                let exists: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM receipts WHERE uuid = ?)")
-                    .bind(new_uuid.to_string())
-                    .fetch_one(pool)
-                    .await?;
-
-                if !exists {
+                if !db.receipt_exists(&new_uuid.to_string()).await? {
                     break new_uuid;
                 }
             }
         }
     };
 
-    // Insert the receipt with the UUID
-    sqlx::query("INSERT INTO receipts (uuid, receipt) VALUES (?, ?)")
-        .bind(uuid.to_string())
-        .bind(serde_json::to_string(&receipt).expect("Failed to serialize receipt"))
-        .execute(pool)
-        .await?;
+    // Insert the receipt with the UUID and chain it into the ledger
+    let uuid_str = uuid.to_string();
+    let prepared = prepare_receipt(db, &receipt, cfg).await?;
+    db.insert_receipt(&prepared.to_new(&uuid_str, meta)).await?;
 
     Ok(uuid)
 }
 
-pub async fn retrieve_receipt<T: for<'a> serde::Deserialize<'a>>(pool: &SqlitePool, uuid: &str) -> Result<T, sqlx::Error> {
-    sqlx::query("SELECT receipt FROM receipts WHERE uuid = ?")
-        .bind(uuid)
-        .fetch_one(pool)
-        .await
-        .map(|row| serde_json::from_str(&row.get::<String, _>(0)).unwrap())
-}
\ No newline at end of file
+/// A receipt encoded and hashed for storage, its payload already in the blob store if there is one.
+#[cfg(any(feature="risc0", test))]
+pub struct PreparedReceipt {
+    payload: Vec<u8>,
+    encoding: &'static str,
+    blob: Option<String>,
+    hash: String,
+}
+
+#[cfg(any(feature="risc0", test))]
+impl PreparedReceipt {
+    pub fn to_new<'a>(&'a self, uuid: &'a str, meta: &'a models::ReceiptMetadata) -> NewReceipt<'a> {
+        NewReceipt {
+            uuid,
+            payload: if self.blob.is_some() { &[] } else { &self.payload },
+            encoding: self.encoding,
+            blob: self.blob.as_deref(),
+            size: self.payload.len() as i64,
+            hash: &self.hash,
+            meta,
+        }
+    }
+}
+
+/// Encode and hash a receipt, the blob goes first so a stored row never points at a missing one.
+#[cfg(any(feature="risc0", test))]
+pub async fn prepare_receipt<T: Serialize>(db: &dyn Storage, receipt: &T, cfg: &config::DB) -> Result<PreparedReceipt> {
+    let (payload, encoding) = encode_receipt(receipt, cfg)?;
+    let hash = ledger::receipt_hash(&serde_json::to_vec(receipt)?);
+    let blob = externalize(db, &payload).await?;
+    Ok(PreparedReceipt { payload, encoding, blob, hash })
+}
+
+/// Put a payload in the blob store if there is one, returning its key.
+#[cfg(any(feature="risc0", test))]
+async fn externalize(db: &dyn Storage, payload: &[u8]) -> Result<Option<String>> {
+    match db.blobs() {
+        Some(blobs) => {
+            let key = blobs::key(payload);
+            blobs.put(&key, payload).await?;
+            Ok(Some(key))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Encoded bytes of a stored receipt, read from the blob store if it was moved there.
+pub async fn load_payload(db: &dyn Storage, payload: Payload) -> Result<Vec<u8>> {
+    match &payload.blob {
+        Some(key) => {
+            let blobs = db.blobs().ok_or_else(|| anyhow::anyhow!("Receipt {} is in a blob store but none is configured", payload.uuid))?;
+            blobs::load(blobs, key).await
+        },
+        None => Ok(payload.bytes),
+    }
+}
+
+pub async fn retrieve_receipt<T: DeserializeOwned>(db: &dyn Storage, uuid: &str) -> Result<Option<T>> {
+    match db.receipt(uuid).await? {
+        Some(payload) => {
+            let encoding = payload.encoding.clone();
+            Ok(Some(decode_receipt(&load_payload(db, payload).await?, &encoding)?))
+        },
+        None => Ok(None),
+    }
+}
+
+/// Re-encode receipts still stored as JSON and move inline ones to the blob store if there is one,
+/// a batch at a time, until none are left. Receipts that fail are left as they are and returned.
+#[cfg(any(feature="risc0", test))]
+pub async fn convert_json_receipts<T: Serialize + DeserializeOwned>(db: Arc<dyn Storage>, cfg: config::DB) -> Vec<String> {
+    let mut converted = 0;
+    let mut failed = vec![];
+    let mut after = String::new();
+    loop {
+        let rows = match db.inline_receipts(db.blobs().is_some(), &after, 100).await {
+            Ok(rows) => rows,
+            Err(e) => {
+                log::error!("Failed to read receipts for conversion: {}", e);
+                break;
+            }
+        };
+        if rows.is_empty() {
+            break;
+        }
+
+        for row in rows {
+            after = row.uuid.clone();
+            if let Err(e) = convert_receipt::<T>(db.as_ref(), row, &cfg).await {
+                log::warn!("Failed to convert receipt {}, leaving it as it is: {}", after, e);
+                failed.push(after.clone());
+                continue;
+            }
+            converted += 1;
+        }
+    }
+    if converted > 0 {
+        log::info!("Converted {} receipts to binary or blob storage.", converted);
+    }
+    if !failed.is_empty() {
+        log::error!("{} receipts could not be converted: {}", failed.len(), failed.join(", "));
+    }
+    failed
+}
+
+#[cfg(any(feature="risc0", test))]
+async fn convert_receipt<T: Serialize + DeserializeOwned>(db: &dyn Storage, row: Payload, cfg: &config::DB) -> Result<()> {
+    let (payload, encoding) = if row.encoding == JSON {
+        let (payload, encoding) = encode_receipt(&decode_receipt::<T>(&row.bytes, JSON)?, cfg)?;
+        (payload, encoding.to_string())
+    } else {
+        (row.bytes, row.encoding.clone())
+    };
+    let blob = externalize(db, &payload).await?;
+    let inline: &[u8] = if blob.is_some() { &[] } else { &payload };
+    db.rewrite_receipt(&row.uuid, &row.encoding, inline, &encoding, blob.as_deref(), payload.len() as i64).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{sqlite::SqliteStorage, testing, NewReceipt};
+    use crate::{blobs::BlobStore, models};
+
+    #[derive(Debug, PartialEq, Serialize, serde::Deserialize)]
+    struct Receipt {
+        journal: Vec<u8>,
+        seal: Vec<u32>,
+    }
+
+    fn receipt(n: u32) -> Receipt {
+        Receipt { journal: n.to_le_bytes().to_vec(), seal: (n..n + 64).collect() }
+    }
+
+    #[test]
+    fn receipts_decode_from_every_encoding() {
+        for (compress, expected) in [(false, BINCODE), (true, BINCODE_ZSTD)] {
+            let cfg = config::DB { compress, ..config::Config::default().db };
+            let (bytes, encoding) = encode_receipt(&receipt(1), &cfg).unwrap();
+            assert_eq!(encoding, expected);
+            assert_eq!(decode_receipt::<Receipt>(&bytes, encoding).unwrap(), receipt(1));
+        }
+        let json = serde_json::to_vec(&receipt(1)).unwrap();
+        assert_eq!(decode_receipt::<Receipt>(&json, JSON).unwrap(), receipt(1));
+        assert!(decode_receipt::<Receipt>(&json, BINCODE_ZSTD).is_err());
+        assert!(decode_receipt::<Receipt>(&json, "cbor").is_err());
+    }
+
+    #[tokio::test]
+    async fn json_receipts_are_converted_without_changing_their_ledger_hash() {
+        for with_blobs in [false, true] {
+            let dir = testing::temp_dir("convert");
+            let blobs: Option<Arc<dyn BlobStore>> = with_blobs
+                .then(|| Arc::new(crate::blobs::fs::FsBlobStore::open(&dir.join("blobs").display().to_string()).unwrap()) as Arc<dyn BlobStore>);
+            let cfg = testing::db_config(&dir);
+            let db: Arc<dyn Storage> = Arc::new(SqliteStorage::open(&cfg, blobs).await.unwrap());
+
+            // more than one batch of them
+            let uuids: Vec<String> = (0..150).map(|n| format!("receipt-{}", n)).collect();
+            let payloads: Vec<Vec<u8>> = (0..150).map(|n| serde_json::to_vec(&receipt(n)).unwrap()).collect();
+            let hashes: Vec<String> = payloads.iter().map(|payload| ledger::receipt_hash(payload)).collect();
+            let meta = models::ReceiptMetadata::default();
+            let rows: Vec<NewReceipt> = (0..150)
+                .map(|n| NewReceipt {
+                    uuid: &uuids[n],
+                    payload: &payloads[n],
+                    encoding: JSON,
+                    blob: None,
+                    size: payloads[n].len() as i64,
+                    hash: &hashes[n],
+                    meta: &meta,
+                })
+                .collect();
+            db.insert_receipts(&rows).await.unwrap();
+
+            assert!(convert_json_receipts::<Receipt>(db.clone(), cfg).await.is_empty());
+            assert!(db.inline_receipts(with_blobs, "", 1).await.unwrap().is_empty());
+            for (n, uuid) in uuids.iter().enumerate() {
+                let payload = db.receipt(uuid).await.unwrap().unwrap();
+                assert_eq!(payload.encoding, BINCODE_ZSTD);
+                assert_eq!(payload.blob.is_some(), with_blobs);
+                assert_eq!(retrieve_receipt::<Receipt>(db.as_ref(), uuid).await.unwrap(), Some(receipt(n as u32)));
+                assert_eq!(db.ledger_entry(uuid).await.unwrap().unwrap().receipt_hash, hashes[n]);
+            }
+            std::fs::remove_dir_all(dir).unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn receipts_that_fail_to_convert_are_skipped_and_reported() {
+        let dir = testing::temp_dir("convert");
+        let cfg = testing::db_config(&dir);
+        let db: Arc<dyn Storage> = Arc::new(SqliteStorage::open(&cfg, None).await.unwrap());
+        let good = serde_json::to_vec(&receipt(1)).unwrap();
+        let bad = b"{\"journal\":".to_vec();
+        let meta = models::ReceiptMetadata::default();
+        let rows: Vec<NewReceipt> = [("a", &good), ("b", &bad), ("c", &good)]
+            .into_iter()
+            .map(|(uuid, payload)| NewReceipt { uuid, payload, encoding: JSON, blob: None, size: payload.len() as i64, hash: "", meta: &meta })
+            .collect();
+        db.insert_receipts(&rows).await.unwrap();
+
+        assert_eq!(convert_json_receipts::<Receipt>(db.clone(), cfg).await, ["b"]);
+        for (uuid, encoding) in [("a", BINCODE_ZSTD), ("b", JSON), ("c", BINCODE_ZSTD)] {
+            assert_eq!(db.receipt(uuid).await.unwrap().unwrap().encoding, encoding);
+        }
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
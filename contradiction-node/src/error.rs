@@ -0,0 +1,175 @@
+use bytes::Bytes;
+use hyper::{header, Response, StatusCode};
+use crate::{handler::full, models};
+
+type BoxBody = http_body_util::combinators::BoxBody<Bytes, hyper::Error>;
+
+/// Errors a route answers with. Raise them anywhere as `anyhow::Error`, the request handler finds them again
+/// and anything else is an internal error.
+#[derive(Debug, thiserror::Error)]
+pub enum ApiError {
+    #[error("{0}")]
+    NotFound(String),
+    /// Malformed body, query string or parameters
+    #[error("{0}")]
+    Validation(String),
+    /// `allow` lists the methods the path does have routes for
+    #[error("Method {method} is not allowed, use {allow}")]
+    MethodNotAllowed { method: String, allow: String },
+    #[error("{0}")]
+    Conflict(String),
+    /// A receipt or signature that doesn't verify
+    #[error("{0}")]
+    #[cfg(feature="risc0")]
+    Verification(String),
+    // no route takes credentials yet, the code is reserved so clients can rely on it
+    #[allow(dead_code)]
+    #[error("{0}")]
+    Unauthorized(String),
+    #[error("{0}")]
+    PayloadTooLarge(String),
+    /// Seconds to wait go in the Retry-After header
+    #[error("{message}")]
+    #[cfg(feature="risc0")]
+    RateLimited { message: String, retry_after: u64 },
+    #[error(transparent)]
+    Internal(anyhow::Error),
+}
+
+impl ApiError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            ApiError::NotFound(_) => StatusCode::NOT_FOUND,
+            ApiError::Validation(_) => StatusCode::BAD_REQUEST,
+            ApiError::MethodNotAllowed { .. } => StatusCode::METHOD_NOT_ALLOWED,
+            ApiError::Conflict(_) => StatusCode::CONFLICT,
+            #[cfg(feature="risc0")]
+            ApiError::Verification(_) => StatusCode::UNPROCESSABLE_ENTITY,
+            ApiError::Unauthorized(_) => StatusCode::UNAUTHORIZED,
+            ApiError::PayloadTooLarge(_) => StatusCode::PAYLOAD_TOO_LARGE,
+            #[cfg(feature="risc0")]
+            ApiError::RateLimited { .. } => StatusCode::TOO_MANY_REQUESTS,
+            ApiError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
+        }
+    }
+
+    pub fn code(&self) -> &'static str {
+        match self {
+            ApiError::NotFound(_) => "not_found",
+            ApiError::Validation(_) => "validation_failed",
+            ApiError::MethodNotAllowed { .. } => "method_not_allowed",
+            ApiError::Conflict(_) => "conflict",
+            #[cfg(feature="risc0")]
+            ApiError::Verification(_) => "verification_failed",
+            ApiError::Unauthorized(_) => "unauthorized",
+            ApiError::PayloadTooLarge(_) => "payload_too_large",
+            #[cfg(feature="risc0")]
+            ApiError::RateLimited { .. } => "rate_limited",
+            ApiError::Internal(_) => "internal_error",
+        }
+    }
+
+    /// The problem body, internal errors are logged rather than shown to the client.
+    pub fn into_response(self) -> Response<BoxBody> {
+        let status = self.status();
+        let detail = match &self {
+            ApiError::Internal(e) => {
+                log::error!("Internal server error: {:#}", e);
+                "Internal Server Error".to_string()
+            },
+            e => {
+                log::debug!("Request failed with {}: {}", status, e);
+                e.to_string()
+            },
+        };
+        let problem = models::Problem {
+            code: self.code().to_string(),
+            title: status.canonical_reason().unwrap_or_default().to_string(),
+            status: status.as_u16(),
+            detail,
+        };
+        let mut response = Response::builder()
+            .status(status)
+            .header(header::CONTENT_TYPE, "application/problem+json");
+        match &self {
+            #[cfg(feature="risc0")]
+            ApiError::RateLimited { retry_after, .. } => response = response.header(header::RETRY_AFTER, retry_after.to_string()),
+            ApiError::MethodNotAllowed { allow, .. } => response = response.header(header::ALLOW, allow),
+            _ => {},
+        }
+        response
+            .body(full(serde_json::to_vec(&problem).expect("Failed to serialize the problem")))
+            .expect("Failed to construct the error response")
+    }
+}
+
+impl From<anyhow::Error> for ApiError {
+    fn from(err: anyhow::Error) -> ApiError {
+        match err.downcast::<ApiError>() {
+            Ok(api) => api,
+            Err(err) => ApiError::Internal(err),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http_body_util::BodyExt;
+    use super::*;
+    use crate::handler::parse_param;
+
+    async fn problem(err: ApiError) -> (StatusCode, hyper::HeaderMap, models::Problem) {
+        let response = err.into_response();
+        let (parts, body) = response.into_parts();
+        assert_eq!(parts.headers[header::CONTENT_TYPE], "application/problem+json");
+        let problem = serde_json::from_slice(&body.collect().await.unwrap().to_bytes()).unwrap();
+        (parts.status, parts.headers, problem)
+    }
+
+    #[tokio::test]
+    async fn every_error_answers_with_its_status_and_code() {
+        let mut errors = vec![
+            (ApiError::NotFound("gone".to_string()), 404, "not_found"),
+            (ApiError::Validation("bad".to_string()), 400, "validation_failed"),
+            (ApiError::MethodNotAllowed { method: "PUT".to_string(), allow: "GET".to_string() }, 405, "method_not_allowed"),
+            (ApiError::Conflict("taken".to_string()), 409, "conflict"),
+            (ApiError::Unauthorized("who".to_string()), 401, "unauthorized"),
+            (ApiError::PayloadTooLarge("big".to_string()), 413, "payload_too_large"),
+            (ApiError::Internal(anyhow::anyhow!("secret")), 500, "internal_error"),
+        ];
+        #[cfg(feature="risc0")]
+        errors.extend([
+            (ApiError::Verification("forged".to_string()), 422, "verification_failed"),
+            (ApiError::RateLimited { message: "slow down".to_string(), retry_after: 3 }, 429, "rate_limited"),
+        ]);
+
+        for (err, status, code) in errors.drain(..) {
+            assert_eq!((err.status().as_u16(), err.code()), (status, code));
+            let detail = err.to_string();
+            let (answered, headers, problem) = problem(err).await;
+            assert_eq!((answered.as_u16(), problem.status, problem.code.as_str()), (status, status, code));
+            assert_eq!(problem.title, answered.canonical_reason().unwrap());
+            match status {
+                // internal errors stay in the log
+                500 => assert_eq!(problem.detail, "Internal Server Error"),
+                405 => assert_eq!(headers[header::ALLOW], "GET"),
+                429 => assert_eq!(headers[header::RETRY_AFTER], "3"),
+                _ => assert_eq!(problem.detail, detail),
+            }
+        }
+    }
+
+    #[test]
+    fn only_raised_api_errors_are_client_errors() {
+        let raised: anyhow::Error = ApiError::Conflict("taken".to_string()).into();
+        assert_eq!(ApiError::from(raised.context("while storing")).status(), StatusCode::CONFLICT);
+
+        // JSON and numbers that fail to parse are only the client's fault where the handler says so
+        let json = serde_json::from_str::<u32>("x").unwrap_err();
+        assert_eq!(ApiError::from(anyhow::Error::from(json)).status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let number = "x".parse::<i64>().unwrap_err();
+        assert_eq!(ApiError::from(anyhow::Error::from(number)).status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let param = parse_param::<i64>("from", "x").unwrap_err();
+        assert_eq!(ApiError::from(param).code(), "validation_failed");
+    }
+}
@@ -1,23 +1,37 @@
-use std::io::Error;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::Arc;
+use std::time::Instant;
 
 use bytes::Bytes;
-use bytes::Buf;
-use http_body_util::{BodyExt, Full};
-use hyper::{body::Incoming as IncomingBody, header, Method, Request, Response, StatusCode};
+use http_body_util::{BodyExt, Full, LengthLimitError, Limited};
+use hyper::{body::Incoming as IncomingBody, header, Request, Response, StatusCode};
 
-use sqlx::sqlite::SqlitePool;
 
-use crate::models;
+use crate::{config, consensus, error::ApiError, ledger, metrics, models, openapi, receipts, router::{self, Next, Router}, storage::Storage, sync, telemetry};
 
 use anyhow::Result;
+use serde::de::DeserializeOwned;
+use tracing::Instrument;
 
 #[cfg(feature="risc0")]
 use crate::risc0::routes;
 
 type BoxBody = http_body_util::combinators::BoxBody<Bytes, hyper::Error>;
 
-static INTERNAL_SERVER_ERROR: &[u8] = b"Internal Server Error";
+/// Everything a request handler may need, cloned into every connection.
+#[derive(Clone)]
+pub struct AppState {
+    pub db: Arc<dyn Storage>,
+    #[cfg(feature="risc0")]
+    pub config: Arc<config::Config>,
+    #[cfg(feature="risc0")]
+    pub key: Arc<ed25519_dalek::SigningKey>,
+    /// Only set when the consensus layer is enabled
+    pub consensus: Option<Arc<consensus::Consensus>>,
+}
 
+#[utoipa::path(get, path = "/v1/ping", tag = "nodes", responses((status = 200, description = "The node is up", body = models::NodeStatus)))]
 async fn ping() -> Result<Response<BoxBody>> {
     let payload = models::NodeStatus {status: "online".to_string(), timestamp: chrono::Utc::now().naive_utc() };
     Ok(Response::builder()
@@ -27,107 +41,458 @@ async fn ping() -> Result<Response<BoxBody>> {
         .expect("Failed to construct the response"))
 }
 
-async fn register_node(req: Request<IncomingBody>, db: SqlitePool) -> Result<Response<BoxBody>> {
-    let node: models::Node = serde_json::from_reader(req.collect().await?.aggregate().reader())?;
+#[utoipa::path(post, path = "/v1/nodes", tag = "nodes", request_body = models::Node, responses(
+    (status = 201, description = "Node registered"),
+    (status = 409, description = "Node already registered", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn register_node(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let node: models::Node = read_json(req).await?;
+
+    if !db.add_node(&node.address, node.port).await? {
+        return Err(ApiError::Conflict("Node already exists.".to_string()).into());
+    }
+    Ok(Response::builder()
+        .status(StatusCode::CREATED)
+        .body(full("Node registered successfully."))
+        .expect("Failed to create node response"))
+}
+
+#[utoipa::path(get, path = "/v1/nodes", tag = "nodes", responses((status = 200, description = "Known peers", body = [models::Node])))]
+async fn nodes(db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let payload: Vec<models::Node> = db.nodes().await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
     
-    let exists: bool = sqlx::query_scalar(
-        "SELECT EXISTS(SELECT 1 FROM nodes WHERE address = ? AND port = ?)"
-    )
-    .bind(&node.address)
-    .bind(node.port as i32) 
-    .fetch_one(&db)
-    .await?;
-
-    if !exists {
-        sqlx::query(
-            "INSERT INTO nodes (address, port) VALUES (?, ?)"
-        )
-        .bind(&node.address)
-        .bind(node.port as i32) 
-        .execute(&db)
-        .await?;
-
-        
-        Ok(Response::builder()
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/deliveries/{uuid}", tag = "receipts", params(("uuid" = String, Path, description = "Receipt UUID")), responses(
+    (status = 200, description = "Delivery state of the receipt per peer", body = [models::Delivery]),
+))]
+async fn delivery_status(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let uuid = param(&req, "uuid")?;
+    let payload = db.deliveries(&uuid).await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/sync/summary", tag = "sync", params(
+    ("prefix" = Option<String>, Query, description = "Hex digits the summarized UUIDs start with, none by default"),
+), responses(
+    (status = 200, description = "Hash of the stored and pruned receipt UUIDs per range, one per hex digit after the prefix", body = [models::SyncRange]),
+    (status = 400, description = "Prefix is not lowercase hex or too long", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn sync_summary(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let prefix = query_param(&req, "prefix").unwrap_or_default();
+    let payload = sync::summary(db, &prefix).await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/sync/ranges/{prefix}", tag = "sync", params(("prefix" = String, Path, description = "Hex digits the UUIDs start with")), responses(
+    (status = 200, description = "Sorted UUIDs of the receipts in the range", body = [String]),
+    (status = 400, description = "Not 1 to 8 lowercase hex digits", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn sync_range(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let prefix = param(&req, "prefix")?;
+    let payload = sync::range_uuids(db, &prefix).await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/receipts", tag = "receipts", params(models::ReceiptQuery), responses(
+    (status = 200, description = "A page of receipt metadata", body = models::ReceiptPage),
+    (status = 400, description = "Malformed filter, sort or cursor", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn list_receipts(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let param = |key| query_param(&req, key).ok();
+    let listing = receipts::Listing {
+        circuit: param("circuit"),
+        image_id: param("image_id"),
+        submitter: param("submitter"),
+        prover_node: param("prover_node"),
+        from: param("from").map(|v| parse_param("from", &v)).transpose()?,
+        to: param("to").map(|v| parse_param("to", &v)).transpose()?,
+        sort: param("sort").map(|v| receipts::Sort::parse(&v)).transpose()?.unwrap_or_default(),
+        descending: param("order").as_deref() != Some("asc"),
+        limit: param("limit").map(|v| parse_param("limit", &v)).transpose()?.unwrap_or(receipts::DEFAULT_LIMIT),
+        cursor: param("cursor"),
+    };
+    let payload = receipts::list(db, &listing).await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/receipts/{uuid}", tag = "receipts", params(("uuid" = String, Path, description = "Receipt UUID")), responses(
+    (status = 200, description = "Metadata of the receipt", body = models::ReceiptSummary),
+    (status = 404, description = "Receipt not stored here", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn receipt_summary(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let uuid = param(&req, "uuid")?;
+    let payload = receipts::summary(db, &uuid)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Receipt not found.".to_string()))?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/pins", tag = "receipts", responses((status = 200, description = "Receipts exempt from retention", body = [models::Pin])))]
+async fn pins(db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let payload = db.pins().await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(post, path = "/v1/pins/{uuid}", tag = "receipts", params(("uuid" = String, Path, description = "Receipt UUID")), responses(
+    (status = 201, description = "Receipt pinned"),
+    (status = 200, description = "Receipt was already pinned"),
+    (status = 404, description = "Receipt not stored here", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn pin(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let uuid = param(&req, "uuid")?;
+    if !db.receipt_exists(&uuid).await? {
+        return Err(ApiError::NotFound("Receipt not found.".to_string()).into());
+    }
+
+    let response = if db.pin(&uuid, chrono::Utc::now().timestamp()).await? {
+        Response::builder()
             .status(StatusCode::CREATED)
-            .body(full("Node registered successfully."))
-            .expect("Failed to create node response")) 
+            .body(full("Receipt pinned."))?
     } else {
-        // Respond that the node already exists
-        Ok(Response::builder()
-            .status(StatusCode::CONFLICT)
-            .body(full("Node already exists."))
-            .expect("Failed to create node response")) 
+        Response::builder()
+            .status(StatusCode::OK)
+            .body(full("Receipt already pinned."))?
+    };
+
+    Ok(response)
+}
+
+#[utoipa::path(delete, path = "/v1/pins/{uuid}", tag = "receipts", params(("uuid" = String, Path, description = "Receipt UUID")), responses(
+    (status = 200, description = "Receipt unpinned"),
+    (status = 404, description = "Receipt is not pinned", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn unpin(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let uuid = param(&req, "uuid")?;
+    if !db.unpin(&uuid).await? {
+        return Err(ApiError::NotFound("Receipt is not pinned.".to_string()).into());
     }
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .body(full("Receipt unpinned."))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/ledger/head", tag = "ledger", responses(
+    (status = 200, description = "Latest ledger entry", body = models::LedgerEntry),
+    (status = 404, description = "Ledger is empty", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn ledger_head(db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let payload = db.ledger_head()
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Ledger is empty.".to_string()))?;
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/ledger/proofs/{uuid}", tag = "ledger", params(("uuid" = String, Path, description = "Receipt UUID")), responses(
+    (status = 200, description = "Ledger entry of the receipt and every entry after it", body = models::InclusionProof),
+    (status = 404, description = "Receipt is not in the ledger", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn ledger_proof(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let uuid = param(&req, "uuid")?;
+    let payload = ledger::proof(db, &uuid)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Receipt is not in the ledger.".to_string()))?;
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
 }
 
-async fn nodes(db: SqlitePool) -> Result<Response<BoxBody>> {
-    let payload: Vec<models::Node> = sqlx::query_as::<_, models::Node>(
-        "SELECT address, port FROM nodes"
-    )
-    .fetch_all(&db)
-    .await?;
+fn consensus_enabled(state: &AppState) -> Result<Arc<consensus::Consensus>> {
+    // the routes are only registered with consensus enabled
+    state.consensus.clone().ok_or_else(|| ApiError::NotFound("Consensus is not enabled on this node.".to_string()).into())
+}
+
+#[utoipa::path(post, path = "/v1/raft/request-vote", tag = "consensus", request_body = consensus::VoteRequest, responses((status = 200, body = consensus::VoteResponse)))]
+async fn request_vote(req: Request<IncomingBody>, state: AppState) -> Result<Response<BoxBody>> {
+    let consensus = consensus_enabled(&state)?;
+    let vote: consensus::VoteRequest = read_json(req).await?;
+    let payload = consensus.handle_vote(vote).await?;
 
     let response = Response::builder()
-        .status(StatusCode::CREATED)
+        .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
         .body(full(serde_json::to_vec(&payload)?))?;
-    
+
+    Ok(response)
+}
+
+#[utoipa::path(post, path = "/v1/raft/append-entries", tag = "consensus", request_body = consensus::AppendRequest, responses((status = 200, body = consensus::AppendResponse)))]
+async fn append_entries(req: Request<IncomingBody>, state: AppState) -> Result<Response<BoxBody>> {
+    let consensus = consensus_enabled(&state)?;
+    let append: consensus::AppendRequest = read_json(req).await?;
+    let payload = consensus.handle_append(append).await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(post, path = "/v1/raft/propose", tag = "consensus", request_body = consensus::Proposal, responses(
+    (status = 200, description = "Log index of the entry", body = consensus::ProposeResponse),
+    (status = 500, description = "No leader elected", body = models::Problem, content_type = "application/problem+json"),
+))]
+async fn propose(req: Request<IncomingBody>, state: AppState) -> Result<Response<BoxBody>> {
+    let consensus = consensus_enabled(&state)?;
+    let proposal: consensus::Proposal = read_json(req).await?;
+    let payload = consensus::ProposeResponse { index: consensus.propose(proposal).await? };
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/consensus/status", tag = "consensus", responses((status = 200, body = consensus::Status)))]
+async fn consensus_status(state: AppState) -> Result<Response<BoxBody>> {
+    let consensus = consensus_enabled(&state)?;
+    let payload = consensus.status().await;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/consensus/log", tag = "consensus", params(
+    ("from" = Option<i64>, Query, description = "First log index, 1 by default"),
+    ("limit" = Option<usize>, Query, description = "Entries at most, 100 by default and at most 1000"),
+), responses((status = 200, description = "Committed entries", body = [consensus::Entry])))]
+async fn consensus_log(req: Request<IncomingBody>, state: AppState) -> Result<Response<BoxBody>> {
+    let consensus = consensus_enabled(&state)?;
+    let from: i64 = parse_param("from", &query_param(&req, "from").unwrap_or_else(|_| "1".to_string()))?;
+    let limit: usize = parse_param("limit", &query_param(&req, "limit").unwrap_or_else(|_| "100".to_string()))?;
+    let payload = consensus.committed(from, limit.min(1000)).await;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+/// Marks responses logged at debug rather than info.
+#[derive(Clone, Copy)]
+struct Quiet;
+
+/// For routes peers call all the time, like heartbeats, which would flood the log.
+async fn quiet(req: Request<IncomingBody>, state: AppState, next: Next) -> Result<Response<BoxBody>> {
+    let mut response = next.run(req, state).await?;
+    response.extensions_mut().insert(Quiet);
     Ok(response)
 }
 
-pub async fn handle_request(req: Request<IncomingBody>, pool: SqlitePool) -> Result<Response<BoxBody>> {
-    let request = (req.method(), req.uri().path());
-    log::info!("Handling request: {} - {}", request.0.to_string(), request.1.to_string());
+/// Every route of the node, the consensus ones only when it is enabled. Paths from before `/v1` stay as
+/// deprecated aliases.
+pub fn router(state: &AppState) -> Router {
+    let spec = Bytes::from(
+        openapi::document(state.consensus.is_some())
+            .to_json()
+            .expect("Failed to serialize the OpenAPI document"),
+    );
+
+    let mut router = Router::new()
+        .get("/v1/openapi.json", move |_, _| openapi_json(spec.clone()))
+        .get("/metrics", |_, state: AppState| async move { prometheus_metrics(state.db.as_ref()).await })
+        .with(quiet)
+        .get("/v1/ping", |_, _| ping())
+        .with(quiet)
+        .alias("/ping")
+        .post("/v1/nodes", |req, state: AppState| async move { register_node(req, state.db.as_ref()).await })
+        .alias("/register_node")
+        .get("/v1/nodes", |_, state: AppState| async move { nodes(state.db.as_ref()).await })
+        .alias("/registered_nodes")
+        .get("/v1/deliveries/{uuid}", |req, state: AppState| async move { delivery_status(req, state.db.as_ref()).await })
+        .alias("/api/delivery-status")
+        .get("/v1/sync/summary", |req, state: AppState| async move { sync_summary(req, state.db.as_ref()).await })
+        .with(quiet)
+        .alias("/api/sync/summary")
+        .get("/v1/sync/ranges/{prefix}", |req, state: AppState| async move { sync_range(req, state.db.as_ref()).await })
+        .with(quiet)
+        .alias("/api/sync/range")
+        .get("/v1/receipts", |req, state: AppState| async move { list_receipts(req, state.db.as_ref()).await })
+        .alias("/api/receipts")
+        .get("/v1/receipts/{uuid}", |req, state: AppState| async move { receipt_summary(req, state.db.as_ref()).await })
+        .alias("/api/receipts/{uuid}")
+        .get("/v1/pins", |_, state: AppState| async move { pins(state.db.as_ref()).await })
+        .alias("/api/pins")
+        .post("/v1/pins/{uuid}", |req, state: AppState| async move { pin(req, state.db.as_ref()).await })
+        .alias("/api/pins")
+        .alias("/api/pins/{uuid}")
+        .delete("/v1/pins/{uuid}", |req, state: AppState| async move { unpin(req, state.db.as_ref()).await })
+        .alias("/api/pins")
+        .alias("/api/pins/{uuid}")
+        .get("/v1/ledger/head", |_, state: AppState| async move { ledger_head(state.db.as_ref()).await })
+        .alias("/api/ledger/head")
+        .get("/v1/ledger/proofs/{uuid}", |req, state: AppState| async move { ledger_proof(req, state.db.as_ref()).await })
+        .alias("/api/ledger/proof");
+
+    if state.consensus.is_some() {
+        router = router
+            .post("/v1/raft/request-vote", request_vote)
+            .with(quiet)
+            .alias("/raft/request-vote")
+            .post("/v1/raft/append-entries", append_entries)
+            .with(quiet)
+            .alias("/raft/append-entries")
+            .post("/v1/raft/propose", propose)
+            .alias("/raft/propose")
+            .get("/v1/consensus/status", |_, state| consensus_status(state))
+            .alias("/api/consensus/status")
+            .get("/v1/consensus/log", consensus_log)
+            .alias("/api/consensus/log");
+    }
 
-    let response: Result<Response<BoxBody>> = match request {
-        (&Method::GET, "/ping") => ping().await,
-        (&Method::POST, "/register_node") => register_node(req, pool).await,
-        (&Method::GET, "/registered_nodes") => nodes(pool).await,
-        _ => {
-            #[cfg(feature="risc0")]
-            routes::route_handler(req, pool).await
-        },
+    #[cfg(feature="risc0")]
+    let router = routes::register(router);
+
+    router
+}
+
+async fn openapi_json(spec: Bytes) -> Result<Response<BoxBody>> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(spec))?)
+}
+
+async fn prometheus_metrics(db: &dyn Storage) -> Result<Response<BoxBody>> {
+    Ok(Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, prometheus::TEXT_FORMAT)
+        .body(full(metrics::render(db).await?))?)
+}
+
+pub async fn handle_request(req: Request<IncomingBody>, state: AppState, router: Arc<Router>) -> Result<Response<BoxBody>> {
+    let started = Instant::now();
+    let (method, path) = (req.method().clone(), req.uri().path().to_string());
+    // label by route so UUIDs in paths don't each get their own series
+    let route = router.pattern(&method, &path).unwrap_or("unmatched").to_string();
+    let span = telemetry::request_span(method.as_str(), &route, req.headers());
+
+    // Every error is answered with a problem body
+    let response = async { router.dispatch(req, state).await.unwrap_or_else(|err| ApiError::from(err).into_response()) }
+        .instrument(span.clone())
+        .await;
+
+    let level = match response.extensions().get::<Quiet>() {
+        Some(_) => log::Level::Debug,
+        None => log::Level::Info,
     };
+    span.in_scope(|| log::log!(level, "{} {} - {} in {:?}", method, path, response.status().as_u16(), started.elapsed()));
+    metrics::HTTP_REQUESTS.with_label_values(&[method.as_str(), &route, response.status().as_str()]).inc();
+    metrics::HTTP_DURATION.with_label_values(&[method.as_str(), &route]).observe(started.elapsed().as_secs_f64());
+    Ok(response)
+}
 
-    // Error handler
-    match response {
-        Ok(res) => Ok(res),
-        Err(err) => {
-            if err.to_string().contains("No matching GET condition") {
-                log::error!("Page not found");
-                // Return 404 not found response.
-                Ok(Response::builder()
-                    .status(StatusCode::NOT_FOUND)
-                    .body(full("NOT FOUND"))
-                    .unwrap())
-            } else if err.downcast_ref::<serde_json::Error>().is_some() || err.to_string().contains("No/malformed query string") || err.to_string().contains("UUID") {
-                Ok(Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(full(format!("Bad request, double check your JSON and/or the request parameters: {}", err.to_string())))
-                    .expect("Failed to construct bad request response."))
-            } else if err.downcast_ref::<sqlx::Error>().is_some() {
-                log::error!("Database error: {}", err);
-                Ok(Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(full(INTERNAL_SERVER_ERROR))
-                    .expect("Failed to construct the database error response"))
-            } else {
-                if let Some(source) = err.source() {
-                    log::error!("Internal server error: {}", source);
-                    
-                } else {
-                    log::error!("Internal server error: {}", err);
-                }
-                Ok(Response::builder()
-                    .status(StatusCode::INTERNAL_SERVER_ERROR)
-                    .body(full(INTERNAL_SERVER_ERROR))
-                    .expect("Failed to internal server error response"))
-            }
-        }
+/// A parameter from the path of the route, or else from the query string.
+pub fn param(req: &Request<IncomingBody>, key: &str) -> Result<String> {
+    match router::path_param(req, key) {
+        Some(value) => Ok(value.to_string()),
+        None => query_param(req, key),
     }
 }
 
+/// Fetch a parameter from the query string.
+pub fn query_param(req: &Request<IncomingBody>, key: &str) -> Result<String> {
+    let params: HashMap<String, String> = req
+        .uri()
+        .query()
+        .map(|v| {
+            url::form_urlencoded::parse(v.as_bytes())
+                .into_owned()
+                .collect()
+        })
+        .unwrap_or_default();
+    match params.get(key) {
+        Some(value) => Ok(value.clone()),
+        None => Err(ApiError::Validation(format!("Missing query parameter {}", key)).into()),
+    }
+}
+
+/// Read the request body whole, refusing bodies over the `max_body` limit.
+pub async fn read_body(req: Request<IncomingBody>) -> Result<Bytes> {
+    let max_body = config::limits().max_body;
+    match Limited::new(req.into_body(), max_body).collect().await {
+        Ok(body) => Ok(body.to_bytes()),
+        Err(e) if e.is::<LengthLimitError>() => Err(ApiError::PayloadTooLarge(format!("Request body is larger than {} bytes", max_body)).into()),
+        Err(e) => Err(anyhow::anyhow!("Failed to read request body: {}", e)),
+    }
+}
+
+/// Read the request body as JSON, malformed JSON is a validation error.
+pub async fn read_json<T: DeserializeOwned>(req: Request<IncomingBody>) -> Result<T> {
+    let body = read_body(req).await?;
+    serde_json::from_slice(&body).map_err(|e| ApiError::Validation(format!("Malformed request body: {}", e)).into())
+}
+
+/// Parse a path or query parameter, a malformed value is a validation error.
+pub fn parse_param<T: FromStr>(key: &str, value: &str) -> Result<T>
+where
+    T::Err: std::fmt::Display,
+{
+    value.parse().map_err(|e| ApiError::Validation(format!("Invalid {} {:?}: {}", key, value, e)).into())
+}
+
 pub fn full<T: Into<Bytes>>(chunk: T) -> BoxBody {
     Full::new(chunk.into())
         .map_err(|never| match never {})
@@ -0,0 +1,93 @@
+use sha2::{Digest, Sha256};
+use anyhow::Result;
+use crate::{models, storage::Storage};
+
+/// `prev_hash` of the first entry.
+pub const GENESIS: &str = "0000000000000000000000000000000000000000000000000000000000000000";
+
+pub fn receipt_hash(payload: &[u8]) -> String {
+    hex::encode(Sha256::digest(payload))
+}
+
+pub fn entry_hash(seq: i64, uuid: &str, receipt_hash: &str, prev_hash: &str) -> String {
+    hex::encode(Sha256::digest(format!("{}:{}:{}:{}", seq, uuid, receipt_hash, prev_hash)))
+}
+
+/// The entry following `head` (its seq and entry hash), storage backends insert it along with the receipt.
+pub fn next_entry(head: Option<(i64, String)>, uuid: &str, receipt_hash: &str) -> models::LedgerEntry {
+    let (seq, prev_hash) = match head {
+        Some((seq, hash)) => (seq + 1, hash),
+        None => (1, GENESIS.to_string()),
+    };
+    models::LedgerEntry {
+        seq,
+        uuid: uuid.to_string(),
+        receipt_hash: receipt_hash.to_string(),
+        entry_hash: entry_hash(seq, uuid, receipt_hash, &prev_hash),
+        prev_hash,
+        created_at: chrono::Utc::now().timestamp(),
+    }
+}
+
+/// The entry of a receipt and every entry after it, enough to recompute the chain up to the head.
+pub async fn proof(db: &dyn Storage, uuid: &str) -> Result<Option<models::InclusionProof>> {
+    let entry = match db.ledger_entry(uuid).await? {
+        Some(entry) => entry,
+        None => return Ok(None),
+    };
+    let following = db.ledger_entries(entry.seq).await?;
+
+    let head = following.last().unwrap_or(&entry).entry_hash.clone();
+    Ok(Some(models::InclusionProof { entry, following, head }))
+}
+
+/// Recompute the whole chain and check every receipt not yet pruned against it, returning the number of entries.
+pub async fn verify(db: &dyn Storage) -> Result<i64> {
+    let entries = db.ledger_entries(0).await?;
+    let receipts = db.receipt_count().await? + db.tombstone_count().await?;
+    // receipts from before the ledger got their entries when it was migrated in, none can be missing since
+    if entries.is_empty() && receipts > 0 {
+        return Err(anyhow::anyhow!("The ledger is empty but {} receipts are stored, it has been wiped", receipts));
+    }
+
+    let mut prev_hash = GENESIS.to_string();
+    let mut unchecked = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let seq = i as i64 + 1;
+        if entry.seq != seq {
+            return Err(anyhow::anyhow!("Ledger entry {} is missing", seq));
+        }
+        if entry.prev_hash != prev_hash {
+            return Err(anyhow::anyhow!("Ledger entry {} does not link to the previous entry", seq));
+        }
+        if entry.entry_hash != entry_hash(entry.seq, &entry.uuid, &entry.receipt_hash, &entry.prev_hash) {
+            return Err(anyhow::anyhow!("Ledger entry {} has been modified", seq));
+        }
+
+        // a receipt that no longer decodes has been tampered with just the same
+        let hash = match db.receipt(&entry.uuid).await? {
+            Some(payload) => {
+                let encoding = payload.encoding.clone();
+                let bytes = crate::db::load_payload(db, payload).await?;
+                Some(crate::db::content_hash(&bytes, &encoding).unwrap_or(Some(String::new())))
+            },
+            None => None,
+        };
+        match hash {
+            Some(Some(hash)) if hash == entry.receipt_hash => {},
+            Some(None) => unchecked += 1,
+            Some(_) => return Err(anyhow::anyhow!("Receipt {} does not match ledger entry {}", entry.uuid, seq)),
+            None if db.is_tombstoned(&entry.uuid).await? => {},
+            None => return Err(anyhow::anyhow!("Receipt {} of ledger entry {} has been deleted", entry.uuid, seq)),
+        }
+        prev_hash = entry.entry_hash.clone();
+    }
+
+    if receipts != entries.len() as i64 {
+        return Err(anyhow::anyhow!("{} receipts are not in the ledger", receipts - entries.len() as i64));
+    }
+    if unchecked > 0 {
+        log::warn!("{} binary receipts can only be checked against the ledger by a build with risc0.", unchecked);
+    }
+    Ok(entries.len() as i64)
+}
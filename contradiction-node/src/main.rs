@@ -2,6 +2,23 @@ mod config;
 mod models;
 mod handler;
 mod db;
+mod error;
+mod outbox;
+mod attestation;
+mod blobs;
+mod cli;
+mod consensus;
+mod ledger;
+mod metrics;
+mod openapi;
+mod placement;
+mod receipts;
+mod reload;
+mod retention;
+mod router;
+mod storage;
+mod sync;
+mod telemetry;
 
 #[cfg(feature="risc0")]
 mod risc0;
@@ -18,9 +35,8 @@ use reqwest::Client;
 extern crate lazy_static;
 use std::time::SystemTime;
 
-use fern;
-
 use anyhow::Result;
+use clap::Parser;
 
 lazy_static! {
     static ref START_TIME: SystemTime = SystemTime::now(); 
@@ -30,34 +46,48 @@ lazy_static! {
     static ref CLIENT: Client = Client::new();
 }
 
+/// API client of a peer, by its `address:port`. Every peer shares one connection pool.
+pub fn peer(node: &str) -> contradiction_api::Client {
+    contradiction_api::Client::with_http(CLIENT.clone(), &format!("http://{}", node))
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-    // Load config
-    let config = config::Config::read_config();
-
-    // Setup logging
-    fern::Dispatch::new()
-        .format(|out, message, record| {
-            out.finish(format_args!(
-                "[{} {} {}] {}",
-                humantime::format_rfc3339(std::time::SystemTime::now()),
-                record.level(),
-                record.target(),
-                message
-            ))
-        })
-        .level(config.log.level)
-        .chain(if config.log.stdout {
-            Box::new(std::io::stdout()) as Box<dyn std::io::Write + Send>
-        } else {
-            Box::new(std::io::sink()) // If stdout is false, don't log to stdout
-        })
-        .chain(fern::log_file(&config.log.file_output)?)
-        .apply()?;
+    let cli = cli::Cli::parse();
+    let overrides = match cli.command {
+        None => cli.serve,
+        Some(cli::Command::Serve(args)) => args,
+        // Run a one-off command instead of the node
+        Some(command) => return Ok(cli::run(command, &cli.config).await?),
+    };
+
+    // Load config, flags take precedence over the file and the environment
+    let mut config = config::Config::load(&cli.config)?;
+    overrides.apply(&mut config);
+    config.validate()?;
+    config::set_limits(config.limits.clone());
+
+    // Setup logging and tracing, the guard flushes the log file when dropped
+    let _logs = telemetry::init(&config)?;
+    log::debug!("Effective settings:\n{}", toml::to_string_pretty(&config.redacted())?);
+
+    // Load the key attestations are signed with
+    let key = attestation::load_or_generate(&config.keys.path)?;
+    log::info!("Node public key: {}", hex::encode(key.verifying_key().to_bytes()));
 
     // Setup database
-    let pool = db::setup_database(&config.db).await;
-    log::debug!("Created database pool.");
+    let blob_store = blobs::open(&config.blobs)?;
+    let db = storage::open(&config.db, blob_store.clone()).await?;
+    log::debug!("Opened {:?} storage with {:?} blobs.", config.db.backend, config.blobs.backend);
+
+    // Refuse to serve receipts that have been tampered with
+    match ledger::verify(db.as_ref()).await {
+        Ok(entries) => log::info!("Ledger intact, {} entries.", entries),
+        Err(e) => {
+            log::error!("Ledger integrity check failed: {}", e);
+            return Err(format!("Ledger integrity check failed: {}", e).into());
+        }
+    }
 
     // Handle nodes in config
     if let Some(nodes) = &config.nodes {
@@ -69,18 +99,7 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
             match hyper::Uri::from_str(&uri) {
                 Ok(_) => {
                     // Insert the node into the database, ignoring the insert on conflict
-                    let _ = sqlx::query(
-                        "
-                        INSERT INTO nodes (address, port)
-                        VALUES ($1, $2)
-                        ON CONFLICT (address, port)
-                        DO NOTHING
-                        ",
-                    )
-                    .bind(&node.address)
-                    .bind(node.port)
-                    .execute(&pool)
-                    .await;
+                    let _ = db.add_node(&node.address, node.port).await;
                 },
                 Err(e) => {
                     log::error!("Invalid URI for node: {}:{}. Error: {}", node.address, node.port, e);
@@ -89,16 +108,38 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
     }
 
+    // Join the consensus cluster
+    let consensus = match config.consensus.enabled {
+        true => {
+            let transport = std::sync::Arc::new(consensus::Http::new(&config.consensus));
+            Some(consensus::Consensus::load(db.clone(), config.consensus.clone(), config.node_id(), transport).await?)
+        },
+        false => None,
+    };
+    let consensus_task = consensus.clone().map(|c| tokio::spawn(c.run()));
+
     // Set up a TCP listener
     let addr = config.socket_address();
     let listener = TcpListener::bind(addr).await?;
     log::info!("Listening on: {}", addr);
 
     // Run the server!
-    let server_pool = pool.clone();
+    let state = handler::AppState {
+        db: db.clone(),
+        #[cfg(feature="risc0")]
+        config: std::sync::Arc::new(config.clone()),
+        #[cfg(feature="risc0")]
+        key: std::sync::Arc::new(key),
+        consensus,
+    };
+    // Prove submitted jobs in the background
+    #[cfg(feature="risc0")]
+    let prover = tokio::spawn(risc0::jobs::run_worker(state.clone()));
+
+    let router = std::sync::Arc::new(handler::router(&state));
     let server = tokio::spawn(async move {
         loop {
-            let (stream, _) = match listener.accept().await {
+            let (stream, remote) = match listener.accept().await {
                 Ok(conn) => conn,
                 Err(e) => {
                     log::error!("Failed to accept connection: {}", e);
@@ -106,9 +147,13 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
                 }
             };
 
-            let server_pool = server_pool.clone();
+            let (state, router) = (state.clone(), router.clone());
             tokio::spawn(async move {
-                let service = service_fn(move |req| handler::handle_request(req, server_pool.clone()));
+                let service = service_fn(move |mut req: hyper::Request<hyper::body::Incoming>| {
+                    // handlers may want to know who is calling
+                    req.extensions_mut().insert(remote);
+                    handler::handle_request(req, state.clone(), router.clone())
+                });
                 let io = io::new(stream);
 
                 if let Err(err) = http1::Builder::new().serve_connection(io, service).await {
@@ -118,43 +163,68 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
         }
     });
 
+    // Start delivering queued receipts to other nodes
+    let dispatcher = tokio::spawn(outbox::run_dispatcher(db.clone(), config.replication.clone(), config.keys.clone()));
+
+    // Apply the runtime-safe settings of the config file on SIGHUP
+    let reloader = tokio::spawn(reload::run(db.clone(), cli.config.clone(), overrides.clone(), config.clone()));
+
+    // Prune receipts past their retention
+    let pruner = tokio::spawn(retention::run(db.clone(), config.clone()));
+
+    // Remove blobs no receipt refers to
+    let gc = blob_store.map(|blobs| tokio::spawn(blobs::run_gc(db.clone(), blobs, config.blobs.clone())));
+
+    // Re-encode receipts older versions stored as JSON, and move them to the blob store
+    #[cfg(feature="risc0")]
+    let conversion = tokio::spawn(db::convert_json_receipts::<risc0_zkvm::Receipt>(db.clone(), config.db.clone()));
+
+    // Start reconciling receipts with other nodes
+    #[cfg(feature="risc0")]
+    let anti_entropy = tokio::spawn(risc0::sync::run(db.clone(), config.clone()));
+
     // Start updating the nodes!
     let update_nodes = tokio::spawn(async move {
+        // peers with a contradiction_peer_up series
+        let mut reported = std::collections::HashSet::new();
         loop {
             tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-            let db = pool.clone();
-            let nodes: Vec<models::Node> = sqlx::query_as::<_, models::Node>(
-                    "SELECT address, port FROM nodes"
-                )
-                .fetch_all(&db)
-                .await.expect("Failed to fetch nodes from database.");
+            let nodes: Vec<models::Node> = match db.nodes().await {
+                Ok(nodes) => nodes,
+                Err(e) => {
+                    log::error!("Failed to fetch nodes from database, trying again next round: {:#}", e);
+                    continue;
+                },
+            };
+            let mut up = std::collections::HashSet::new();
             for node in nodes {
-                let node_status = CLIENT.get(&format!("http://{}:{}/ping", node.address, node.port)).send().await;
+                let id = placement::node_id(&node.address, node.port);
+                let node_status = peer(&id).ping().await;
                 match node_status {
-                    Ok(response) => {
-                        if response.status().is_success() {
-                            log::info!("Node {}:{} is alive.", node.address, node.port);
-                        } else {
-                            log::info!("Node {}:{} is dead.", node.address, node.port);
-                            sqlx::query("DELETE FROM nodes WHERE address = ? AND port = ?")
-                                .bind(node.address)
-                                .bind(node.port)
-                                .execute(&db)
-                                .await
-                                .expect("Failed to delete dead node.");
+                    Ok(_) => {
+                        log::info!("Node {}:{} is alive.", node.address, node.port);
+                        metrics::PEER_UP.with_label_values(&[&id]).set(1);
+                        up.insert(id);
+                    },
+                    Err(contradiction_api::Error::Status { .. }) => {
+                        log::info!("Node {}:{} is dead.", node.address, node.port);
+                        if let Err(e) = db.remove_node(&node.address, node.port).await {
+                            log::error!("Failed to delete dead node {}:{}: {:#}", node.address, node.port, e);
                         }
                     },
                     Err(_e) => {
                         log::info!("Node {}:{} is dead/url malformed.", node.address, node.port);
-                        sqlx::query("DELETE FROM nodes WHERE address = ? AND port = ?")
-                            .bind(node.address)
-                            .bind(node.port)
-                            .execute(&db)
-                            .await
-                            .expect("Failed to delete dead node.");
+                        if let Err(e) = db.remove_node(&node.address, node.port).await {
+                            log::error!("Failed to delete dead node {}:{}: {:#}", node.address, node.port, e);
+                        }
                     }
                 }
             }
+            // drop the series of peers removed here or with `peers remove`, they would report their last state forever
+            for gone in reported.difference(&up) {
+                let _ = metrics::PEER_UP.remove_label_values(&[gone]);
+            }
+            reported = up;
         }
     });
 
@@ -165,6 +235,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
 
     server.abort();
     update_nodes.abort();
+    dispatcher.abort();
+    pruner.abort();
+    reloader.abort();
+    for task in [consensus_task, gc].into_iter().flatten() {
+        task.abort();
+    }
+    #[cfg(feature="risc0")]
+    {
+        anti_entropy.abort();
+        conversion.abort();
+        prover.abort();
+    }
+    telemetry::shutdown();
 
     Ok(())
 }
@@ -0,0 +1,92 @@
+use anyhow::Result;
+use prometheus::{
+    exponential_buckets, register_histogram_vec, register_int_counter_vec, register_int_gauge, register_int_gauge_vec,
+    Encoder, HistogramVec, IntCounterVec, IntGauge, IntGaugeVec, TextEncoder,
+};
+use crate::storage::Storage;
+
+lazy_static! {
+    pub static ref HTTP_REQUESTS: IntCounterVec = register_int_counter_vec!(
+        "contradiction_http_requests_total",
+        "Requests answered, by the route they matched and the status.",
+        &["method", "route", "status"]
+    ).expect("Failed to register metric");
+    pub static ref HTTP_DURATION: HistogramVec = register_histogram_vec!(
+        "contradiction_http_request_duration_seconds",
+        "Time taken to answer requests, by the route they matched.",
+        &["method", "route"]
+    ).expect("Failed to register metric");
+    pub static ref PROVING_DURATION: HistogramVec = register_histogram_vec!(
+        "contradiction_proving_duration_seconds",
+        "Time taken to prove a circuit.",
+        &["circuit"],
+        exponential_buckets(0.5, 2.0, 14).expect("Invalid buckets")
+    ).expect("Failed to register metric");
+    pub static ref PROVING_CYCLES: HistogramVec = register_histogram_vec!(
+        "contradiction_proving_cycles",
+        "Cycles covered by the proofs of a circuit.",
+        &["circuit"],
+        exponential_buckets(65536.0, 2.0, 16).expect("Invalid buckets")
+    ).expect("Failed to register metric");
+    pub static ref JOBS_QUEUED: IntGauge = register_int_gauge!(
+        "contradiction_jobs_queued",
+        "Proving jobs waiting for the prover."
+    ).expect("Failed to register metric");
+    /// `source` is where the receipt or attestation came from: receipt, bundle, sync, lookup or attestation
+    pub static ref VERIFICATION_FAILURES: IntCounterVec = register_int_counter_vec!(
+        "contradiction_verification_failures_total",
+        "Receipts and attestations that failed verification.",
+        &["source"]
+    ).expect("Failed to register metric");
+    pub static ref PEERS: IntGauge = register_int_gauge!(
+        "contradiction_peers",
+        "Peers currently registered."
+    ).expect("Failed to register metric");
+    pub static ref PEER_UP: IntGaugeVec = register_int_gauge_vec!(
+        "contradiction_peer_up",
+        "Whether the peer answered its last ping.",
+        &["peer"]
+    ).expect("Failed to register metric");
+    /// `outcome` is the status the delivery is left in: delivered, pending to be retried, or failed
+    pub static ref DELIVERIES: IntCounterVec = register_int_counter_vec!(
+        "contradiction_deliveries_total",
+        "Attempts to deliver receipts to peers.",
+        &["outcome"]
+    ).expect("Failed to register metric");
+    /// `outcome` is ok or failed
+    pub static ref SYNC_ROUNDS: IntCounterVec = register_int_counter_vec!(
+        "contradiction_sync_rounds_total",
+        "Anti-entropy rounds with a peer.",
+        &["outcome"]
+    ).expect("Failed to register metric");
+    pub static ref SYNC_PULLED: IntCounterVec = register_int_counter_vec!(
+        "contradiction_sync_pulled_total",
+        "Receipts pulled from peers by anti-entropy.",
+        &["peer"]
+    ).expect("Failed to register metric");
+    pub static ref SYNC_FAILED: IntCounterVec = register_int_counter_vec!(
+        "contradiction_sync_failed_total",
+        "Receipts anti-entropy failed to pull from peers.",
+        &["peer"]
+    ).expect("Failed to register metric");
+    /// `state` is idle, in_use or max
+    pub static ref DB_CONNECTIONS: IntGaugeVec = register_int_gauge_vec!(
+        "contradiction_db_connections",
+        "Connections of the database pool.",
+        &["state"]
+    ).expect("Failed to register metric");
+}
+
+/// Update the gauges read from the database, then render every metric in the Prometheus text format.
+pub async fn render(db: &dyn Storage) -> Result<Vec<u8>> {
+    JOBS_QUEUED.set(db.queued_jobs().await?);
+    PEERS.set(db.nodes().await?.len() as i64);
+    let pool = db.pool_status();
+    DB_CONNECTIONS.with_label_values(&["idle"]).set(pool.idle as i64);
+    DB_CONNECTIONS.with_label_values(&["in_use"]).set(pool.size as i64 - pool.idle as i64);
+    DB_CONNECTIONS.with_label_values(&["max"]).set(pool.max.into());
+
+    let mut buffer = Vec::new();
+    TextEncoder::new().encode(&prometheus::gather(), &mut buffer)?;
+    Ok(buffer)
+}
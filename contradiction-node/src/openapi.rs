@@ -0,0 +1,151 @@
+use utoipa::OpenApi;
+use crate::{consensus, handler, models};
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "Contradiction node", description = "Errors are answered with a `Problem`, see its `code` for the kinds."),
+    paths(
+        handler::ping,
+        handler::register_node,
+        handler::nodes,
+        handler::delivery_status,
+        handler::sync_summary,
+        handler::sync_range,
+        handler::list_receipts,
+        handler::receipt_summary,
+        handler::pins,
+        handler::pin,
+        handler::unpin,
+        handler::ledger_head,
+        handler::ledger_proof,
+    ),
+    components(schemas(
+        models::Problem,
+        models::NodeStatus,
+        models::Node,
+        models::Delivery,
+        models::SyncRange,
+        models::ReceiptSummary,
+        models::ReceiptPage,
+        models::Pin,
+        models::LedgerEntry,
+        models::InclusionProof,
+    )),
+    tags(
+        (name = "nodes", description = "Peers of the cluster"),
+        (name = "receipts", description = "Stored receipts and their replication"),
+        (name = "sync", description = "Anti-entropy between peers"),
+        (name = "ledger", description = "Append-only log of stored receipts"),
+    ),
+)]
+struct NodeApi;
+
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        handler::request_vote,
+        handler::append_entries,
+        handler::propose,
+        handler::consensus_status,
+        handler::consensus_log,
+    ),
+    components(schemas(
+        consensus::VoteRequest,
+        consensus::VoteResponse,
+        consensus::AppendRequest,
+        consensus::AppendResponse,
+        consensus::Entry,
+        consensus::Proposal,
+        consensus::ProposeResponse,
+        consensus::Role,
+        consensus::Status,
+    )),
+    tags((name = "consensus", description = "Raft RPCs between nodes and the committed receipt log")),
+)]
+struct ConsensusApi;
+
+#[cfg(feature = "risc0")]
+#[derive(OpenApi)]
+#[openapi(
+    paths(
+        crate::risc0::routes::do_compute,
+        crate::risc0::routes::submit_job,
+        crate::risc0::routes::job_status,
+        crate::risc0::routes::fetch_compute,
+        crate::risc0::routes::save_compute,
+        crate::risc0::routes::export_bundle,
+        crate::risc0::routes::import_bundle,
+    ),
+    components(schemas(
+        contradiction_api::circuits::CircuitInputs,
+        contradiction_api::circuits::Hypotenuse,
+        contradiction_api::circuits::LinearPolynomial,
+        models::DefaultResponse,
+        models::Job,
+        models::FetchResponse,
+        models::AttestedResponse,
+        models::Attestation,
+        models::IncomingReceipt,
+        models::ReceiptMetadata,
+        models::ImportReport,
+    )),
+    tags(
+        (name = "proving", description = "Proving circuits, the receipts are stored under the returned UUIDs"),
+        (name = "bundles", description = "Moving receipts between clusters"),
+    ),
+)]
+struct ProvingApi;
+
+/// Description of the `/v1` routes this node serves.
+pub fn document(consensus: bool) -> utoipa::openapi::OpenApi {
+    let mut doc = NodeApi::openapi();
+    // utoipa fills in the crate's license, which isn't set
+    doc.info.license = None;
+    if consensus {
+        doc.merge(ConsensusApi::openapi());
+    }
+    #[cfg(feature = "risc0")]
+    doc.merge(ProvingApi::openapi());
+    doc
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::sync::Arc;
+    use super::*;
+    use crate::storage::{sqlite::SqliteStorage, testing};
+
+    #[tokio::test]
+    async fn every_v1_route_is_documented() {
+        let dir = testing::temp_dir("openapi");
+        let state = handler::AppState {
+            db: Arc::new(SqliteStorage::open(&testing::db_config(&dir), None).await.unwrap()),
+            #[cfg(feature="risc0")]
+            config: Arc::new(crate::config::Config::default()),
+            #[cfg(feature="risc0")]
+            key: Arc::new(ed25519_dalek::SigningKey::from_bytes(&[7; 32])),
+            consensus: None,
+        };
+        let routes: BTreeSet<(String, String)> = handler::router(&state)
+            .routes()
+            .filter(|(_, path)| path.starts_with("/v1/") && *path != "/v1/openapi.json")
+            .map(|(method, path)| (method.as_str().to_lowercase(), path.to_string()))
+            .collect();
+
+        // as clients get it
+        let json = document(false).to_json().unwrap();
+        let doc: serde_json::Value = serde_json::from_str(&json).unwrap();
+        assert!(doc["openapi"].as_str().unwrap().starts_with("3."));
+        let mut documented = BTreeSet::new();
+        for (path, item) in doc["paths"].as_object().unwrap() {
+            for (method, operation) in item.as_object().unwrap() {
+                assert!(operation["responses"].as_object().is_some_and(|r| !r.is_empty()), "{} {} has no responses", method, path);
+                documented.insert((method.clone(), path.clone()));
+            }
+        }
+        assert_eq!(documented, routes);
+        assert!(doc["paths"]["/v1/nodes"]["get"]["responses"]["200"].is_object());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,301 @@
+use std::sync::Arc;
+use anyhow::Result;
+use futures::StreamExt;
+use tokio::sync::Notify;
+use tracing::Instrument;
+use crate::{attestation, config, metrics, models, placement, storage::Storage, telemetry};
+
+lazy_static! {
+    static ref WAKE: Notify = Notify::new();
+}
+
+/// A delivery whose next attempt is due, with the payload to send.
+#[derive(sqlx::FromRow)]
+pub struct Due {
+    pub uuid: String,
+    pub address: String,
+    pub port: u16,
+    pub attempts: u32,
+    pub path: String,
+    pub payload: String,
+}
+
+/// Outcome of an attempt as recorded on the delivery.
+pub struct Update {
+    pub status: &'static str,
+    pub attempts: u32,
+    pub next_retry_at: i64,
+    pub last_error: Option<String>,
+    pub updated_at: i64,
+}
+
+enum Outcome {
+    Delivered(Option<models::Attestation>),
+    Retry(String),
+    Rejected(String),
+}
+
+/// Queue a payload for delivery to every given node and wake the dispatcher.
+#[cfg(feature="risc0")]
+pub async fn enqueue(db: &dyn Storage, uuid: &str, path: &str, payload: &str, nodes: &[models::Node]) -> Result<()> {
+    db.enqueue_deliveries(uuid, path, payload, nodes).await?;
+    WAKE.notify_one();
+    Ok(())
+}
+
+/// Delay before the next attempt, doubling from `retry_base` up to `retry_max` seconds.
+fn backoff(cfg: &config::Replication, attempts: u32) -> i64 {
+    let delay = cfg.retry_base.saturating_mul(1u64 << attempts.min(32));
+    delay.min(cfg.retry_max) as i64
+}
+
+async fn deliver(due: &Due) -> Outcome {
+    // the dispatcher schedules retries itself
+    let client = telemetry::propagate(crate::peer(&placement::node_id(&due.address, due.port)), &due.uuid).retries(0);
+    match client.post_raw(&due.path, due.payload.clone().into_bytes()).await {
+        Ok(body) => {
+            // Peers that verified the receipt answer with a signed attestation
+            let attestation = serde_json::from_slice::<models::AttestedResponse>(&body)
+                .ok()
+                .map(|r| r.attestation);
+            Outcome::Delivered(attestation)
+        },
+        // The peer answered but refused the payload, retrying won't change its mind
+        Err(e @ contradiction_api::Error::Status { .. }) if !e.is_retryable() => {
+            Outcome::Rejected(format!("Peer rejected payload: {}", e))
+        },
+        Err(e) => Outcome::Retry(e.to_string()),
+    }
+}
+
+/// The key a peer's attestations have to be signed with, from the config or else pinned on first use.
+async fn pinned_key(db: &dyn Storage, cfg: &config::Keys, attestation: &models::Attestation) -> Result<String> {
+    if let Some(key) = cfg.peers.get(&attestation.node) {
+        return Ok(key.clone());
+    }
+    if !cfg.pin_unknown {
+        return Err(anyhow::anyhow!("No key is pinned for {}", attestation.node));
+    }
+    // only a key that signed this very attestation gets pinned
+    attestation::verify(attestation, &attestation.public_key)?;
+    let pinned = db.pin_peer_key(&attestation.node, &attestation.public_key).await?;
+    if pinned == attestation.public_key {
+        log::debug!("Pinned key {} for {}.", pinned, attestation.node);
+    }
+    Ok(pinned)
+}
+
+/// Check an attestation vouches for the receipt as stored here, its image and its journal.
+async fn attests_stored(db: &dyn Storage, attestation: &models::Attestation) -> Result<()> {
+    let meta = db.receipt_metadata(&attestation.uuid)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Receipt {} is not stored", attestation.uuid))?;
+    let image_id = meta.image_id.unwrap_or_default();
+    if attestation.image_id != image_id {
+        return Err(anyhow::anyhow!("Attests image {} instead of {}", attestation.image_id, image_id));
+    }
+    let journal_hash = attestation::journal_hash(&meta.journal.unwrap_or_default());
+    if attestation.journal_hash != journal_hash {
+        return Err(anyhow::anyhow!("Attests journal {} instead of {}", attestation.journal_hash, journal_hash));
+    }
+    Ok(())
+}
+
+async fn store_attestation(db: &dyn Storage, cfg: &config::Keys, peer: &str, uuid: &str, attestation: &models::Attestation) {
+    if attestation.uuid != uuid {
+        log::warn!("{} attested {} instead of {}, ignoring it.", attestation.node, attestation.uuid, uuid);
+        return;
+    }
+    if attestation.node != peer {
+        log::warn!("{} answered with an attestation by {}, ignoring it.", peer, attestation.node);
+        metrics::VERIFICATION_FAILURES.with_label_values(&["attestation"]).inc();
+        return;
+    }
+    // what it vouches for is checked first, a key is only pinned for an attestation of the stored receipt
+    let verified = match attests_stored(db, attestation).await {
+        Ok(()) => pinned_key(db, cfg, attestation).await,
+        Err(e) => Err(e),
+    };
+    let verified = match verified {
+        Ok(pinned) => attestation::verify(attestation, &pinned),
+        Err(e) => Err(e),
+    };
+    if let Err(e) = verified {
+        log::warn!("Invalid attestation for {} from {}: {}", uuid, attestation.node, e);
+        metrics::VERIFICATION_FAILURES.with_label_values(&["attestation"]).inc();
+        return;
+    }
+    if let Err(e) = db.insert_attestation(attestation).await {
+        log::error!("Failed to store attestation for {} from {}: {}", uuid, attestation.node, e);
+    }
+}
+
+/// Deliveries attempted at once, so one slow peer doesn't hold up the rest.
+const CONCURRENCY: usize = 8;
+
+async fn attempt(db: &dyn Storage, cfg: &config::Replication, keys: &config::Keys, row: Due) -> Result<()> {
+    let attempts = row.attempts + 1;
+    let peer = placement::node_id(&row.address, row.port);
+    let span = tracing::info_span!("delivery", job = %row.uuid, peer = %peer);
+    let outcome = deliver(&row).instrument(span).await;
+    let now = chrono::Utc::now().timestamp();
+    let (status, next_retry_at, last_error) = match outcome {
+        Outcome::Delivered(attestation) => {
+            log::debug!("Delivered {} to {}:{}.", row.uuid, row.address, row.port);
+            if let Some(attestation) = attestation {
+                store_attestation(db, keys, &peer, &row.uuid, &attestation).await;
+            }
+            ("delivered", now, None)
+        },
+        Outcome::Rejected(e) => {
+            log::warn!("Giving up on delivering {} to {}:{}: {}", row.uuid, row.address, row.port, e);
+            ("failed", now, Some(e))
+        },
+        Outcome::Retry(e) if attempts >= cfg.max_attempts => {
+            log::warn!("Giving up on delivering {} to {}:{} after {} attempts: {}", row.uuid, row.address, row.port, attempts, e);
+            ("failed", now, Some(e))
+        },
+        Outcome::Retry(e) => {
+            log::info!("Delivery of {} to {}:{} failed, attempt {}: {}", row.uuid, row.address, row.port, attempts, e);
+            ("pending", now + backoff(cfg, attempts), Some(e))
+        },
+    };
+    metrics::DELIVERIES.with_label_values(&[status]).inc();
+    db.update_delivery(&row, &Update { status, attempts, next_retry_at, last_error, updated_at: now }).await
+}
+
+async fn dispatch_due(db: &dyn Storage, cfg: &config::Replication, keys: &config::Keys) -> Result<()> {
+    let due = db.due_deliveries(chrono::Utc::now().timestamp(), 50).await?;
+    let recorded: Vec<Result<()>> = futures::stream::iter(due)
+        .map(|row| attempt(db, cfg, keys, row))
+        .buffer_unordered(CONCURRENCY)
+        .collect()
+        .await;
+    recorded.into_iter().collect::<Result<()>>()?;
+
+    // Drop payloads nobody is waiting for anymore
+    db.prune_payloads().await
+}
+
+/// Deliver queued payloads forever, waking up on new work or every `poll_interval` seconds.
+pub async fn run_dispatcher(db: Arc<dyn Storage>, cfg: config::Replication, keys: config::Keys) {
+    loop {
+        if let Err(e) = dispatch_due(db.as_ref(), &cfg, &keys).await {
+            log::error!("Outbox dispatch failed: {}", e);
+        }
+        tokio::select! {
+            _ = WAKE.notified() => {},
+            _ = tokio::time::sleep(tokio::time::Duration::from_secs(cfg.poll_interval)) => {},
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::Bytes;
+    use ed25519_dalek::SigningKey;
+    use http_body_util::Full;
+    use hyper::{body::Incoming, server::conn::http1, service::service_fn, Request, Response};
+    use hyper_util::rt::TokioIo;
+    use super::*;
+    use crate::storage::testing;
+
+    const UUID: &str = "6f1c2a9e-3b4d-4c8e-9f10-2a3b4c5d6e7f";
+
+    /// A peer answering every request with the status its path names, e.g. `/503`.
+    async fn peer() -> models::Node {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let service = service_fn(|req: Request<Incoming>| async move {
+                    let status: u16 = req.uri().path().trim_start_matches('/').parse().unwrap();
+                    Ok::<_, hyper::Error>(Response::builder().status(status).body(Full::new(Bytes::from_static(b"{}"))).unwrap())
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        models::Node { address: "127.0.0.1".to_string(), port, last_ping_at: None }
+    }
+
+    async fn delivery(db: &dyn Storage, uuid: &str) -> models::Delivery {
+        db.deliveries(uuid).await.unwrap().pop().unwrap()
+    }
+
+    #[test]
+    fn retries_back_off_exponentially_up_to_the_maximum() {
+        let cfg = config::Replication::default();
+        let delays: Vec<i64> = [1, 2, 3, 10, 64].into_iter().map(|attempts| backoff(&cfg, attempts)).collect();
+        assert_eq!(delays, [10, 20, 40, 3600, 3600]);
+    }
+
+    #[tokio::test]
+    async fn deliveries_end_delivered_or_failed() {
+        let store = testing::sqlite().await;
+        let node = peer().await;
+        for (uuid, path) in [("ok", "/200"), ("rejected", "/400"), ("busy", "/503")] {
+            store.enqueue_deliveries(uuid, path, "{}", std::slice::from_ref(&node)).await.unwrap();
+        }
+        // retried right away
+        let cfg = config::Replication { max_attempts: 2, retry_base: 0, ..Default::default() };
+        let keys = config::Keys::default();
+
+        dispatch_due(&*store, &cfg, &keys).await.unwrap();
+        let ok = delivery(&*store, "ok").await;
+        assert_eq!((ok.status.as_str(), ok.attempts, ok.last_error), ("delivered", 1, None));
+        let rejected = delivery(&*store, "rejected").await;
+        assert_eq!((rejected.status.as_str(), rejected.attempts), ("failed", 1));
+        assert!(rejected.last_error.unwrap().contains("400"));
+        let busy = delivery(&*store, "busy").await;
+        assert_eq!((busy.status.as_str(), busy.attempts), ("pending", 1));
+
+        dispatch_due(&*store, &cfg, &keys).await.unwrap();
+        let busy = delivery(&*store, "busy").await;
+        assert_eq!((busy.status.as_str(), busy.attempts), ("failed", 2));
+        assert!(busy.last_error.unwrap().contains("503"));
+        // nothing is left to deliver
+        assert!(store.due_deliveries(i64::MAX, 10).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn failed_deliveries_wait_for_their_backoff() {
+        let store = testing::sqlite().await;
+        store.enqueue_deliveries("busy", "/503", "{}", &[peer().await]).await.unwrap();
+        let cfg = config::Replication::default();
+
+        dispatch_due(&*store, &cfg, &config::Keys::default()).await.unwrap();
+        let busy = delivery(&*store, "busy").await;
+        assert_eq!((busy.status.as_str(), busy.attempts), ("pending", 1));
+        assert_eq!(busy.next_retry_at - busy.updated_at, backoff(&cfg, 1));
+
+        // not due again until then
+        dispatch_due(&*store, &cfg, &config::Keys::default()).await.unwrap();
+        assert_eq!(delivery(&*store, "busy").await.attempts, 1);
+        assert!(store.due_deliveries(busy.next_retry_at - 1, 10).await.unwrap().is_empty());
+        assert_eq!(store.due_deliveries(busy.next_retry_at, 10).await.unwrap().len(), 1);
+    }
+
+    fn attest(seed: u8, node: &str, image_id: &str, journal: &[u8]) -> models::Attestation {
+        attestation::attest(&SigningKey::from_bytes(&[seed; 32]), node, UUID, image_id, &attestation::journal_hash(journal))
+    }
+
+    #[tokio::test]
+    async fn only_attestations_of_the_stored_receipt_count() {
+        let store = testing::sqlite().await;
+        let meta = models::ReceiptMetadata { image_id: Some("image".to_string()), journal: Some(b"journal".to_vec()), ..Default::default() };
+        testing::insert_receipt(&*store, UUID, &meta).await;
+        let keys = config::Keys::default();
+
+        let honest = attest(1, "10.0.0.1:8080", "image", b"journal");
+        let other_journal = attest(2, "10.0.0.2:8080", "image", b"forged");
+        let other_image = attest(3, "10.0.0.3:8080", "other", b"journal");
+        for attestation in [&honest, &other_journal, &other_image] {
+            store_attestation(&*store, &keys, &attestation.node, UUID, attestation).await;
+        }
+
+        let nodes: Vec<String> = store.attestations(UUID).await.unwrap().into_iter().map(|a| a.node).collect();
+        assert_eq!(nodes, ["10.0.0.1:8080"]);
+        // nor did the peers get their keys pinned
+        assert!(!store.unpin_peer_key("10.0.0.2:8080").await.unwrap());
+    }
+}
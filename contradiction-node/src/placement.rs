@@ -0,0 +1,163 @@
+#[cfg(any(feature="risc0", test))]
+use sha2::{Digest, Sha256};
+#[cfg(any(feature="risc0", test))]
+use crate::models;
+
+/// Identity of a node as peers know it.
+pub fn node_id(address: &str, port: u16) -> String {
+    format!("{}:{}", address, port)
+}
+
+/// Rendezvous (highest random weight) score of a node for a receipt.
+#[cfg(any(feature="risc0", test))]
+fn score(uuid: &str, node: &str) -> u64 {
+    let digest = Sha256::new()
+        .chain_update(uuid.as_bytes())
+        .chain_update(b"@")
+        .chain_update(node.as_bytes())
+        .finalize();
+    let mut bytes = [0u8; 8];
+    bytes.copy_from_slice(&digest[..8]);
+    u64::from_be_bytes(bytes)
+}
+
+/// Order node ids by their preference to hold the receipt, best first.
+#[cfg(any(feature="risc0", test))]
+pub fn rank(uuid: &str, mut nodes: Vec<String>) -> Vec<String> {
+    nodes.sort_by_cached_key(|node| std::cmp::Reverse((score(uuid, node), node.clone())));
+    nodes.dedup();
+    nodes
+}
+
+/// Node ids that should hold a receipt. A factor of 0 places it on every node.
+#[cfg(any(feature="risc0", test))]
+pub fn replicas(uuid: &str, nodes: Vec<String>, factor: usize) -> Vec<String> {
+    let ranked = rank(uuid, nodes);
+    match factor {
+        0 => ranked,
+        n => ranked.into_iter().take(n).collect(),
+    }
+}
+
+/// Peers (from the `nodes` table) a new receipt should be pushed to, never including ourselves.
+#[cfg(any(feature="risc0", test))]
+pub fn targets(uuid: &str, peers: Vec<models::Node>, self_id: &str, factor: usize) -> Vec<models::Node> {
+    let mut ids: Vec<String> = peers.iter().map(|n| node_id(&n.address, n.port)).collect();
+    ids.push(self_id.to_string());
+    let chosen = replicas(uuid, ids, factor);
+
+    peers
+        .into_iter()
+        .filter(|n| {
+            let id = node_id(&n.address, n.port);
+            id != self_id && chosen.contains(&id)
+        })
+        .collect()
+}
+
+/// Peers ordered by how likely they are to hold a receipt, replicas first.
+#[cfg(any(feature="risc0", test))]
+pub fn lookup_order(uuid: &str, mut peers: Vec<models::Node>, self_id: &str) -> Vec<models::Node> {
+    peers.retain(|n| node_id(&n.address, n.port) != self_id);
+    peers.sort_by_cached_key(|n| {
+        let id = node_id(&n.address, n.port);
+        std::cmp::Reverse((score(uuid, &id), id))
+    });
+    peers
+}
+
+/// Whether this node is one of the replicas of a receipt, given the peers it knows of.
+#[cfg(any(feature="risc0", test))]
+pub fn holds(uuid: &str, peers: &[models::Node], self_id: &str, factor: usize) -> bool {
+    if factor == 0 {
+        return true;
+    }
+    let mut ids: Vec<String> = peers.iter().map(|n| node_id(&n.address, n.port)).collect();
+    ids.push(self_id.to_string());
+    replicas(uuid, ids, factor).iter().any(|id| id == self_id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "6f1c2a9e-3b4d-4c8e-9f10-2a3b4c5d6e7f";
+
+    fn ids(n: usize) -> Vec<String> {
+        (0..n).map(|i| node_id(&format!("10.0.0.{}", i), 8080)).collect()
+    }
+
+    fn nodes(ids: &[String]) -> Vec<models::Node> {
+        ids.iter()
+            .map(|id| {
+                let (address, port) = id.rsplit_once(':').unwrap();
+                models::Node { address: address.to_string(), port: port.parse().unwrap(), last_ping_at: None }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn ranking_does_not_depend_on_the_order_nodes_are_listed_in() {
+        let mut reversed = ids(5);
+        reversed.reverse();
+        assert_eq!(rank(UUID, ids(5)), rank(UUID, reversed));
+    }
+
+    #[test]
+    fn ranking_removes_duplicates() {
+        let mut twice = ids(3);
+        twice.extend(ids(3));
+        assert_eq!(rank(UUID, twice).len(), 3);
+    }
+
+    #[test]
+    fn a_new_node_only_displaces_replicas_it_outranks() {
+        // consistent hashing: adding a node never reorders the others
+        let before = rank(UUID, ids(5));
+        let after: Vec<String> = rank(UUID, ids(6)).into_iter().filter(|id| before.contains(id)).collect();
+        assert_eq!(before, after);
+    }
+
+    #[test]
+    fn factor_limits_the_replicas() {
+        assert_eq!(replicas(UUID, ids(5), 2), rank(UUID, ids(5))[..2]);
+        assert_eq!(replicas(UUID, ids(5), 0).len(), 5);
+        assert_eq!(replicas(UUID, ids(5), 9).len(), 5);
+    }
+
+    #[test]
+    fn receipts_are_spread_over_the_nodes() {
+        let mut first = std::collections::HashMap::new();
+        for i in 0..1000 {
+            let uuid = format!("{:08x}-0000-4000-8000-000000000000", i);
+            *first.entry(rank(&uuid, ids(4)).remove(0)).or_insert(0) += 1;
+        }
+        assert_eq!(first.len(), 4);
+        assert!(first.values().all(|&n| n > 150), "{:?}", first);
+    }
+
+    #[test]
+    fn targets_and_holds_agree_with_the_replicas() {
+        let all = ids(5);
+        let chosen = replicas(UUID, all.clone(), 2);
+        for self_id in &all {
+            let peers: Vec<String> = all.iter().filter(|id| *id != self_id).cloned().collect();
+            let targets: Vec<String> = targets(UUID, nodes(&peers), self_id, 2)
+                .iter()
+                .map(|n| node_id(&n.address, n.port))
+                .collect();
+            assert!(targets.iter().all(|id| chosen.contains(id) && id != self_id));
+            assert_eq!(targets.len(), if chosen.contains(self_id) { 1 } else { 2 });
+            assert_eq!(holds(UUID, &nodes(&peers), self_id, 2), chosen.contains(self_id));
+        }
+    }
+
+    #[test]
+    fn lookups_ask_the_replicas_first_and_never_ourselves() {
+        let all = ids(5);
+        let order = lookup_order(UUID, nodes(&all), &all[0]);
+        let order: Vec<String> = order.iter().map(|n| node_id(&n.address, n.port)).collect();
+        let expected: Vec<String> = rank(UUID, all.clone()).into_iter().filter(|id| *id != all[0]).collect();
+        assert_eq!(order, expected);
+    }
+}
@@ -0,0 +1,254 @@
+use sqlx::{Database, Encode, QueryBuilder, Type};
+use anyhow::Result;
+use crate::{error::ApiError, models, storage::Storage};
+
+pub const DEFAULT_LIMIT: i64 = 50;
+pub const MAX_LIMIT: i64 = 500;
+
+/// Filters and ordering for a receipt listing, every filter is optional.
+#[derive(Debug, Default)]
+pub struct Listing {
+    pub circuit: Option<String>,
+    pub image_id: Option<String>,
+    pub submitter: Option<String>,
+    pub prover_node: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub sort: Sort,
+    pub descending: bool,
+    pub limit: i64,
+    pub cursor: Option<String>,
+}
+
+#[derive(Debug, Default, Clone, Copy)]
+pub enum Sort {
+    #[default]
+    CreatedAt,
+    Size,
+    Cycles,
+    ProvingMs,
+}
+
+impl Sort {
+    pub fn parse(value: &str) -> Result<Sort> {
+        match value {
+            "created_at" => Ok(Sort::CreatedAt),
+            "size" => Ok(Sort::Size),
+            "cycles" => Ok(Sort::Cycles),
+            "proving_ms" => Ok(Sort::ProvingMs),
+            _ => Err(ApiError::Validation(format!("Invalid receipt listing sort {}, use created_at, size, cycles or proving_ms", value)).into()),
+        }
+    }
+
+    // receipts stored before their metadata existed sort as 0
+    fn column(&self) -> &'static str {
+        match self {
+            Sort::CreatedAt => "COALESCE(created_at, 0)",
+            Sort::Size => "COALESCE(size, 0)",
+            Sort::Cycles => "COALESCE(cycles, 0)",
+            Sort::ProvingMs => "COALESCE(proving_ms, 0)",
+        }
+    }
+}
+
+/// Cursors point just past the last receipt of a page, as the sort value and uuid of that receipt.
+fn encode_cursor(value: i64, uuid: &str) -> String {
+    hex::encode(format!("{}:{}", value, uuid))
+}
+
+fn decode_cursor(cursor: &str) -> Result<(i64, String)> {
+    let invalid = || anyhow::Error::from(ApiError::Validation("Invalid receipt listing cursor".to_string()));
+    let decoded = String::from_utf8(hex::decode(cursor).map_err(|_| invalid())?).map_err(|_| invalid())?;
+    let (value, uuid) = decoded.split_once(':').ok_or_else(invalid)?;
+    Ok((value.parse().map_err(|_| invalid())?, uuid.to_string()))
+}
+
+fn sort_value(sort: Sort, receipt: &models::ReceiptSummary) -> i64 {
+    match sort {
+        Sort::CreatedAt => receipt.created_at,
+        Sort::Size => receipt.size,
+        Sort::Cycles => receipt.cycles,
+        Sort::ProvingMs => receipt.proving_ms,
+    }
+    .unwrap_or(0)
+}
+
+/// Add the listing's filters, cursor, order and `limit` to a query selecting from `receipts`.
+pub fn push_filters<'a, DB>(query: &mut QueryBuilder<'a, DB>, listing: &Listing, limit: i64) -> Result<()>
+where
+    DB: Database,
+    String: Encode<'a, DB> + Type<DB>,
+    i64: Encode<'a, DB> + Type<DB>,
+{
+    let column = listing.sort.column();
+    let (cmp, order) = if listing.descending { ("<", "DESC") } else { (">", "ASC") };
+
+    query.push(" WHERE 1 = 1");
+    if let Some(circuit) = &listing.circuit {
+        query.push(" AND circuit = ").push_bind(circuit.clone());
+    }
+    if let Some(image_id) = &listing.image_id {
+        query.push(" AND image_id = ").push_bind(image_id.to_lowercase());
+    }
+    if let Some(submitter) = &listing.submitter {
+        query.push(" AND submitter = ").push_bind(submitter.clone());
+    }
+    if let Some(prover_node) = &listing.prover_node {
+        query.push(" AND prover_node = ").push_bind(prover_node.clone());
+    }
+    if let Some(from) = listing.from {
+        query.push(" AND created_at >= ").push_bind(from);
+    }
+    if let Some(to) = listing.to {
+        query.push(" AND created_at < ").push_bind(to);
+    }
+    if let Some(cursor) = &listing.cursor {
+        let (value, uuid) = decode_cursor(cursor)?;
+        query
+            .push(format!(" AND ({}, uuid) {} (", column, cmp))
+            .push_bind(value)
+            .push(", ")
+            .push_bind(uuid)
+            .push(")");
+    }
+    query
+        .push(format!(" ORDER BY {} {}, uuid {} LIMIT ", column, order, order))
+        .push_bind(limit);
+    Ok(())
+}
+
+/// One page of receipt metadata, the receipts themselves are fetched separately.
+pub async fn list(db: &dyn Storage, listing: &Listing) -> Result<models::ReceiptPage> {
+    let limit = listing.limit.clamp(1, MAX_LIMIT);
+    // one extra row tells us whether there is another page
+    let mut receipts = db.list_receipts(listing, limit + 1).await?;
+
+    let next_cursor = if receipts.len() as i64 > limit {
+        receipts.truncate(limit as usize);
+        receipts.last().map(|r| encode_cursor(sort_value(listing.sort, r), &r.uuid))
+    } else {
+        None
+    };
+    Ok(models::ReceiptPage { receipts, next_cursor })
+}
+
+/// Metadata of a single receipt, as it appears in listings.
+pub async fn summary(db: &dyn Storage, uuid: &str) -> Result<Option<models::ReceiptSummary>> {
+    let Some(meta) = db.receipt_metadata(uuid).await? else {
+        return Ok(None);
+    };
+    Ok(Some(models::ReceiptSummary {
+        uuid: uuid.to_string(),
+        circuit: meta.circuit,
+        image_id: meta.image_id,
+        journal: meta.journal.map(hex::encode),
+        proof_kind: meta.proof_kind,
+        prover_node: meta.prover_node,
+        submitter: meta.submitter,
+        input_hash: meta.input_hash,
+        created_at: meta.created_at,
+        proving_ms: meta.proving_ms,
+        cycles: meta.cycles,
+        size: meta.size,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::{testing, NewReceipt};
+
+    fn meta(created_at: Option<i64>, circuit: &str, cycles: i64) -> models::ReceiptMetadata {
+        models::ReceiptMetadata {
+            circuit: Some(circuit.to_string()),
+            image_id: Some(format!("{}-image", circuit)),
+            submitter: Some(format!("{}-submitter", circuit)),
+            created_at,
+            cycles: Some(cycles),
+            ..Default::default()
+        }
+    }
+
+    /// Every page of a listing in turn, as uuids.
+    async fn pages(db: &dyn Storage, mut listing: Listing) -> Vec<Vec<String>> {
+        let mut pages = vec![];
+        loop {
+            let page = list(db, &listing).await.unwrap();
+            pages.push(page.receipts.into_iter().map(|r| r.uuid).collect());
+            match page.next_cursor {
+                Some(cursor) => listing.cursor = Some(cursor),
+                None => return pages,
+            }
+        }
+    }
+
+    async fn seeded() -> testing::TempStore {
+        let store = testing::sqlite().await;
+        let receipts = [
+            ("a", Some(10), "hypotenuse", 5),
+            ("b", Some(10), "linear", 3),
+            ("c", Some(10), "hypotenuse", 9),
+            ("d", Some(20), "linear", 1),
+            ("e", Some(20), "hypotenuse", 7),
+            ("f", Some(30), "linear", 3),
+            // stamped with the time it is stored
+            ("g", None, "hypotenuse", 0),
+        ];
+        for (uuid, created_at, circuit, cycles) in receipts {
+            testing::insert_receipt(&*store, uuid, &meta(created_at, circuit, cycles)).await;
+        }
+        store
+    }
+
+    #[tokio::test]
+    async fn pages_cover_every_receipt_once_across_ties() {
+        let store = seeded().await;
+        let newest_first = pages(&*store, Listing { limit: 2, descending: true, ..Default::default() }).await;
+        assert_eq!(newest_first, [vec!["g", "f"], vec!["e", "d"], vec!["c", "b"], vec!["a"]]);
+
+        let oldest_first = pages(&*store, Listing { limit: 3, ..Default::default() }).await;
+        assert_eq!(oldest_first, [vec!["a", "b", "c"], vec!["d", "e", "f"], vec!["g"]]);
+
+        let by_cycles = pages(&*store, Listing { limit: 2, sort: Sort::Cycles, descending: true, ..Default::default() }).await;
+        assert_eq!(by_cycles.concat(), ["c", "e", "a", "f", "b", "d", "g"]);
+    }
+
+    #[tokio::test]
+    async fn filters_narrow_the_listing() {
+        let store = seeded().await;
+        let listed = |listing: Listing| {
+            let store = &store;
+            async move { pages(&**store, Listing { limit: 2, ..listing }).await.concat() }
+        };
+        assert_eq!(listed(Listing { circuit: Some("linear".to_string()), ..Default::default() }).await, ["b", "d", "f"]);
+        assert_eq!(listed(Listing { image_id: Some("LINEAR-IMAGE".to_string()), ..Default::default() }).await, ["b", "d", "f"]);
+        assert_eq!(listed(Listing { submitter: Some("hypotenuse-submitter".to_string()), ..Default::default() }).await, ["a", "c", "e", "g"]);
+        assert_eq!(listed(Listing { from: Some(10), to: Some(30), ..Default::default() }).await, ["a", "b", "c", "d", "e"]);
+        assert_eq!(
+            listed(Listing { circuit: Some("hypotenuse".to_string()), from: Some(20), to: Some(30), ..Default::default() }).await,
+            ["e"]
+        );
+        assert!(listed(Listing { prover_node: Some("nobody".to_string()), ..Default::default() }).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn limits_are_clamped_and_cursors_checked() {
+        let store = testing::sqlite().await;
+        let uuids: Vec<String> = (0..MAX_LIMIT + 1).map(|n| format!("{:04}", n)).collect();
+        let meta = models::ReceiptMetadata { created_at: Some(1), ..Default::default() };
+        let rows: Vec<NewReceipt> = uuids
+            .iter()
+            .map(|uuid| NewReceipt { uuid, payload: b"{}", encoding: crate::db::JSON, blob: None, size: 2, hash: "", meta: &meta })
+            .collect();
+        store.insert_receipts(&rows).await.unwrap();
+
+        let page = list(&*store, &Listing { limit: 10_000, ..Default::default() }).await.unwrap();
+        assert_eq!(page.receipts.len() as i64, MAX_LIMIT);
+        assert!(page.next_cursor.is_some());
+        let page = list(&*store, &Listing { limit: 0, ..Default::default() }).await.unwrap();
+        assert_eq!(page.receipts.len(), 1);
+
+        let err = list(&*store, &Listing { cursor: Some("not hex".to_string()), ..Default::default() }).await.unwrap_err();
+        assert_eq!(ApiError::from(err).code(), "validation_failed");
+    }
+}
@@ -0,0 +1,103 @@
+use std::collections::BTreeSet;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio::signal::unix::{signal, SignalKind};
+use crate::{cli::ServeArgs, config::{self, Config}, storage::Storage, telemetry};
+
+/// Sections of the config whose changes only take effect after a restart.
+fn needs_restart(old: &Config, new: &Config) -> Vec<String> {
+    // leave out what a reload applies
+    let mut new = new.clone();
+    new.log.level = old.log.level;
+    new.limits = old.limits.clone();
+    new.nodes = old.nodes.clone();
+
+    let (Ok(toml::Value::Table(old)), Ok(toml::Value::Table(new))) = (toml::Value::try_from(old), toml::Value::try_from(&new)) else {
+        return Vec::new();
+    };
+    let changed: BTreeSet<&String> = old
+        .keys()
+        .chain(new.keys())
+        .filter(|key| old.get(*key) != new.get(*key))
+        .collect();
+    changed.into_iter().cloned().collect()
+}
+
+/// Apply what can change while running to `current`, the rest of it stays as the node started. Returns the
+/// sections that still differ from the new config.
+async fn apply(db: &dyn Storage, current: &mut Config, new: &Config) -> Vec<String> {
+    if new.log.level != current.log.level {
+        telemetry::set_level(new.log.level);
+        log::info!("Log level changed from {} to {}.", current.log.level, new.log.level);
+        current.log.level = new.log.level;
+    }
+    if new.limits != current.limits {
+        config::set_limits(new.limits.clone());
+        log::info!("Limits changed to {:?}.", new.limits);
+        current.limits = new.limits.clone();
+    }
+    // peers are only added, the ones dropped from the config may have registered themselves
+    for node in new.nodes.iter().flatten() {
+        match db.add_node(&node.address, node.port).await {
+            Ok(true) => log::info!("Added node {}:{} from the config.", node.address, node.port),
+            Ok(false) => {},
+            Err(e) => log::error!("Failed to add node {}:{}: {}", node.address, node.port, e),
+        }
+    }
+    current.nodes = new.nodes.clone();
+
+    let stale = needs_restart(current, new);
+    if !stale.is_empty() {
+        log::warn!("Changes to {} take effect after a restart.", stale.join(", "));
+    }
+    stale
+}
+
+/// Reload the config on SIGHUP, applying the log level, peers and limits. Flags given on the
+/// command line keep taking precedence.
+pub async fn run(db: Arc<dyn Storage>, path: PathBuf, overrides: ServeArgs, mut current: Config) {
+    let mut hangup = match signal(SignalKind::hangup()) {
+        Ok(hangup) => hangup,
+        Err(e) => {
+            log::error!("Failed to listen for SIGHUP, config reload is disabled: {}", e);
+            return;
+        }
+    };
+    while hangup.recv().await.is_some() {
+        log::info!("SIGHUP received, reloading {}.", path.display());
+        let loaded = Config::load(&path).and_then(|mut cfg| {
+            overrides.apply(&mut cfg);
+            cfg.validate()?;
+            Ok(cfg)
+        });
+        match loaded {
+            Ok(cfg) => {
+                apply(db.as_ref(), &mut current, &cfg).await;
+            },
+            Err(e) => log::error!("Keeping the current config: {}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing;
+
+    #[tokio::test]
+    async fn restart_only_changes_are_reported_until_the_restart() {
+        let store = testing::sqlite().await;
+        let mut current = Config::default();
+        let mut new = Config::default();
+        new.api.port += 1;
+        // a limit no other test reads, they are global
+        new.limits.max_queued_jobs += 1;
+
+        assert_eq!(apply(&*store, &mut current, &new).await, ["api"]);
+        assert_eq!(current.limits, new.limits);
+        assert_eq!(current.api.port, Config::default().api.port);
+        // still not in effect on the next reload
+        assert_eq!(apply(&*store, &mut current, &new).await, ["api"]);
+        assert!(apply(&*store, &mut current, &Config { limits: new.limits.clone(), ..Config::default() }).await.is_empty());
+    }
+}
@@ -0,0 +1,146 @@
+use std::sync::Arc;
+use anyhow::Result;
+use crate::{blobs, config, storage::Storage};
+
+const BATCH: i64 = 500;
+
+/// Prune every receipt its circuit's policy no longer keeps, returning how many were removed.
+pub async fn prune(db: &dyn Storage, cfg: &config::Retention) -> Result<usize> {
+    let now = chrono::Utc::now().timestamp();
+    let mut pruned = 0;
+    for circuit in db.circuits().await? {
+        let policy = cfg.policy(circuit.as_deref());
+        if policy.keeps_forever() {
+            continue;
+        }
+        let before = policy.days.map(|days| now - days as i64 * 86400);
+        let keep_last = policy.last.unwrap_or(0) as i64;
+        loop {
+            let expired = db.expired_receipts(circuit.as_deref(), before, keep_last, BATCH).await?;
+            if expired.is_empty() {
+                break;
+            }
+            let mut removed = 0;
+            for uuid in &expired {
+                if db.prune_receipt(uuid, now).await? {
+                    removed += 1;
+                }
+            }
+            pruned += removed;
+            // everything left was pinned in the meantime
+            if removed == 0 {
+                break;
+            }
+        }
+    }
+    Ok(pruned)
+}
+
+/// Every `retention.interval` seconds, prune expired receipts and collect the blobs they leave behind.
+pub async fn run(db: Arc<dyn Storage>, cfg: config::Config) {
+    let retention = &cfg.retention;
+    if retention.default.keeps_forever() && retention.circuits.values().all(|p| p.keeps_forever()) {
+        log::info!("No retention policy set, keeping receipts forever.");
+        return;
+    }
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(retention.interval)).await;
+        let pruned = match prune(db.as_ref(), retention).await {
+            Ok(pruned) => pruned,
+            Err(e) => {
+                log::error!("Pruning receipts failed: {}", e);
+                continue;
+            }
+        };
+        if pruned == 0 {
+            log::debug!("No receipts to prune.");
+            continue;
+        }
+        log::info!("Pruned {} expired receipts.", pruned);
+
+        if let Some(blobs) = db.blobs() {
+            match blobs::collect_garbage(db.as_ref(), blobs, cfg.blobs.gc_grace).await {
+                Ok(removed) => log::info!("Removed {} blobs of pruned receipts.", removed),
+                Err(e) => log::error!("Failed to remove blobs of pruned receipts: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{error::ApiError, models, storage::testing};
+
+    const DAY: i64 = 86400;
+
+    fn stored(circuit: &str, age_days: i64) -> models::ReceiptMetadata {
+        let created_at = chrono::Utc::now().timestamp() - age_days * DAY;
+        models::ReceiptMetadata { circuit: Some(circuit.to_string()), created_at: Some(created_at), ..Default::default() }
+    }
+
+    async fn remaining(db: &dyn Storage, uuids: &[&str]) -> Vec<String> {
+        let mut kept = vec![];
+        for uuid in uuids {
+            if db.receipt_exists(uuid).await.unwrap() {
+                kept.push(uuid.to_string());
+            }
+        }
+        kept
+    }
+
+    #[tokio::test]
+    async fn pinned_receipts_survive_their_policy() {
+        let store = testing::sqlite().await;
+        for uuid in ["a", "b", "c"] {
+            testing::insert_receipt(&*store, uuid, &stored("hypotenuse", 10)).await;
+        }
+        testing::insert_receipt(&*store, "d", &stored("hypotenuse", 0)).await;
+        assert!(store.pin("b", 1).await.unwrap());
+        let cfg = config::Retention { default: config::Policy { days: Some(7), last: None }, ..Default::default() };
+
+        assert_eq!(prune(&*store, &cfg).await.unwrap(), 2);
+        assert_eq!(remaining(&*store, &["a", "b", "c", "d"]).await, ["b", "d"]);
+        // once unpinned it goes with the next run
+        assert!(store.unpin("b").await.unwrap());
+        assert_eq!(prune(&*store, &cfg).await.unwrap(), 1);
+        assert_eq!(store.tombstone_count().await.unwrap(), 3);
+    }
+
+    #[tokio::test]
+    async fn circuit_policies_override_the_default() {
+        let store = testing::sqlite().await;
+        let receipts = [("kept-1", "kept", 30), ("last-1", "last", 30), ("last-2", "last", 20), ("other-1", "other", 30), ("other-2", "other", 1)];
+        for (uuid, circuit, age) in receipts {
+            testing::insert_receipt(&*store, uuid, &stored(circuit, age)).await;
+        }
+        let cfg = config::Retention {
+            default: config::Policy { days: Some(7), last: None },
+            circuits: [
+                ("kept".to_string(), config::Policy::default()),
+                ("last".to_string(), config::Policy { days: None, last: Some(1) }),
+            ]
+            .into(),
+            ..Default::default()
+        };
+
+        assert_eq!(prune(&*store, &cfg).await.unwrap(), 2);
+        assert_eq!(remaining(&*store, &receipts.map(|(uuid, _, _)| uuid)).await, ["kept-1", "last-2", "other-2"]);
+    }
+
+    #[tokio::test]
+    async fn pruned_receipts_are_not_stored_again() {
+        let store = testing::sqlite().await;
+        let uuid = uuid::Uuid::new_v4().to_string();
+        testing::insert_receipt(&*store, &uuid, &stored("hypotenuse", 10)).await;
+        let cfg = config::Retention { default: config::Policy { days: Some(7), last: None }, ..Default::default() };
+        assert_eq!(prune(&*store, &cfg).await.unwrap(), 1);
+
+        let err = crate::db::insert_receipt(&*store, &uuid, Some(uuid.clone()), &stored("hypotenuse", 0), &config::Config::default().db)
+            .await
+            .unwrap_err();
+        assert_eq!(ApiError::from(err).code(), "conflict");
+        assert!(!store.receipt_exists(&uuid).await.unwrap());
+        assert!(store.is_tombstoned(&uuid).await.unwrap());
+    }
+}
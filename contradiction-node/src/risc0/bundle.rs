@@ -0,0 +1,317 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+use risc0_zkvm::Receipt;
+use serde::{Deserialize, Serialize};
+use anyhow::Result;
+use crate::{config, error::ApiError, metrics, receipts, risc0::models, storage::Storage};
+use crate::{models::{ImportReport, ReceiptMetadata}, db::prepare_receipt as prepare, db::retrieve_receipt as retrieve};
+
+/// Bundles are zstd compressed tar archives of a manifest, the bincode encoded receipts and the guest ELFs
+/// they were proven with.
+const VERSION: u32 = 1;
+const MANIFEST: &str = "manifest.json";
+const ZSTD_LEVEL: i32 = 3;
+
+#[derive(Debug, Deserialize, Serialize)]
+struct Manifest {
+    version: u32,
+    exported_at: i64,
+    exported_by: String,
+    receipts: Vec<Entry>,
+}
+
+/// The journal is left out of the metadata, it is part of the receipt.
+#[derive(Debug, Deserialize, Serialize)]
+struct Entry {
+    uuid: String,
+    metadata: ReceiptMetadata,
+}
+
+/// Which receipts to export, the listed UUIDs or else every receipt matching the filters.
+#[derive(Debug, Default)]
+pub struct Selection {
+    pub uuids: Vec<String>,
+    pub circuit: Option<String>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+}
+
+fn receipt_path(uuid: &str) -> String {
+    format!("receipts/{}.bin", uuid)
+}
+
+fn elf_path(image_id: &str) -> String {
+    format!("elfs/{}.elf", image_id)
+}
+
+/// What a bundled receipt has to verify against, the ELF only for circuits this node doesn't know.
+struct Circuit<'a> {
+    name: &'a str,
+    id: [u32; 8],
+    elf: Option<&'a [u8]>,
+}
+
+fn invalid(reason: String) -> anyhow::Error {
+    ApiError::Validation(format!("Invalid bundle: {}", reason)).into()
+}
+
+async fn select(db: &dyn Storage, selection: &Selection) -> Result<Vec<String>> {
+    if !selection.uuids.is_empty() {
+        return Ok(selection.uuids.clone());
+    }
+    let mut listing = receipts::Listing {
+        circuit: selection.circuit.clone(),
+        from: selection.from,
+        to: selection.to,
+        limit: receipts::MAX_LIMIT,
+        ..Default::default()
+    };
+    let mut uuids = Vec::new();
+    loop {
+        let page = receipts::list(db, &listing).await?;
+        uuids.extend(page.receipts.into_iter().map(|r| r.uuid));
+        match page.next_cursor {
+            Some(cursor) => listing.cursor = Some(cursor),
+            None => return Ok(uuids),
+        }
+    }
+}
+
+/// ELFs of circuits this node was built with, or else of circuits it imported.
+async fn find_elf(db: &dyn Storage, image_id: &str) -> Result<Option<Vec<u8>>> {
+    match models::known_image(image_id).and_then(|(circuit, _)| models::circuit_elf(circuit)) {
+        Some(elf) => Ok(Some(elf.to_vec())),
+        None => db.elf(image_id).await,
+    }
+}
+
+fn append(archive: &mut tar::Builder<Vec<u8>>, path: &str, bytes: &[u8]) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(bytes.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    archive.append_data(&mut header, path, bytes)?;
+    Ok(())
+}
+
+pub async fn export(db: &dyn Storage, selection: &Selection, node: &str) -> Result<Vec<u8>> {
+    let mut manifest = Manifest {
+        version: VERSION,
+        exported_at: chrono::Utc::now().timestamp(),
+        exported_by: node.to_string(),
+        receipts: Vec::new(),
+    };
+    let mut archive = tar::Builder::new(Vec::new());
+    let mut elfs = HashSet::new();
+
+    for uuid in select(db, selection).await? {
+        let receipt: Receipt = retrieve(db, &uuid)
+            .await?
+            .ok_or_else(|| ApiError::NotFound(format!("Receipt {} not found", uuid)))?;
+        let meta = db.receipt_metadata(&uuid).await?.unwrap_or_default();
+        append(&mut archive, &receipt_path(&uuid), &bincode::serialize(&receipt)?)?;
+
+        if let Some(image_id) = &meta.image_id {
+            if elfs.insert(image_id.clone()) {
+                match find_elf(db, image_id).await? {
+                    Some(elf) => append(&mut archive, &elf_path(image_id), &elf)?,
+                    None => log::warn!("No guest ELF for image {}, exporting its receipts without it.", image_id),
+                }
+            }
+        }
+        manifest.receipts.push(Entry { uuid, metadata: ReceiptMetadata { journal: None, ..meta } });
+    }
+
+    append(&mut archive, MANIFEST, &serde_json::to_vec_pretty(&manifest)?)?;
+    Ok(zstd::encode_all(archive.into_inner()?.as_slice(), ZSTD_LEVEL)?)
+}
+
+/// Unpack a bundle, refusing one that decompresses to more than `limit` bytes.
+fn read_files(bundle: &[u8], limit: u64) -> Result<HashMap<String, Vec<u8>>> {
+    let mut tar = Vec::new();
+    zstd::Decoder::new(bundle)
+        .and_then(|decoder| decoder.take(limit + 1).read_to_end(&mut tar))
+        .map_err(|e| invalid(e.to_string()))?;
+    if tar.len() as u64 > limit {
+        return Err(ApiError::PayloadTooLarge(format!("Bundle is larger than {} bytes decompressed", limit)).into());
+    }
+    let mut archive = tar::Archive::new(tar.as_slice());
+    let mut files = HashMap::new();
+    for entry in archive.entries().map_err(|e| invalid(e.to_string()))? {
+        let mut entry = entry.map_err(|e| invalid(e.to_string()))?;
+        let path = entry.path()?.to_string_lossy().to_string();
+        let mut bytes = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        files.insert(path, bytes);
+    }
+    Ok(files)
+}
+
+/// Unknown circuits need their ELF in the bundle, its image ID has to be the one the manifest claims.
+fn circuit_of<'a>(entry: &'a Entry, files: &'a HashMap<String, Vec<u8>>) -> Result<Circuit<'a>> {
+    let image_id = entry.metadata.image_id.as_deref().ok_or_else(|| invalid(format!("receipt {} has no image ID", entry.uuid)))?;
+    if let Some((name, id)) = models::known_image(image_id) {
+        return Ok(Circuit { name, id, elf: None });
+    }
+
+    let name = entry.metadata.circuit.as_deref().ok_or_else(|| invalid(format!("receipt {} has no circuit", entry.uuid)))?;
+    // the name of a known circuit belongs to its image, another ELF can't be listed under it
+    if models::CIRCUITS.iter().any(|(known, _)| *known == name) {
+        return Err(invalid(format!("image {} is not the one of circuit {}", image_id, name)));
+    }
+    let elf = files.get(&elf_path(image_id)).ok_or_else(|| invalid(format!("no guest ELF for unknown image {}", image_id)))?;
+    let digest = risc0_zkvm::compute_image_id(elf).map_err(|e| invalid(format!("guest ELF of image {}: {}", image_id, e)))?;
+    let id: [u32; 8] = digest.as_words().try_into()?;
+    if models::image_id_hex(id) != image_id {
+        return Err(invalid(format!("guest ELF does not match image {}", image_id)));
+    }
+    Ok(Circuit { name, id, elf: Some(elf.as_slice()) })
+}
+
+/// Verify every receipt of a bundle and only then store them in one transaction, a bundle is imported whole
+/// or not at all. ELFs of unknown circuits are stored first, they are harmless on their own.
+pub async fn import(db: &dyn Storage, bundle: &[u8], cfg: &config::DB, limit: u64) -> Result<ImportReport> {
+    let files = read_files(bundle, limit)?;
+    let manifest: Manifest = serde_json::from_slice(files.get(MANIFEST).ok_or_else(|| invalid("no manifest".to_string()))?)
+        .map_err(|e| invalid(e.to_string()))?;
+    if manifest.version != VERSION {
+        return Err(invalid(format!("unsupported version {}", manifest.version)));
+    }
+
+    let mut verified = Vec::new();
+    for entry in &manifest.receipts {
+        uuid::Uuid::parse_str(&entry.uuid).map_err(|_| invalid(format!("receipt UUID {} is malformed", entry.uuid)))?;
+        let bytes = files.get(&receipt_path(&entry.uuid)).ok_or_else(|| invalid(format!("receipt {} is missing", entry.uuid)))?;
+        let receipt: Receipt = bincode::deserialize(bytes).map_err(|e| invalid(format!("receipt {}: {}", entry.uuid, e)))?;
+        let circuit = circuit_of(entry, &files)?;
+        receipt.verify(circuit.id).map_err(|e| {
+            metrics::VERIFICATION_FAILURES.with_label_values(&["bundle"]).inc();
+            ApiError::Verification(format!("Invalid bundle: receipt {} failed verification: {}", entry.uuid, e))
+        })?;
+        verified.push((entry, receipt, circuit));
+    }
+
+    let mut report = ImportReport::default();
+    let mut seen = HashSet::new();
+    let mut prepared = Vec::new();
+    for (entry, receipt, circuit) in verified {
+        if !seen.insert(&entry.uuid) || db.receipt_exists(&entry.uuid).await? || db.is_tombstoned(&entry.uuid).await? {
+            report.skipped += 1;
+            continue;
+        }
+        if let Some(elf) = circuit.elf {
+            db.insert_elf(&models::image_id_hex(circuit.id), circuit.name, elf).await?;
+        }
+        let meta = super::receipts::metadata(&receipt, circuit.name, circuit.id, entry.metadata.clone());
+        prepared.push((entry, prepare(db, &receipt, cfg).await?, meta));
+    }
+
+    let receipts: Vec<_> = prepared.iter().map(|(entry, receipt, meta)| receipt.to_new(&entry.uuid, meta)).collect();
+    db.insert_receipts(&receipts).await?;
+    report.imported = receipts.len();
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing;
+
+    const LIMIT: u64 = 1 << 30;
+
+    /// A Hypotenuse receipt, proven in dev mode so it takes no time.
+    fn prove(x: u32, y: u32) -> Receipt {
+        std::env::set_var("RISC0_DEV_MODE", "1");
+        let inputs = models::CircuitInputs::Hypotenuse(models::Hypotenuse { x, y });
+        crate::risc0::executor::execute_circuit(&inputs).unwrap().receipt
+    }
+
+    async fn seeded() -> (testing::TempStore, Vec<String>) {
+        let store = testing::sqlite().await;
+        let (_, id) = models::CIRCUITS[0];
+        let mut uuids = Vec::new();
+        for (x, y) in [(3, 4), (5, 12)] {
+            let receipt = prove(x, y);
+            let meta = crate::risc0::receipts::metadata(&receipt, "Hypotenuse", id, ReceiptMetadata::default());
+            let uuid = crate::db::insert_receipt(&*store, receipt, None, &meta, &config::Config::default().db).await.unwrap();
+            uuids.push(uuid.to_string());
+        }
+        (store, uuids)
+    }
+
+    fn pack(files: &HashMap<String, Vec<u8>>) -> Vec<u8> {
+        let mut archive = tar::Builder::new(Vec::new());
+        for (path, bytes) in files {
+            append(&mut archive, path, bytes).unwrap();
+        }
+        zstd::encode_all(archive.into_inner().unwrap().as_slice(), ZSTD_LEVEL).unwrap()
+    }
+
+    async fn import_into_empty(bundle: &[u8], limit: u64) -> (testing::TempStore, Result<ImportReport>) {
+        let store = testing::sqlite().await;
+        let report = import(&*store, bundle, &config::Config::default().db, limit).await;
+        (store, report)
+    }
+
+    #[tokio::test]
+    async fn bundles_round_trip() {
+        let (source, uuids) = seeded().await;
+        let bundle = export(&*source, &Selection::default(), "10.0.0.1:8080").await.unwrap();
+
+        let (target, report) = import_into_empty(&bundle, LIMIT).await;
+        let report = report.unwrap();
+        assert_eq!((report.imported, report.skipped), (2, 0));
+        for uuid in &uuids {
+            let original: Receipt = retrieve(&*source, uuid).await.unwrap().unwrap();
+            let copy: Receipt = retrieve(&*target, uuid).await.unwrap().unwrap();
+            assert_eq!(copy.journal.bytes, original.journal.bytes);
+            let meta = target.receipt_metadata(uuid).await.unwrap().unwrap();
+            assert_eq!(meta.circuit.as_deref(), Some("Hypotenuse"));
+        }
+
+        let report = import(&*target, &bundle, &config::Config::default().db, LIMIT).await.unwrap();
+        assert_eq!((report.imported, report.skipped), (0, 2));
+    }
+
+    #[tokio::test]
+    async fn a_tampered_receipt_rejects_the_whole_bundle() {
+        let (source, uuids) = seeded().await;
+        let mut files = read_files(&export(&*source, &Selection::default(), "10.0.0.1:8080").await.unwrap(), LIMIT).unwrap();
+        let path = receipt_path(&uuids[1]);
+        let mut receipt: Receipt = bincode::deserialize(&files[&path]).unwrap();
+        receipt.journal.bytes = 99u32.to_le_bytes().to_vec();
+        files.insert(path, bincode::serialize(&receipt).unwrap());
+
+        let (target, report) = import_into_empty(&pack(&files), LIMIT).await;
+        assert!(matches!(report.unwrap_err().downcast_ref::<ApiError>(), Some(ApiError::Verification(_))));
+        assert_eq!(target.receipt_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn an_elf_has_to_match_its_image() {
+        let (source, _) = seeded().await;
+        let mut files = read_files(&export(&*source, &Selection::default(), "10.0.0.1:8080").await.unwrap(), LIMIT).unwrap();
+        let mut manifest: Manifest = serde_json::from_slice(&files[MANIFEST]).unwrap();
+        let unknown = "00".repeat(32);
+        for entry in &mut manifest.receipts {
+            entry.metadata.image_id = Some(unknown.clone());
+            entry.metadata.circuit = Some("Lookalike".to_string());
+        }
+        files.insert(MANIFEST.to_string(), serde_json::to_vec(&manifest).unwrap());
+        files.insert(elf_path(&unknown), models::circuit_elf("Hypotenuse").unwrap().to_vec());
+
+        let (target, report) = import_into_empty(&pack(&files), LIMIT).await;
+        let err = report.unwrap_err().to_string();
+        assert!(err.contains("does not match"), "{}", err);
+        assert_eq!(target.receipt_count().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn bundles_over_the_limit_are_refused() {
+        let (source, _) = seeded().await;
+        let bundle = export(&*source, &Selection::default(), "10.0.0.1:8080").await.unwrap();
+        let (target, report) = import_into_empty(&bundle, 1024).await;
+        assert!(matches!(report.unwrap_err().downcast_ref::<ApiError>(), Some(ApiError::PayloadTooLarge(_))));
+        assert_eq!(target.receipt_count().await.unwrap(), 0);
+    }
+}
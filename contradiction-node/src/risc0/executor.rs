@@ -1,22 +1,35 @@
-use risc0_zkvm::{default_prover, ExecutorEnv};
+use risc0_zkvm::{default_executor, default_prover, ExecutorEnv};
 use risc0_zkvm::Receipt;
 use anyhow::{Result, Error};
 use crate::risc0::models;
 
-pub fn execute_circuit<T: models::IntoExecutorEnv>(input: &T) -> Result<Receipt> {
-    let mut env_builder = ExecutorEnv::builder();
+pub struct Proof {
+    pub receipt: Receipt,
+    pub proving_ms: i64,
+    pub cycles: i64,
+}
 
-    let (elf, id) = models::fetch_circuit(input);
-    
+fn build_env<T: models::IntoExecutorEnv>(input: &T) -> Result<ExecutorEnv<'static>> {
+    let mut env_builder = ExecutorEnv::builder();
     match input.write_to_env(&mut env_builder) {
         Ok(_) => {},
         Err(e) => return Err(Error::msg(e.to_string())),
     }
-    
-    let env = env_builder.build()?;
+    env_builder.build()
+}
+
+pub fn execute_circuit(input: &models::CircuitInputs) -> Result<Proof> {
+    let (elf, id) = input.image();
+
+    // executing without proving is cheap and tells us how many cycles the proof covers
+    let session = default_executor().execute(build_env(input)?, elf)?;
+    let cycles = session.segments.iter().map(|s| s.cycles as i64).sum();
+
+    let started = std::time::Instant::now();
     let prover = default_prover();
-    
-    let receipt = prover.prove(env, elf)?;
+    let receipt = prover.prove(build_env(input)?, elf)?;
+    let proving_ms = started.elapsed().as_millis() as i64;
+
     receipt.verify(id)?;
-    Ok(receipt)
-}
\ No newline at end of file
+    Ok(Proof { receipt, proving_ms, cycles })
+}
@@ -0,0 +1,79 @@
+use tokio::sync::Notify;
+use anyhow::Result;
+use tracing::Instrument;
+use crate::{error::ApiError, handler::AppState, models as responses, risc0::{models, routes}, storage::Storage};
+
+pub const QUEUED: &str = "queued";
+pub const DONE: &str = "done";
+pub const FAILED: &str = "failed";
+
+const POLL_INTERVAL: u64 = 5;
+
+lazy_static! {
+    static ref WAKE: Notify = Notify::new();
+}
+
+/// Queue a circuit to be proven, the receipt will be stored under the returned job's UUID.
+pub async fn submit(db: &dyn Storage, inputs: &models::CircuitInputs, submitter: String) -> Result<responses::Job> {
+    let max_queued = crate::config::limits().max_queued_jobs;
+    if db.queued_jobs().await? >= max_queued {
+        return Err(ApiError::RateLimited { message: format!("Job queue is full, {} jobs are waiting", max_queued), retry_after: 60 }.into());
+    }
+    let now = chrono::Utc::now().timestamp();
+    let job = responses::Job {
+        uuid: uuid::Uuid::new_v4().to_string(),
+        circuit: inputs.name().to_string(),
+        status: QUEUED.to_string(),
+        error: None,
+        submitter: Some(submitter),
+        created_at: now,
+        updated_at: now,
+    };
+    db.insert_job(&job, &serde_json::to_string(inputs)?).await?;
+    WAKE.notify_one();
+    Ok(job)
+}
+
+async fn run_job(state: &AppState, job: &responses::Job, inputs: &str) -> Result<()> {
+    // proven before the node went down, only the status update was lost
+    if state.db.receipt_exists(&job.uuid).await? {
+        return Ok(());
+    }
+    let inputs: models::CircuitInputs = serde_json::from_str(inputs)?;
+    routes::prove(state, inputs, job.submitter.clone().unwrap_or_default(), Some(job.uuid.clone())).await?;
+    Ok(())
+}
+
+/// Prove queued jobs one at a time, proving takes all the CPU there is.
+pub async fn run_worker(state: AppState) {
+    match state.db.requeue_jobs(chrono::Utc::now().timestamp()).await {
+        Ok(0) => {},
+        Ok(requeued) => log::info!("Queued {} interrupted jobs again.", requeued),
+        Err(e) => log::error!("Failed to queue interrupted jobs again: {}", e),
+    }
+    loop {
+        match state.db.claim_job(chrono::Utc::now().timestamp()).await {
+            Ok(Some((job, inputs))) => {
+                let span = tracing::info_span!("job", job = %job.uuid, circuit = %job.circuit);
+                span.in_scope(|| log::info!("Proving {} for job {}.", job.circuit, job.uuid));
+                let (status, error) = match run_job(&state, &job, &inputs).instrument(span).await {
+                    Ok(()) => (DONE, None),
+                    Err(e) => {
+                        log::warn!("Job {} failed: {}", job.uuid, e);
+                        (FAILED, Some(e.to_string()))
+                    }
+                };
+                if let Err(e) = state.db.finish_job(&job.uuid, status, error.as_deref(), chrono::Utc::now().timestamp()).await {
+                    log::error!("Failed to update job {}: {}", job.uuid, e);
+                }
+                continue;
+            },
+            Ok(None) => {},
+            Err(e) => log::error!("Failed to claim a job: {}", e),
+        }
+        tokio::select! {
+            _ = WAKE.notified() => {},
+            _ = tokio::time::sleep(tokio::time::Duration::from_secs(POLL_INTERVAL)) => {},
+        }
+    }
+}
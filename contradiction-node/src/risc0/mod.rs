@@ -1,3 +1,7 @@
+pub mod bundle;
 pub mod executor;
+pub mod jobs;
 pub mod models;
-pub mod routes;
\ No newline at end of file
+pub mod receipts;
+pub mod routes;
+pub mod sync;
\ No newline at end of file
@@ -1,18 +1,14 @@
-use risc0_zkvm::{ExecutorEnvBuilder, Receipt};
-use serde::{Serialize, Deserialize};
-use contradiction_risc0_methods as methods;
+use risc0_zkvm::{ExecutorEnvBuilder, InnerReceipt, Receipt};
 use anyhow::Result;
+use crate::{error::ApiError, models::ReceiptMetadata};
+
+pub use contradiction_api::circuits::{circuit_elf, image_id_hex, CircuitInputs, Hypotenuse, LinearPolynomial, CIRCUITS};
+pub use contradiction_api::models::IncomingReceipt;
 
 pub trait IntoExecutorEnv {
     fn write_to_env(&self, builder: &mut ExecutorEnvBuilder) -> Result<()>;
 }
 
-#[derive(Debug, Deserialize, Serialize,)]
-pub enum CircuitInputs {
-    Hypotenuse(Hypotenuse),
-    LinearPolynomial(LinearPolynomial),
-}
-
 impl IntoExecutorEnv for CircuitInputs {
     fn write_to_env(&self, builder: &mut ExecutorEnvBuilder) -> Result<()> {
         match self {
@@ -22,12 +18,6 @@ impl IntoExecutorEnv for CircuitInputs {
     }
 }
 
-#[derive(Serialize, Deserialize, Debug)]
-pub struct Hypotenuse {
-    x: u32,
-    y: u32,
-}
-
 impl IntoExecutorEnv for Hypotenuse {
     fn write_to_env(&self, builder: &mut ExecutorEnvBuilder) -> Result<()> {
         builder.write(&self.x)?;
@@ -36,13 +26,6 @@ impl IntoExecutorEnv for Hypotenuse {
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct LinearPolynomial {
-    a: u32,
-    b: u32,
-    c: u32,
-}
-
 impl IntoExecutorEnv for LinearPolynomial {
     fn write_to_env(&self, builder: &mut ExecutorEnvBuilder) -> Result<()> {
         builder.write(&self.a)?;
@@ -52,17 +35,47 @@ impl IntoExecutorEnv for LinearPolynomial {
     }
 }
 
-pub fn fetch_circuit<T: IntoExecutorEnv>(input: &T) -> (&'static [u8], [u32; 8]) {
-    match input {
-        Hypotenuse => {
-            (methods::HYPOTENUSE_ELF, methods::HYPOTENUSE_ID)
-        }
+/// Name and image ID of the known circuit with the given hex image ID.
+pub fn known_image(image_id: &str) -> Option<(&'static str, [u32; 8])> {
+    CIRCUITS.iter().find(|(_, id)| image_id_hex(*id) == image_id).copied()
+}
+
+pub fn journal_hash(receipt: &Receipt) -> String {
+    crate::attestation::journal_hash(&receipt.journal.bytes)
+}
+
+/// Find the circuit (name and image ID) a receipt was produced by, verifying it against each known image ID.
+pub fn identify_circuit(receipt: &Receipt) -> Result<(&'static str, [u32; 8])> {
+    CIRCUITS
+        .iter()
+        .find(|(_, id)| receipt.verify(*id).is_ok())
+        .copied()
+        .ok_or_else(|| ApiError::Verification("Receipt does not verify against any known circuit".to_string()).into())
+}
+
+pub fn proof_kind(receipt: &Receipt) -> &'static str {
+    match receipt.inner {
+        InnerReceipt::Composite(_) => "composite",
+        InnerReceipt::Compact(_) => "compact",
+        InnerReceipt::Succinct(_) => "succinct",
+        InnerReceipt::Fake { .. } => "fake",
+        _ => "unknown",
     }
 }
 
-#[derive(Debug, Deserialize, Serialize)]
-pub struct IncomingReceipt {
-    pub uuid: String,
-    pub circuit: CircuitInputs,
-    pub receipt: Receipt,
-}
\ No newline at end of file
+/// Metadata that can be read off a verified receipt, anything else is left for the caller to fill in.
+pub fn receipt_metadata(receipt: &Receipt, circuit: &str, image_id: [u32; 8]) -> ReceiptMetadata {
+    ReceiptMetadata {
+        circuit: Some(circuit.to_string()),
+        image_id: Some(image_id_hex(image_id)),
+        journal: Some(receipt.journal.bytes.clone()),
+        proof_kind: Some(proof_kind(receipt).to_string()),
+        ..Default::default()
+    }
+}
+
+/// Metadata for a receipt copied from a peer, which doesn't tell us who proved it.
+pub fn copied_metadata(receipt: &Receipt) -> Result<ReceiptMetadata> {
+    let (circuit, id) = identify_circuit(receipt)?;
+    Ok(receipt_metadata(receipt, circuit, id))
+}
@@ -0,0 +1,16 @@
+use risc0_zkvm::Receipt;
+use crate::{models::ReceiptMetadata, risc0::models};
+
+/// Metadata to store a verified receipt with. We describe the receipt ourselves, only what it can't tell
+/// (who proved it for whom, when and at what cost, and its inputs) is taken from `sent`.
+pub fn metadata(receipt: &Receipt, circuit: &str, image_id: [u32; 8], sent: ReceiptMetadata) -> ReceiptMetadata {
+    ReceiptMetadata {
+        prover_node: sent.prover_node,
+        submitter: sent.submitter,
+        input_hash: sent.input_hash,
+        created_at: sent.created_at,
+        proving_ms: sent.proving_ms,
+        cycles: sent.cycles,
+        ..models::receipt_metadata(receipt, circuit, image_id)
+    }
+}
@@ -1,56 +1,84 @@
-use bytes::{Buf, Bytes};
-use http_body_util::BodyExt;
-use std::collections::HashMap;
-use crate::{risc0::models, handler::full, risc0::executor::execute_circuit, CLIENT};
+use bytes::Bytes;
+use crate::{risc0::{bundle, jobs, models, receipts}, handler::{full, param, parse_param, query_param, read_body, read_json, AppState}, router::Router, risc0::executor::execute_circuit, error::ApiError, metrics, outbox, telemetry, placement, attestation, consensus, config, storage::Storage};
+use std::net::SocketAddr;
+use ed25519_dalek::SigningKey;
 use risc0_zkvm::Receipt;
-use hyper::{body::Incoming as IncomingBody, header, Method, Request, Response, StatusCode};
-use sqlx::sqlite::SqlitePool;
+use hyper::{body::Incoming as IncomingBody, header, Request, Response, StatusCode};
 use anyhow::Result;
 use crate::{models as responses, db::insert_receipt as insert, db::retrieve_receipt as retrieve};
 
 type BoxBody = http_body_util::combinators::BoxBody<Bytes, hyper::Error>;
 
-async fn do_compute(req: Request<IncomingBody>, pool: SqlitePool) -> Result<Response<BoxBody>> {
-    // deserialize circuit inputs
-    let inputs: models::CircuitInputs = serde_json::from_reader(req.collect().await?.aggregate().reader())?;
+const OCTET_STREAM: &str = "application/octet-stream";
+
+/// Prove a circuit, store the receipt and hand it to the cluster, returning the receipt's UUID.
+pub async fn prove(state: &AppState, inputs: models::CircuitInputs, submitter: String, uuid: Option<String>) -> Result<uuid::Uuid> {
+    let (db, cfg) = (state.db.as_ref(), &state.config);
+
+    // execute and prove, off the async workers as it takes a while
+    let span = tracing::Span::current();
+    let (proof, inputs) = tokio::task::spawn_blocking(move || span.in_scope(|| execute_circuit(&inputs).map(|proof| (proof, inputs)))).await??;
+    let receipt: Receipt = proof.receipt;
+    let id = inputs.image().1;
+    metrics::PROVING_DURATION.with_label_values(&[inputs.name()]).observe(proof.proving_ms as f64 / 1000.0);
+    metrics::PROVING_CYCLES.with_label_values(&[inputs.name()]).observe(proof.cycles as f64);
+    let meta = receipts::metadata(&receipt, inputs.name(), id, responses::ReceiptMetadata {
+        prover_node: Some(cfg.node_id()),
+        submitter: Some(submitter.clone()),
+        input_hash: Some(inputs.hash()?),
+        created_at: Some(chrono::Utc::now().timestamp()),
+        proving_ms: Some(proof.proving_ms),
+        cycles: Some(proof.cycles),
+        ..Default::default()
+    });
 
-    // execute and prove
-    let receipt: Receipt = execute_circuit(&inputs)?;
-    
     // insert receipt into database
-    let uuid = insert(&pool, receipt.clone(), None).await?;
+    let uuid = insert(db, receipt.clone(), uuid, &meta, &cfg.db).await?;
 
-    let payload = responses::DefaultResponse {status_code: 201, text: uuid.to_string() };
+    // the receipt was verified right after proving, count ourselves as the first attestation
+    let image_id = models::image_id_hex(id);
+    let own = attestation::attest(&state.key, &cfg.node_id(), &uuid.to_string(), &image_id, &models::journal_hash(&receipt));
+    db.insert_attestation(&own).await?;
 
-    // send receipt to other nodes
-    let node_payload = models::IncomingReceipt {uuid: uuid.to_string(), circuit: inputs, receipt: receipt};
-    let nodes = sqlx::query_as::<_, responses::Node>(
-        "SELECT address, port FROM nodes"
-    )
-    .fetch_all(&pool)
-    .await?;
-    for node in nodes {
-        let url = format!("http://{}:{}/api/save-compute", node.address, node.port);
-        let payload = serde_json::to_string(&node_payload)?;
-
-        let response = CLIENT.post(url)
-            .header("Content-Type", "application/json")
-            .body(payload.clone()) // Clone payload for each request
-            .send()
-            .await;
-
-        match response {
-            Ok(resp) => {
-                if !resp.status().is_success() {
-                    log::warn!("Failed to send receipt to node. URL: {}, Status: {}", resp.url(), resp.status());
-                }
-            },
-            Err(e) => {
-                log::warn!("Error sending receipt to node: {}", e);
-            }
-        }
+    // have the cluster agree on it, elections may take a while so don't hold up the response
+    if let Some(consensus) = &state.consensus {
+        let proposal = consensus::Proposal {
+            uuid: uuid.to_string(),
+            image_id,
+            journal_hash: own.journal_hash.clone(),
+            submitter,
+        };
+        tokio::spawn(consensus.clone().submit(proposal));
     }
 
+    // queue the receipt for delivery to other nodes
+    let node_payload = models::IncomingReceipt {uuid: uuid.to_string(), circuit: inputs, receipt, metadata: Some(meta)};
+    let nodes = placement::targets(&uuid.to_string(), db.nodes().await?, &cfg.node_id(), cfg.replication.factor);
+    outbox::enqueue(db, &uuid.to_string(), "/v1/receipts", &serde_json::to_string(&node_payload)?, &nodes).await?;
+
+    Ok(uuid)
+}
+
+fn submitter(req: &Request<IncomingBody>) -> String {
+    req.extensions()
+        .get::<SocketAddr>()
+        .map(|addr| addr.ip().to_string())
+        .unwrap_or_default()
+}
+
+#[utoipa::path(post, path = "/v1/compute", tag = "proving", request_body = models::CircuitInputs, responses(
+    (status = 201, description = "Proven and stored, `text` is the receipt's UUID", body = responses::DefaultResponse),
+    (status = 400, description = "Unknown circuit or malformed inputs", body = responses::Problem, content_type = "application/problem+json"),
+))]
+async fn do_compute(req: Request<IncomingBody>, state: &AppState) -> Result<Response<BoxBody>> {
+    let submitter = submitter(&req);
+
+    // deserialize circuit inputs
+    let inputs: models::CircuitInputs = read_json(req).await?;
+    let uuid = prove(state, inputs, submitter, None).await?;
+
+    let payload = responses::DefaultResponse {status_code: 201, text: uuid.to_string() };
+
     // Use the ? operator to handle the Result returned by Response::builder()
     let response = Response::builder()
         .status(StatusCode::CREATED)
@@ -60,63 +88,230 @@ async fn do_compute(req: Request<IncomingBody>, pool: SqlitePool) -> Result<Resp
     Ok(response)
 }
 
-async fn fetch_compute(req: Request<IncomingBody>, pool: SqlitePool) -> Result<Response<BoxBody>> {
-    // fetch UUID from query string
-    let params: HashMap<String, String> = req
-        .uri()
-        .query()
-        .map(|v| {
-            url::form_urlencoded::parse(v.as_bytes())
-                .into_owned()
-                .collect()
-        })
-        .unwrap_or_else(HashMap::new);
-    let uuid: &String = match params.contains_key("uuid") {
-        true => params.get("uuid").unwrap(),
-        false => return Err(anyhow::anyhow!("No/malformed query string"))
-    };
-    let receipt: Receipt = retrieve(&pool, uuid).await?;
-    let payload = responses::DefaultResponse {status_code: 200, text: serde_json::to_string(&receipt)? };
+#[utoipa::path(post, path = "/v1/jobs", tag = "proving", request_body = models::CircuitInputs, responses(
+    (status = 202, description = "Queued, the Location header has the job's URL", body = responses::Job),
+    (status = 400, description = "Unknown circuit or malformed inputs", body = responses::Problem, content_type = "application/problem+json"),
+    (status = 429, description = "Job queue is full, retry after the Retry-After header's seconds", body = responses::Problem, content_type = "application/problem+json"),
+))]
+async fn submit_job(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let submitter = submitter(&req);
+    let inputs: models::CircuitInputs = read_json(req).await?;
+    let payload = jobs::submit(db, &inputs, submitter).await?;
+
+    let response = Response::builder()
+        .status(StatusCode::ACCEPTED)
+        .header(header::CONTENT_TYPE, "application/json")
+        .header(header::LOCATION, format!("/v1/jobs/{}", payload.uuid))
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/jobs/{uuid}", tag = "proving", params(("uuid" = String, Path, description = "Job UUID, also the receipt's")), responses(
+    (status = 200, description = "The job, its status is queued, running, done or failed", body = responses::Job),
+    (status = 404, description = "Unknown job", body = responses::Problem, content_type = "application/problem+json"),
+))]
+async fn job_status(req: Request<IncomingBody>, db: &dyn Storage) -> Result<Response<BoxBody>> {
+    let uuid = param(&req, "uuid")?;
+    let payload = db.job(&uuid)
+        .await?
+        .ok_or_else(|| ApiError::NotFound("Job not found.".to_string()))?;
     let response = Response::builder()
         .status(StatusCode::OK)
         .header(header::CONTENT_TYPE, "application/json")
         .body(full(serde_json::to_vec(&payload)?))?;
+
     Ok(response)
 }
 
-async fn save_compute(req: Request<IncomingBody>, pool: SqlitePool) -> Result<Response<BoxBody>> {
-    let incoming: models::IncomingReceipt = serde_json::from_reader(req.collect().await?.aggregate().reader())?;
-    let id = models::fetch_circuit(&incoming.circuit).1;
-    match incoming.receipt.verify(id) {
-        Ok(_) => {
-            let _ = insert(&pool, incoming.receipt, Some(incoming.uuid.clone())).await;
-            let payload = responses::DefaultResponse {status_code: 201, text: incoming.uuid.to_string() };
-            let response = Response::builder()
-                .status(StatusCode::CREATED)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(full(serde_json::to_vec(&payload)?))?;
-
-            Ok(response)
-        },
-        Err(_) => {
-            let payload =  responses::DefaultResponse {status_code: 422, text: "Circuit receipt failed verifictation / an error during verification arose.".to_string()};
-            let response = Response::builder()
-                .status(StatusCode::UNPROCESSABLE_ENTITY)
-                .header(header::CONTENT_TYPE, "application/json")
-                .body(full(serde_json::to_vec(&payload)?))?;
-            Ok(response)
+/// Ask a single peer for a receipt and verify whatever it returns, along with the circuit it verified against.
+async fn ask_peer(node: responses::Node, uuid: String, timeout: u64) -> Result<(String, Receipt, (&'static str, [u32; 8]))> {
+    // fetching locally keeps the peer from asking around on our behalf, the other peers are asked at once anyway
+    let client = telemetry::propagate(crate::peer(&placement::node_id(&node.address, node.port)), &uuid)
+        .retries(0)
+        .timeout(std::time::Duration::from_secs(timeout));
+    let receipt = client.fetch_local(&uuid)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("{}:{} does not hold receipt {}", node.address, node.port, uuid))?
+        .receipt;
+    let circuit = models::identify_circuit(&receipt).inspect_err(|_| metrics::VERIFICATION_FAILURES.with_label_values(&["lookup"]).inc())?;
+    log::debug!("Receipt {} ({}) located on {}:{}.", uuid, circuit.0, node.address, node.port);
+    Ok((placement::node_id(&node.address, node.port), receipt, circuit))
+}
+
+/// Ask every peer for a receipt at once, the first verified copy wins.
+async fn locate_receipt(db: &dyn Storage, cfg: &config::Config, uuid: &str) -> Result<Option<(String, Receipt)>> {
+    let nodes = placement::lookup_order(uuid, db.nodes().await?, &cfg.node_id());
+    if nodes.is_empty() {
+        return Ok(None);
+    }
+
+    let requests = nodes
+        .into_iter()
+        .map(|node| Box::pin(ask_peer(node, uuid.to_string(), cfg.lookup.timeout)));
+    let (node, receipt, (circuit, id)) = match futures::future::select_ok(requests).await {
+        Ok((found, _)) => found,
+        Err(e) => {
+            log::debug!("No peer could serve receipt {}, last error: {}", uuid, e);
+            return Ok(None);
         }
+    };
+
+    if cfg.lookup.cache {
+        // already verified by ask_peer
+        let meta = models::receipt_metadata(&receipt, circuit, id);
+        if let Err(e) = insert(db, receipt.clone(), Some(uuid.to_string()), &meta, &cfg.db).await {
+            log::warn!("Failed to cache receipt {} from {}: {}", uuid, node, e);
+        }
+    }
+    Ok(Some((node, receipt)))
+}
+
+#[utoipa::path(get, path = "/v1/receipts/{uuid}/proof", tag = "receipts", params(
+    ("uuid" = String, Path, description = "Receipt UUID"),
+    ("local" = Option<bool>, Query, description = "Don't ask peers when the receipt isn't stored here"),
+), responses(
+    (status = 200, description = "The receipt, bincode encoded with X-Served-By, X-Attested-By and X-Quorum headers when `Accept: application/octet-stream`", body = responses::FetchResponse),
+    (status = 404, description = "No node could serve the receipt", body = responses::Problem, content_type = "application/problem+json"),
+))]
+async fn fetch_compute(req: Request<IncomingBody>, db: &dyn Storage, cfg: &config::Config) -> Result<Response<BoxBody>> {
+    // fetch UUID from query string
+    let uuid = param(&req, "uuid")?;
+    let local_only = query_param(&req, "local").is_ok_and(|v| v == "true");
+    let binary = req.headers()
+        .get(header::ACCEPT)
+        .and_then(|v| v.to_str().ok())
+        .is_some_and(|v| v.contains(OCTET_STREAM));
+
+    let found = match retrieve::<Receipt>(db, &uuid).await? {
+        Some(receipt) => Some((cfg.node_id(), receipt)),
+        None if !local_only => locate_receipt(db, cfg, &uuid).await?,
+        None => None,
+    };
+
+    let (served_by, receipt) = found.ok_or_else(|| ApiError::NotFound("Receipt not found.".to_string()))?;
+    let attested_by: Vec<String> = db.attestations(&uuid)
+        .await?
+        .into_iter()
+        .map(|a| a.node)
+        .collect();
+    let quorum = attested_by.len() >= cfg.quorum.threshold;
+    if binary {
+        return Ok(Response::builder()
+            .status(StatusCode::OK)
+            .header(header::CONTENT_TYPE, OCTET_STREAM)
+            .header("X-Served-By", served_by)
+            .header("X-Attested-By", attested_by.join(","))
+            .header("X-Quorum", quorum.to_string())
+            .body(full(bincode::serialize(&receipt)?))?);
     }
+    let payload = responses::FetchResponse {
+        status_code: 200,
+        text: serde_json::to_string(&receipt)?,
+        served_by,
+        attested_by,
+        quorum,
+    };
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+    Ok(response)
 }
 
-pub async fn route_handler(req: Request<IncomingBody>, pool: SqlitePool) -> Result<Response<BoxBody>> {
-    log::debug!("risc0 route handler checking request");
-    match (req.method(), req.uri().path()) {
-        (&Method::POST, "/api/do-compute") => do_compute(req, pool).await,
-        (&Method::GET, "/api/fetch-compute") => fetch_compute(req, pool).await,
-        (&Method::POST, "/api/save-compute") => save_compute(req, pool).await,
-        _ => {
-            Err(anyhow::anyhow!("No matching GET condition"))
-        },
+#[utoipa::path(post, path = "/v1/receipts", tag = "receipts", request_body = models::IncomingReceipt, responses(
+    (status = 201, description = "Verified and stored, with this node's signed attestation", body = responses::AttestedResponse),
+    (status = 422, description = "Receipt failed verification", body = responses::Problem, content_type = "application/problem+json"),
+))]
+async fn save_compute(req: Request<IncomingBody>, db: &dyn Storage, cfg: &config::Config, key: &SigningKey) -> Result<Response<BoxBody>> {
+    let incoming: models::IncomingReceipt = read_json(req).await?;
+    let id = incoming.circuit.image().1;
+    incoming.receipt
+        .verify(id)
+        .map_err(|e| {
+            metrics::VERIFICATION_FAILURES.with_label_values(&["receipt"]).inc();
+            ApiError::Verification(format!("Receipt failed verification: {}", e))
+        })?;
+
+    // let the sender know we verified it
+    let attestation = attestation::attest(key, &cfg.node_id(), &incoming.uuid, &models::image_id_hex(id), &models::journal_hash(&incoming.receipt));
+    // the inputs came along, so their hash is ours to take
+    let sent = responses::ReceiptMetadata { input_hash: Some(incoming.circuit.hash()?), ..incoming.metadata.unwrap_or_default() };
+    let meta = receipts::metadata(&incoming.receipt, incoming.circuit.name(), id, sent);
+    // a retried delivery finds the receipt stored already, anything else that fails is the sender's to retry
+    if !db.receipt_exists(&incoming.uuid).await? {
+        insert(db, incoming.receipt, Some(incoming.uuid.clone()), &meta, &cfg.db).await?;
     }
-}
\ No newline at end of file
+    let payload = responses::AttestedResponse {status_code: 201, text: incoming.uuid.to_string(), attestation };
+    let response = Response::builder()
+        .status(StatusCode::CREATED)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(get, path = "/v1/bundles", tag = "bundles", params(
+    ("uuid" = Option<String>, Query, description = "Comma separated receipt UUIDs, the filters below are ignored when given"),
+    ("circuit" = Option<String>, Query, description = "Circuit name"),
+    ("from" = Option<i64>, Query, description = "Unix time, `from <= created_at`"),
+    ("to" = Option<i64>, Query, description = "Unix time, `created_at < to`"),
+), responses(
+    (status = 200, description = "zstd compressed tar of the receipts and their guest ELFs", content_type = "application/zstd"),
+    (status = 404, description = "A listed receipt isn't stored here", body = responses::Problem, content_type = "application/problem+json"),
+))]
+async fn export_bundle(req: Request<IncomingBody>, db: &dyn Storage, cfg: &config::Config) -> Result<Response<BoxBody>> {
+    let param = |key| query_param(&req, key).ok();
+    let selection = bundle::Selection {
+        uuids: param("uuid").map(|v| v.split(',').map(str::to_string).collect()).unwrap_or_default(),
+        circuit: param("circuit"),
+        from: param("from").map(|v| parse_param("from", &v)).transpose()?,
+        to: param("to").map(|v| parse_param("to", &v)).transpose()?,
+    };
+    let payload = bundle::export(db, &selection, &cfg.node_id()).await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/zstd")
+        .header(header::CONTENT_DISPOSITION, "attachment; filename=\"receipts.tar.zst\"")
+        .body(full(payload))?;
+
+    Ok(response)
+}
+
+#[utoipa::path(post, path = "/v1/bundles", tag = "bundles", request_body(content = String, description = "A bundle as exported", content_type = "application/zstd"), responses(
+    (status = 200, description = "Receipts imported and skipped", body = responses::ImportReport),
+    (status = 400, description = "Malformed bundle", body = responses::Problem, content_type = "application/problem+json"),
+    (status = 422, description = "A receipt failed verification", body = responses::Problem, content_type = "application/problem+json"),
+))]
+async fn import_bundle(req: Request<IncomingBody>, db: &dyn Storage, cfg: &config::Config) -> Result<Response<BoxBody>> {
+    let body = read_body(req).await?;
+    let payload = bundle::import(db, &body, &cfg.db, config::limits().max_bundle).await?;
+
+    let response = Response::builder()
+        .status(StatusCode::OK)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(full(serde_json::to_vec(&payload)?))?;
+
+    Ok(response)
+}
+
+/// Routes that need the zkVM.
+pub fn register(router: Router) -> Router {
+    router
+        .post("/v1/compute", |req, state: AppState| async move { do_compute(req, &state).await })
+        .alias("/api/do-compute")
+        .post("/v1/jobs", |req, state: AppState| async move { submit_job(req, state.db.as_ref()).await })
+        .alias("/api/jobs")
+        .get("/v1/jobs/{uuid}", |req, state: AppState| async move { job_status(req, state.db.as_ref()).await })
+        .alias("/api/jobs")
+        .alias("/api/jobs/{uuid}")
+        .get("/v1/receipts/{uuid}/proof", |req, state: AppState| async move { fetch_compute(req, state.db.as_ref(), &state.config).await })
+        .alias("/api/fetch-compute")
+        .post("/v1/receipts", |req, state: AppState| async move { save_compute(req, state.db.as_ref(), &state.config, &state.key).await })
+        .alias("/api/save-compute")
+        .get("/v1/bundles", |req, state: AppState| async move { export_bundle(req, state.db.as_ref(), &state.config).await })
+        .alias("/api/export")
+        .post("/v1/bundles", |req, state: AppState| async move { import_bundle(req, state.db.as_ref(), &state.config).await })
+        .alias("/api/import")
+}
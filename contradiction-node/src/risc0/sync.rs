@@ -0,0 +1,105 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use anyhow::Result;
+use crate::{config, metrics, telemetry, models as responses, risc0::models, sync, placement, db::insert_receipt as insert, storage::Storage};
+
+/// Fetch, verify and store a receipt a peer holds and we are missing.
+async fn pull(db: &dyn Storage, client: &contradiction_api::Client, uuid: &str, cfg: &config::Config) -> Result<()> {
+    // only what the peer stores itself, it would otherwise go looking for the receipt on our behalf
+    let receipt = telemetry::propagate(client.clone(), uuid)
+        .fetch_local(uuid)
+        .await?
+        .ok_or_else(|| anyhow::anyhow!("Peer no longer has receipt {}", uuid))?
+        .receipt;
+
+    // Never store something we could not have verified ourselves
+    let meta = models::copied_metadata(&receipt).inspect_err(|_| {
+        metrics::VERIFICATION_FAILURES.with_label_values(&["sync"]).inc();
+    })?;
+    log::debug!("Pulled {} receipt {}.", meta.circuit.as_deref().unwrap_or_default(), uuid);
+    insert(db, receipt, Some(uuid.to_string()), &meta, &cfg.db).await?;
+    Ok(())
+}
+
+/// Compare receipt sets with a single peer and pull (at most `max_pull`) receipts we are missing
+/// and are meant to hold. Differing ranges are split until they are small enough to list.
+async fn sync_with(db: &dyn Storage, node: &responses::Node, peers: &[responses::Node], cfg: &config::Config) -> Result<usize> {
+    let peer = placement::node_id(&node.address, node.port);
+    let client = crate::peer(&peer);
+
+    let mut pulled = 0;
+    let mut prefixes = vec![String::new()];
+    while let Some(prefix) = prefixes.pop() {
+        let remote = client.sync_summary(&prefix).await?;
+        let local = sync::summary(db, &prefix).await?;
+
+        for range in sync::differing_ranges(&local, &remote) {
+            if sync::subdivide(range) {
+                prefixes.push(range.prefix.clone());
+                continue;
+            }
+            let remote_uuids = client.sync_range(&range.prefix).await?;
+            let local_uuids: HashSet<String> = sync::range_uuids(db, &range.prefix).await?.into_iter().collect();
+
+            let wanted = remote_uuids
+                .into_iter()
+                .filter(|u| !local_uuids.contains(u))
+                .filter(|u| placement::holds(u, peers, &cfg.node_id(), cfg.replication.factor));
+            for uuid in wanted {
+                if pulled >= cfg.sync.max_pull {
+                    return Ok(pulled);
+                }
+                // pruned here on purpose, peers with a longer retention still have it
+                if db.is_tombstoned(&uuid).await? {
+                    continue;
+                }
+                // one bad receipt must not keep the others from converging
+                match pull(db, &client, &uuid, cfg).await {
+                    Ok(()) => {
+                        metrics::SYNC_PULLED.with_label_values(&[&peer]).inc();
+                        pulled += 1;
+                    },
+                    Err(e) => {
+                        log::warn!("Failed to pull receipt {} from {}: {}", uuid, peer, e);
+                        metrics::SYNC_FAILED.with_label_values(&[&peer]).inc();
+                    }
+                }
+            }
+        }
+    }
+    Ok(pulled)
+}
+
+/// Every `sync.interval` seconds, reconcile receipts with the next peer in line.
+pub async fn run(db: Arc<dyn Storage>, cfg: config::Config) {
+    if !cfg.sync.enabled {
+        log::info!("Receipt sync is disabled.");
+        return;
+    }
+    let mut turn: usize = 0;
+    loop {
+        tokio::time::sleep(tokio::time::Duration::from_secs(cfg.sync.interval)).await;
+
+        let nodes = match db.nodes().await {
+            Ok(nodes) => nodes,
+            Err(e) => {
+                log::error!("Failed to fetch nodes for sync: {}", e);
+                continue;
+            }
+        };
+        if nodes.is_empty() {
+            continue;
+        }
+
+        // Rotate through the peers so every one of them is eventually compared with
+        let node = &nodes[turn % nodes.len()];
+        turn = turn.wrapping_add(1);
+        let result = sync_with(db.as_ref(), node, &nodes, &cfg).await;
+        metrics::SYNC_ROUNDS.with_label_values(&[if result.is_ok() { "ok" } else { "failed" }]).inc();
+        match result {
+            Ok(0) => log::debug!("Receipts in sync with {}:{}.", node.address, node.port),
+            Ok(pulled) => log::info!("Pulled {} missing receipts from {}:{}.", pulled, node.address, node.port),
+            Err(e) => log::warn!("Sync with {}:{} failed: {}", node.address, node.port, e),
+        }
+    }
+}
@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::Arc;
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use hyper::{body::Incoming as IncomingBody, header, Method, Request, Response, StatusCode};
+use anyhow::Result;
+use crate::{error::ApiError, handler::{full, AppState}};
+
+type BoxBody = http_body_util::combinators::BoxBody<Bytes, hyper::Error>;
+type Handler = Arc<dyn Fn(Request<IncomingBody>, AppState) -> BoxFuture<'static, Result<Response<BoxBody>>> + Send + Sync>;
+type Middleware = Arc<dyn Fn(Request<IncomingBody>, AppState, Next) -> BoxFuture<'static, Result<Response<BoxBody>>> + Send + Sync>;
+
+/// Values of the `{name}` segments of the matched route, in the request's extensions.
+#[derive(Clone, Debug, Default)]
+pub struct Params(HashMap<String, String>);
+
+/// The value of a `{name}` segment of the route the request matched.
+pub fn path_param<'a>(req: &'a Request<IncomingBody>, key: &str) -> Option<&'a str> {
+    req.extensions().get::<Params>()?.0.get(key).map(String::as_str)
+}
+
+enum Segment {
+    Static(String),
+    Param(String),
+}
+
+struct Route {
+    method: Method,
+    path: String,
+    segments: Vec<Segment>,
+    handler: Handler,
+    middleware: Vec<Middleware>,
+    /// Older path of another route
+    alias: bool,
+}
+
+fn segments(path: &str) -> Vec<Segment> {
+    path.split('/')
+        .skip(1)
+        .map(|s| match s.strip_prefix('{').and_then(|s| s.strip_suffix('}')) {
+            Some(name) => Segment::Param(name.to_string()),
+            None => Segment::Static(s.to_string()),
+        })
+        .collect()
+}
+
+impl Route {
+    fn matches(&self, path: &str) -> Option<Params> {
+        let parts: Vec<&str> = path.split('/').skip(1).collect();
+        if parts.len() != self.segments.len() {
+            return None;
+        }
+        let mut params = HashMap::new();
+        for (segment, part) in self.segments.iter().zip(parts) {
+            match segment {
+                Segment::Static(s) if s == part => {},
+                Segment::Param(name) if !part.is_empty() => {
+                    params.insert(name.clone(), part.to_string());
+                },
+                _ => return None,
+            }
+        }
+        Some(Params(params))
+    }
+}
+
+/// The rest of a route's middleware chain, ending in its handler.
+pub struct Next {
+    chain: Vec<Middleware>,
+    handler: Handler,
+}
+
+impl Next {
+    pub fn run(mut self, req: Request<IncomingBody>, state: AppState) -> BoxFuture<'static, Result<Response<BoxBody>>> {
+        if self.chain.is_empty() {
+            return (self.handler)(req, state);
+        }
+        let middleware = self.chain.remove(0);
+        middleware(req, state, self)
+    }
+}
+
+/// Routes requests by method and path, `{name}` segments match any single segment.
+///
+/// HEAD is answered by the GET route and OPTIONS with the methods a path allows, a path without a route
+/// for the method gets 405.
+#[derive(Default)]
+pub struct Router {
+    routes: Vec<Route>,
+}
+
+impl Router {
+    pub fn new() -> Router {
+        Router::default()
+    }
+
+    pub fn route<F, Fut>(mut self, method: Method, path: &str, handler: F) -> Router
+    where
+        F: Fn(Request<IncomingBody>, AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<BoxBody>>> + Send + 'static,
+    {
+        let handler: Handler = Arc::new(move |req, state| Box::pin(handler(req, state)));
+        self.routes.push(Route { method, path: path.to_string(), segments: segments(path), handler, middleware: Vec::new(), alias: false });
+        self
+    }
+
+    /// Method and path of every route but the aliases.
+    #[cfg(test)]
+    pub fn routes(&self) -> impl Iterator<Item = (&Method, &str)> {
+        self.routes.iter().filter(|route| !route.alias).map(|route| (&route.method, route.path.as_str()))
+    }
+
+    pub fn get<F, Fut>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(Request<IncomingBody>, AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<BoxBody>>> + Send + 'static,
+    {
+        self.route(Method::GET, path, handler)
+    }
+
+    pub fn post<F, Fut>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(Request<IncomingBody>, AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<BoxBody>>> + Send + 'static,
+    {
+        self.route(Method::POST, path, handler)
+    }
+
+    pub fn delete<F, Fut>(self, path: &str, handler: F) -> Router
+    where
+        F: Fn(Request<IncomingBody>, AppState) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<BoxBody>>> + Send + 'static,
+    {
+        self.route(Method::DELETE, path, handler)
+    }
+
+    /// Run the route registered last through a middleware, which decides whether and how to call the rest.
+    /// Middleware added first runs first.
+    pub fn with<F, Fut>(mut self, middleware: F) -> Router
+    where
+        F: Fn(Request<IncomingBody>, AppState, Next) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Response<BoxBody>>> + Send + 'static,
+    {
+        let route = self.routes.last_mut().expect("Middleware added before any route");
+        route.middleware.push(Arc::new(move |req, state, next| Box::pin(middleware(req, state, next))));
+        self
+    }
+
+    /// Serve the route registered last under an older path too, its responses pointing at the current one with
+    /// Deprecation and Link headers. Add the route's middleware before its aliases.
+    pub fn alias(mut self, path: &str) -> Router {
+        let route = self.routes.iter().rev().find(|r| !r.alias).expect("Alias added before any route");
+        let link = format!("<{}>; rel=\"successor-version\"", route.path);
+        let deprecated: Middleware = Arc::new(move |req, state, next: Next| {
+            let link = link.clone();
+            Box::pin(async move {
+                let mut response = next.run(req, state).await.unwrap_or_else(|e| ApiError::from(e).into_response());
+                response.headers_mut().insert("Deprecation", header::HeaderValue::from_static("true"));
+                response.headers_mut().insert(header::LINK, header::HeaderValue::from_str(&link)?);
+                Ok(response)
+            })
+        });
+        let alias = Route {
+            method: route.method.clone(),
+            path: path.to_string(),
+            segments: segments(path),
+            handler: route.handler.clone(),
+            middleware: std::iter::once(deprecated).chain(route.middleware.iter().cloned()).collect(),
+            alias: true,
+        };
+        self.routes.push(alias);
+        self
+    }
+
+    /// Methods with a route for the path, as in the Allow header.
+    fn allowed(&self, path: &str) -> Vec<Method> {
+        let mut allow: Vec<Method> = Vec::new();
+        for route in self.routes.iter().filter(|r| r.matches(path).is_some()) {
+            if route.method == Method::GET {
+                allow.push(Method::HEAD);
+            }
+            allow.push(route.method.clone());
+        }
+        if !allow.is_empty() {
+            allow.push(Method::OPTIONS);
+        }
+        let mut unique = Vec::new();
+        for method in allow {
+            if !unique.contains(&method) {
+                unique.push(method);
+            }
+        }
+        unique
+    }
+
+    fn find(&self, method: &Method, path: &str) -> Option<(&Route, Params)> {
+        // HEAD runs the GET route, hyper leaves out the body
+        let method = match method {
+            &Method::HEAD => &Method::GET,
+            method => method,
+        };
+        self.routes
+            .iter()
+            .filter(|route| route.method == *method)
+            .find_map(|route| route.matches(path).map(|params| (route, params)))
+    }
+
+    /// The path of the route a request would be dispatched to, as it was registered.
+    pub fn pattern(&self, method: &Method, path: &str) -> Option<&str> {
+        self.find(method, path).map(|(route, _)| route.path.as_str())
+    }
+
+    pub async fn dispatch(&self, mut req: Request<IncomingBody>, state: AppState) -> Result<Response<BoxBody>> {
+        let path = req.uri().path().to_string();
+        if let Some((route, params)) = self.find(req.method(), &path) {
+            req.extensions_mut().insert(params);
+            let next = Next { chain: route.middleware.clone(), handler: route.handler.clone() };
+            return next.run(req, state).await;
+        }
+
+        let allow = self.allowed(&path);
+        if allow.is_empty() {
+            return Err(ApiError::NotFound(format!("No route for {} {}", req.method(), path)).into());
+        }
+        let allow = allow.iter().map(Method::as_str).collect::<Vec<_>>().join(", ");
+        if req.method() == Method::OPTIONS {
+            return Ok(Response::builder()
+                .status(StatusCode::NO_CONTENT)
+                .header(header::ALLOW, allow)
+                .body(full(Bytes::new()))?);
+        }
+        Err(ApiError::MethodNotAllowed { method: req.method().to_string(), allow }.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::{server::conn::http1, service::service_fn};
+    use hyper_util::rt::TokioIo;
+    use crate::{config, handler::{self, handle_request}, storage::sqlite::SqliteStorage};
+
+    fn echo(req: Request<IncomingBody>, _: AppState) -> BoxFuture<'static, Result<Response<BoxBody>>> {
+        let body = format!("{} {}", req.method(), path_param(&req, "uuid").unwrap_or("-"));
+        Box::pin(async move { Ok(Response::new(full(body))) })
+    }
+
+    fn receipts() -> Router {
+        Router::new()
+            .get("/v1/receipts/{uuid}", echo)
+            .alias("/api/receipt/{uuid}")
+            .post("/v1/receipts", echo)
+            .post("/v1/receipts/{uuid}", echo)
+    }
+
+    async fn state() -> (AppState, std::path::PathBuf) {
+        let dir = std::env::temp_dir().join(format!("contradiction-router-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let cfg = config::DB { path: dir.join("node.db").display().to_string(), ..config::Config::default().db };
+        let state = AppState {
+            db: Arc::new(SqliteStorage::open(&cfg, None).await.unwrap()),
+            #[cfg(feature="risc0")]
+            config: Arc::new(config::Config::default()),
+            #[cfg(feature="risc0")]
+            key: Arc::new(ed25519_dalek::SigningKey::from_bytes(&[7; 32])),
+            consensus: None,
+        };
+        (state, dir)
+    }
+
+    /// Serve a router on a loopback port the way the node does, returning its address.
+    async fn listen(router: Router, state: AppState) -> String {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = format!("http://{}", listener.local_addr().unwrap());
+        let router = Arc::new(router);
+        tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let (state, router) = (state.clone(), router.clone());
+                let service = service_fn(move |req| handle_request(req, state.clone(), router.clone()));
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+        address
+    }
+
+    async fn serve(router: Router) -> (String, std::path::PathBuf) {
+        let (state, dir) = state().await;
+        (listen(router, state).await, dir)
+    }
+
+    #[test]
+    fn patterns_are_found_by_method_and_path() {
+        let router = receipts();
+        assert_eq!(router.pattern(&Method::GET, "/v1/receipts/abc"), Some("/v1/receipts/{uuid}"));
+        assert_eq!(router.pattern(&Method::HEAD, "/v1/receipts/abc"), Some("/v1/receipts/{uuid}"));
+        assert_eq!(router.pattern(&Method::POST, "/v1/receipts"), Some("/v1/receipts"));
+        assert_eq!(router.pattern(&Method::GET, "/api/receipt/abc"), Some("/api/receipt/{uuid}"));
+        assert_eq!(router.pattern(&Method::GET, "/v1/receipts/"), None);
+        assert_eq!(router.pattern(&Method::GET, "/v1/receipts/abc/proof"), None);
+        assert_eq!(router.pattern(&Method::DELETE, "/v1/receipts/abc"), None);
+    }
+
+    #[tokio::test]
+    async fn requests_reach_their_route_with_its_params() {
+        let (node, dir) = serve(receipts()).await;
+        let client = reqwest::Client::new();
+
+        let response = client.get(format!("{}/v1/receipts/abc", node)).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert!(response.headers().get("Deprecation").is_none());
+        assert_eq!(response.text().await.unwrap(), "GET abc");
+        let response = client.post(format!("{}/v1/receipts/abc", node)).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "POST abc");
+        let response = client.post(format!("{}/v1/receipts", node)).send().await.unwrap();
+        assert_eq!(response.text().await.unwrap(), "POST -");
+
+        let response = client.get(format!("{}/v1/unknown", node)).send().await.unwrap();
+        assert_eq!(response.status(), 404);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn aliases_point_at_the_current_route() {
+        let (node, dir) = serve(receipts()).await;
+        let response = reqwest::get(format!("{}/api/receipt/abc", node)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["Deprecation"], "true");
+        assert_eq!(response.headers()["link"], "</v1/receipts/{uuid}>; rel=\"successor-version\"");
+        assert_eq!(response.text().await.unwrap(), "GET abc");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn head_and_options_are_answered_for_every_path() {
+        let (node, dir) = serve(receipts()).await;
+        let client = reqwest::Client::new();
+
+        let response = client.head(format!("{}/v1/receipts/abc", node)).send().await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.text().await.unwrap(), "");
+
+        let response = client.request(reqwest::Method::OPTIONS, format!("{}/v1/receipts/abc", node)).send().await.unwrap();
+        assert_eq!(response.status(), 204);
+        assert_eq!(response.headers()["allow"], "HEAD, GET, POST, OPTIONS");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn other_methods_are_not_allowed() {
+        let (node, dir) = serve(receipts()).await;
+        let response = reqwest::Client::new().delete(format!("{}/v1/receipts", node)).send().await.unwrap();
+        assert_eq!(response.status(), 405);
+        assert_eq!(response.headers()["allow"], "POST, OPTIONS");
+        let problem: serde_json::Value = serde_json::from_slice(&response.bytes().await.unwrap()).unwrap();
+        assert_eq!(problem["code"], "method_not_allowed");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn middleware_added_first_runs_first() {
+        let tag = |name: &'static str| {
+            move |req: Request<IncomingBody>, state: AppState, next: Next| async move {
+                let mut response = next.run(req, state).await?;
+                let seen = response.headers().get("x-seen").map(|v| format!("{},{}", name, v.to_str().unwrap()));
+                response.headers_mut().insert("x-seen", header::HeaderValue::from_str(&seen.unwrap_or(name.to_string()))?);
+                Ok(response)
+            }
+        };
+        let (node, dir) = serve(Router::new().get("/v1/ping", echo).with(tag("outer")).with(tag("inner"))).await;
+        let response = reqwest::get(format!("{}/v1/ping", node)).await.unwrap();
+        assert_eq!(response.headers()["x-seen"], "outer,inner");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn metrics_are_prometheus_text_by_route_and_status() {
+        let (state, dir) = state().await;
+        let node = listen(handler::router(&state), state).await;
+        assert_eq!(reqwest::get(format!("{}/v1/ping", node)).await.unwrap().status(), 200);
+        assert_eq!(reqwest::get(format!("{}/v1/receipts/not-stored", node)).await.unwrap().status(), 404);
+
+        let response = reqwest::get(format!("{}/metrics", node)).await.unwrap();
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], prometheus::TEXT_FORMAT);
+        let text = response.text().await.unwrap();
+        let counted = |route: &str, status: &str| {
+            text.lines().any(|line| {
+                line.starts_with("contradiction_http_requests_total{")
+                    && line.contains(&format!("method=\"GET\",route=\"{}\",status=\"{}\"", route, status))
+            })
+        };
+        assert!(counted("/v1/ping", "200"), "{}", text);
+        // by pattern, not by the path asked for
+        assert!(counted("/v1/receipts/{uuid}", "404"), "{}", text);
+        assert!(text.contains("# TYPE contradiction_http_request_duration_seconds histogram"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
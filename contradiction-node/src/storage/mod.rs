@@ -0,0 +1,202 @@
+use std::sync::Arc;
+use async_trait::async_trait;
+use anyhow::Result;
+use crate::{blobs::BlobStore, config, consensus, models, outbox, receipts};
+
+pub mod sqlite;
+#[cfg(feature="postgres")]
+pub mod postgres;
+#[cfg(test)]
+pub mod testing;
+
+/// A receipt ready to be stored, already encoded and hashed for the ledger.
+#[cfg(any(feature="risc0", test))]
+pub struct NewReceipt<'a> {
+    pub uuid: &'a str,
+    /// Empty when the payload lives in the blob store
+    pub payload: &'a [u8],
+    pub encoding: &'a str,
+    pub blob: Option<&'a str>,
+    /// Size of the encoded payload, wherever it is kept
+    pub size: i64,
+    pub hash: &'a str,
+    pub meta: &'a models::ReceiptMetadata,
+}
+
+/// A stored receipt as found in the database, either inline or by the key of its blob.
+#[derive(sqlx::FromRow)]
+pub struct Payload {
+    pub uuid: String,
+    pub bytes: Vec<u8>,
+    pub encoding: String,
+    pub blob: Option<String>,
+}
+
+/// Connections of the database pool.
+pub struct PoolStatus {
+    pub size: u32,
+    pub idle: usize,
+    pub max: u32,
+}
+
+/// Everything the node keeps in its database. SQLite is the default backend, Postgres is available
+/// behind the `postgres` feature.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    /// Highest schema migration applied to the database.
+    async fn schema_version(&self) -> Result<i64>;
+    /// Where receipt payloads are kept outside the database, if anywhere.
+    fn blobs(&self) -> Option<&dyn BlobStore>;
+    fn pool_status(&self) -> PoolStatus;
+
+    // Nodes
+    async fn nodes(&self) -> Result<Vec<models::Node>>;
+    /// Returns false if the node was already known.
+    async fn add_node(&self, address: &str, port: u16) -> Result<bool>;
+    async fn remove_node(&self, address: &str, port: u16) -> Result<()>;
+
+    // Receipts
+    async fn receipt_exists(&self, uuid: &str) -> Result<bool>;
+    /// Store a receipt and append its ledger entry, both or neither.
+    #[cfg(any(feature="risc0", test))]
+    async fn insert_receipt(&self, receipt: &NewReceipt<'_>) -> Result<()> {
+        self.insert_receipts(std::slice::from_ref(receipt)).await
+    }
+    /// Store receipts in order and append their ledger entries, all of them or none.
+    #[cfg(any(feature="risc0", test))]
+    async fn insert_receipts(&self, receipts: &[NewReceipt<'_>]) -> Result<()>;
+    async fn receipt(&self, uuid: &str) -> Result<Option<Payload>>;
+    async fn receipt_metadata(&self, uuid: &str) -> Result<Option<models::ReceiptMetadata>>;
+    /// Receipts held inline in the database that are still JSON, or all of them with `all`, by UUID after `after`.
+    #[cfg(any(feature="risc0", test))]
+    async fn inline_receipts(&self, all: bool, after: &str, limit: i64) -> Result<Vec<Payload>>;
+    /// Replace the inline payload of a receipt, as long as it is still inline and in `from` encoding.
+    #[cfg(any(feature="risc0", test))]
+    async fn rewrite_receipt(&self, uuid: &str, from: &str, payload: &[u8], encoding: &str, blob: Option<&str>, size: i64) -> Result<()>;
+    /// Keys of every blob a receipt refers to.
+    async fn blob_keys(&self) -> Result<Vec<String>>;
+    async fn receipt_count(&self) -> Result<i64>;
+    /// Sorted UUIDs of all receipts, or only those starting with `prefix`.
+    async fn receipt_uuids(&self, prefix: Option<&str>) -> Result<Vec<String>>;
+    /// Up to `limit` receipts matching a listing, in its order.
+    async fn list_receipts(&self, listing: &receipts::Listing, limit: i64) -> Result<Vec<models::ReceiptSummary>>;
+
+    // Jobs
+    #[cfg(any(feature="risc0", test))]
+    async fn insert_job(&self, job: &models::Job, inputs: &str) -> Result<()>;
+    #[cfg(any(feature="risc0", test))]
+    async fn job(&self, uuid: &str) -> Result<Option<models::Job>>;
+    async fn queued_jobs(&self) -> Result<i64>;
+    /// Mark the oldest queued job as running, returning it with its inputs.
+    #[cfg(any(feature="risc0", test))]
+    async fn claim_job(&self, now: i64) -> Result<Option<(models::Job, String)>>;
+    #[cfg(any(feature="risc0", test))]
+    async fn finish_job(&self, uuid: &str, status: &str, error: Option<&str>, now: i64) -> Result<()>;
+    /// Queue jobs left running by a previous run again, returning how many there were.
+    #[cfg(any(feature="risc0", test))]
+    async fn requeue_jobs(&self, now: i64) -> Result<u64>;
+
+    // Guest ELFs
+    /// Store the ELF of an imported circuit, keeping an existing one.
+    #[cfg(feature="risc0")]
+    async fn insert_elf(&self, image_id: &str, circuit: &str, elf: &[u8]) -> Result<()>;
+    #[cfg(feature="risc0")]
+    async fn elf(&self, image_id: &str) -> Result<Option<Vec<u8>>>;
+
+    // Retention
+    /// Every circuit with stored receipts, None for receipts without one.
+    async fn circuits(&self) -> Result<Vec<Option<String>>>;
+    /// UUIDs of unpinned receipts of a circuit created before `before` and not among its
+    /// `keep_last` newest.
+    async fn expired_receipts(&self, circuit: Option<&str>, before: Option<i64>, keep_last: i64, limit: i64) -> Result<Vec<String>>;
+    /// Delete a receipt and everything queued for it, leaving a tombstone. Returns false if it was pinned or gone.
+    async fn prune_receipt(&self, uuid: &str, pruned_at: i64) -> Result<bool>;
+    async fn is_tombstoned(&self, uuid: &str) -> Result<bool>;
+    async fn tombstone_count(&self) -> Result<i64>;
+    /// Sorted UUIDs of the pruned receipts starting with `prefix`.
+    async fn tombstoned_uuids(&self, prefix: &str) -> Result<Vec<String>>;
+    /// Returns false if the receipt was already pinned.
+    async fn pin(&self, uuid: &str, created_at: i64) -> Result<bool>;
+    /// Returns false if the receipt was not pinned.
+    async fn unpin(&self, uuid: &str) -> Result<bool>;
+    async fn pins(&self) -> Result<Vec<models::Pin>>;
+
+    // Ledger
+    async fn ledger_head(&self) -> Result<Option<models::LedgerEntry>>;
+    async fn ledger_entry(&self, uuid: &str) -> Result<Option<models::LedgerEntry>>;
+    /// Entries with a sequence number above `seq`, in order.
+    async fn ledger_entries(&self, seq: i64) -> Result<Vec<models::LedgerEntry>>;
+
+    // Attestations
+    /// Store an attestation, replacing an earlier one by the same node.
+    async fn insert_attestation(&self, attestation: &models::Attestation) -> Result<()>;
+    #[cfg(any(feature="risc0", test))]
+    async fn attestations(&self, uuid: &str) -> Result<Vec<models::Attestation>>;
+    /// Pin a peer's public key unless one already is, returning the pinned key.
+    async fn pin_peer_key(&self, node: &str, public_key: &str) -> Result<String>;
+    /// Returns false if no key was pinned for the peer.
+    async fn unpin_peer_key(&self, node: &str) -> Result<bool>;
+
+    // Delivery jobs
+    /// Queue a payload for delivery to every given node.
+    #[cfg(any(feature="risc0", test))]
+    async fn enqueue_deliveries(&self, uuid: &str, path: &str, payload: &str, nodes: &[models::Node]) -> Result<()>;
+    async fn deliveries(&self, uuid: &str) -> Result<Vec<models::Delivery>>;
+    async fn due_deliveries(&self, now: i64, limit: i64) -> Result<Vec<outbox::Due>>;
+    async fn update_delivery(&self, due: &outbox::Due, update: &outbox::Update) -> Result<()>;
+    /// Drop payloads no pending delivery needs anymore.
+    async fn prune_payloads(&self) -> Result<()>;
+
+    // Consensus
+    /// Term, vote and commit index, if ever saved.
+    async fn consensus_state(&self) -> Result<Option<(i64, Option<String>, i64)>>;
+    async fn save_consensus_state(&self, term: i64, voted_for: Option<&str>, commit_index: i64) -> Result<()>;
+    async fn consensus_log(&self) -> Result<Vec<consensus::Entry>>;
+    async fn append_consensus_log(&self, entries: &[consensus::Entry]) -> Result<()>;
+    /// Delete log entries from index `from` on.
+    async fn truncate_consensus_log(&self, from: i64) -> Result<()>;
+}
+
+/// Open the configured backend and bring its schema up to date.
+pub async fn open(cfg: &config::DB, blobs: Option<Arc<dyn BlobStore>>) -> Result<Arc<dyn Storage>> {
+    match cfg.backend {
+        config::Backend::Sqlite => Ok(Arc::new(sqlite::SqliteStorage::open(cfg, blobs).await?)),
+        #[cfg(feature="postgres")]
+        config::Backend::Postgres => Ok(Arc::new(postgres::PostgresStorage::open(cfg, blobs).await?)),
+        #[cfg(not(feature="postgres"))]
+        config::Backend::Postgres => Err(anyhow::anyhow!("This node was built without the postgres feature")),
+    }
+}
+
+/// Pool sizes and acquire timeout from the config, the rest is left to sqlx.
+fn pool_options<DB: sqlx::Database>(cfg: &config::DB) -> sqlx::pool::PoolOptions<DB> {
+    let mut options = sqlx::pool::PoolOptions::new();
+    if let Some(min) = cfg.pool_min {
+        options = options.min_connections(min.into());
+    }
+    if let Some(max) = cfg.pool_max {
+        options = options.max_connections(max.into());
+    }
+    if let Some(timeout) = cfg.timeout {
+        options = options.acquire_timeout(std::time::Duration::from_secs(timeout.into()));
+    }
+    options
+}
+
+fn status<DB: sqlx::Database>(pool: &sqlx::Pool<DB>) -> PoolStatus {
+    PoolStatus { size: pool.size(), idle: pool.num_idle(), max: pool.options().get_max_connections() }
+}
+
+/// Refuse to run against a database written by a newer binary.
+fn check_version(current: i64, latest: i64) -> Result<()> {
+    if current > latest {
+        return Err(anyhow::anyhow!(
+            "Database schema version {} is newer than the {} this binary supports, refusing to start",
+            current, latest
+        ));
+    }
+    if current < latest {
+        log::info!("Migrating database schema from version {} to {}.", current, latest);
+    }
+    Ok(())
+}
@@ -0,0 +1,983 @@
+use async_trait::async_trait;
+use sqlx::{Sqlite, QueryBuilder, migrate::{MigrateDatabase, Migrator}, sqlite::{SqliteConnectOptions, SqliteJournalMode, SqlitePool, SqliteSynchronous}};
+use std::{str::FromStr, sync::Arc, time::Duration};
+use tokio::sync::Mutex;
+use anyhow::Result;
+use crate::{blobs::BlobStore, config, consensus, db, ledger, models, outbox, receipts};
+use super::{Payload, Storage};
+#[cfg(any(feature="risc0", test))]
+use super::NewReceipt;
+
+static MIGRATOR: Migrator = sqlx::migrate!("./migrations/sqlite");
+
+const LEDGER_COLUMNS: &str = "seq, uuid, receipt_hash, prev_hash, entry_hash, created_at";
+#[cfg(any(feature="risc0", test))]
+const JOB_COLUMNS: &str = "uuid, circuit, status, error, submitter, created_at, updated_at";
+
+pub struct SqliteStorage {
+    pool: SqlitePool,
+    // Appends read the ledger head and write after it, they must not interleave
+    append: Mutex<()>,
+    blobs: Option<Arc<dyn BlobStore>>,
+}
+
+impl SqliteStorage {
+    pub async fn open(cfg: &config::DB, blobs: Option<Arc<dyn BlobStore>>) -> Result<SqliteStorage> {
+        if !Sqlite::database_exists(&cfg.path).await.unwrap_or(false) {
+            log::info!("Creating database {}", &cfg.path);
+            Sqlite::create_database(&cfg.path).await?;
+        }
+        let pool = super::pool_options(cfg).connect_with(connect_options(cfg)?).await?;
+        let storage = SqliteStorage { pool, append: Mutex::new(()), blobs };
+        storage.log_settings().await?;
+        let version = storage.schema_version().await?;
+        super::check_version(version, latest_version())?;
+        MIGRATOR.run(&storage.pool).await?;
+        // the first migration is the one that brings the ledger into the schema
+        if version == 0 {
+            let added = storage.backfill_ledger().await?;
+            if added > 0 {
+                log::warn!("Added {} receipts stored before the ledger existed to it.", added);
+            }
+        }
+        Ok(storage)
+    }
+
+    /// Give receipts stored before the ledger existed an entry, returning how many were added. Only done
+    /// while the ledger is still empty, databases from before it are the only ones holding receipts without one.
+    async fn backfill_ledger(&self) -> Result<usize> {
+        let entries: i64 = sqlx::query_scalar("SELECT COUNT(*) FROM ledger").fetch_one(&self.pool).await?;
+        if entries > 0 {
+            return Ok(0);
+        }
+        let uuids: Vec<String> = sqlx::query_scalar("SELECT uuid FROM receipts ORDER BY rowid")
+            .fetch_all(&self.pool)
+            .await?;
+        if uuids.is_empty() {
+            return Ok(0);
+        }
+
+        // hashed like the ledger hashes them everywhere else, whether stored inline or in the blob store
+        let mut hashes = Vec::with_capacity(uuids.len());
+        for uuid in &uuids {
+            let payload = self.receipt(uuid).await?.ok_or_else(|| anyhow::anyhow!("Receipt {} disappeared during the backfill", uuid))?;
+            let encoding = payload.encoding.clone();
+            let hash = db::content_hash(&db::load_payload(self, payload).await?, &encoding)?
+                .ok_or_else(|| anyhow::anyhow!("Receipt {} is binary, only a build with risc0 can enter it into the ledger", uuid))?;
+            hashes.push(hash);
+        }
+
+        let _guard = self.append.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let mut head = None;
+        for (uuid, hash) in uuids.iter().zip(&hashes) {
+            let entry = ledger::next_entry(head, uuid, hash);
+            insert_ledger_entry(&mut tx, &entry).await?;
+            head = Some((entry.seq, entry.entry_hash));
+        }
+        tx.commit().await?;
+        Ok(uuids.len())
+    }
+
+    /// Log the settings in effect, as SQLite reports them rather than as configured.
+    async fn log_settings(&self) -> Result<()> {
+        let (journal_mode, synchronous, busy_timeout, foreign_keys): (String, String, String, String) = sqlx::query_as(
+            "
+            SELECT CAST(j.journal_mode AS TEXT), CAST(s.synchronous AS TEXT), CAST(b.timeout AS TEXT), CAST(f.foreign_keys AS TEXT)
+            FROM pragma_journal_mode() j, pragma_synchronous() s, pragma_busy_timeout() b, pragma_foreign_keys() f
+            ",
+        )
+        .fetch_one(&self.pool)
+        .await?;
+        let synchronous = match synchronous.as_str() {
+            "0" => "OFF",
+            "1" => "NORMAL",
+            "2" => "FULL",
+            "3" => "EXTRA",
+            other => other,
+        };
+        let options = self.pool.options();
+        log::info!(
+            "SQLite journal_mode={} synchronous={} busy_timeout={}ms foreign_keys={}, pool of {} to {} connections, acquire timeout {:?}.",
+            journal_mode,
+            synchronous,
+            busy_timeout,
+            if foreign_keys == "1" { "ON" } else { "OFF" },
+            options.get_min_connections(),
+            options.get_max_connections(),
+            options.get_acquire_timeout(),
+        );
+        Ok(())
+    }
+}
+
+/// Connection options with the configured pragmas. The ones sqlx knows about are set through it so
+/// they replace its defaults instead of running after them.
+fn connect_options(cfg: &config::DB) -> Result<SqliteConnectOptions> {
+    let mut options = SqliteConnectOptions::from_str(&cfg.path)?;
+    for (name, value) in cfg.pragmas()? {
+        options = match name.as_str() {
+            "journal_mode" => options.journal_mode(
+                SqliteJournalMode::from_str(&value).map_err(|_| anyhow::anyhow!("Unknown journal_mode {:?}", value))?,
+            ),
+            "synchronous" => options.synchronous(
+                SqliteSynchronous::from_str(&value).map_err(|_| anyhow::anyhow!("Unknown synchronous {:?}", value))?,
+            ),
+            "busy_timeout" => {
+                let ms: u64 = value.parse().map_err(|_| anyhow::anyhow!("busy_timeout {:?} is not milliseconds", value))?;
+                options.busy_timeout(Duration::from_millis(ms))
+            },
+            "foreign_keys" => options.foreign_keys(match value.to_lowercase().as_str() {
+                "on" | "true" | "yes" | "1" => true,
+                "off" | "false" | "no" | "0" => false,
+                _ => anyhow::bail!("foreign_keys {:?} is not on or off", value),
+            }),
+            _ => options.pragma(name, value),
+        };
+    }
+    Ok(options)
+}
+
+/// Latest migration version embedded in this binary.
+pub fn latest_version() -> i64 {
+    MIGRATOR.iter().map(|m| m.version).max().unwrap_or(0)
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn schema_version(&self) -> Result<i64> {
+        let tracked: bool = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = '_sqlx_migrations')")
+            .fetch_one(&self.pool)
+            .await?;
+        if !tracked {
+            return Ok(0);
+        }
+        let version: Option<i64> = sqlx::query_scalar("SELECT MAX(version) FROM _sqlx_migrations WHERE success")
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(version.unwrap_or(0))
+    }
+
+    fn blobs(&self) -> Option<&dyn BlobStore> {
+        self.blobs.as_deref()
+    }
+
+    fn pool_status(&self) -> super::PoolStatus {
+        super::status(&self.pool)
+    }
+
+    async fn nodes(&self) -> Result<Vec<models::Node>> {
+        let nodes = sqlx::query_as::<_, models::Node>("SELECT address, port FROM nodes ORDER BY address, port")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(nodes)
+    }
+
+    async fn add_node(&self, address: &str, port: u16) -> Result<bool> {
+        let result = sqlx::query(
+            "
+            INSERT INTO nodes (address, port)
+            VALUES (?, ?)
+            ON CONFLICT (address, port)
+            DO NOTHING
+            ",
+        )
+        .bind(address)
+        .bind(port)
+        .execute(&self.pool)
+        .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn remove_node(&self, address: &str, port: u16) -> Result<()> {
+        sqlx::query("DELETE FROM nodes WHERE address = ? AND port = ?")
+            .bind(address)
+            .bind(port)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn receipt_exists(&self, uuid: &str) -> Result<bool> {
+        let exists = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM receipts WHERE uuid = ?)")
+            .bind(uuid)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(exists)
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn insert_receipts(&self, receipts: &[NewReceipt<'_>]) -> Result<()> {
+        let _guard = self.append.lock().await;
+        let mut tx = self.pool.begin().await?;
+        let mut head: Option<(i64, String)> = sqlx::query_as("SELECT seq, entry_hash FROM ledger ORDER BY seq DESC LIMIT 1")
+            .fetch_optional(&mut *tx)
+            .await?;
+        for receipt in receipts {
+            let meta = receipt.meta;
+            sqlx::query(
+                "
+                INSERT INTO receipts (uuid, receipt, encoding, blob_hash, circuit, image_id, journal, proof_kind, prover_node, submitter, input_hash, created_at, proving_ms, cycles, size)
+                VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+                ",
+            )
+            .bind(receipt.uuid)
+            .bind(receipt.payload)
+            .bind(receipt.encoding)
+            .bind(receipt.blob)
+            .bind(&meta.circuit)
+            .bind(&meta.image_id)
+            .bind(&meta.journal)
+            .bind(&meta.proof_kind)
+            .bind(&meta.prover_node)
+            .bind(&meta.submitter)
+            .bind(&meta.input_hash)
+            .bind(meta.created_at.unwrap_or_else(|| chrono::Utc::now().timestamp()))
+            .bind(meta.proving_ms)
+            .bind(meta.cycles)
+            .bind(receipt.size)
+            .execute(&mut *tx)
+            .await?;
+
+            let entry = ledger::next_entry(head, receipt.uuid, receipt.hash);
+            insert_ledger_entry(&mut tx, &entry).await?;
+            head = Some((entry.seq, entry.entry_hash));
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn receipt(&self, uuid: &str) -> Result<Option<Payload>> {
+        let receipt = sqlx::query_as::<_, Payload>("SELECT uuid, receipt AS bytes, encoding, blob_hash AS blob FROM receipts WHERE uuid = ?")
+            .bind(uuid)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(receipt)
+    }
+
+    async fn receipt_metadata(&self, uuid: &str) -> Result<Option<models::ReceiptMetadata>> {
+        let meta = sqlx::query_as::<_, models::ReceiptMetadata>(
+            "
+            SELECT circuit, image_id, journal, proof_kind, prover_node, submitter, input_hash, created_at, proving_ms, cycles, size
+            FROM receipts WHERE uuid = ?
+            ",
+        )
+        .bind(uuid)
+        .fetch_optional(&self.pool)
+        .await?;
+        Ok(meta)
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn inline_receipts(&self, all: bool, after: &str, limit: i64) -> Result<Vec<Payload>> {
+        let receipts = sqlx::query_as::<_, Payload>(
+            "
+            SELECT uuid, receipt AS bytes, encoding, blob_hash AS blob FROM receipts
+            WHERE blob_hash IS NULL AND (encoding = 'json' OR ?) AND uuid > ?
+            ORDER BY uuid
+            LIMIT ?
+            ",
+        )
+        .bind(all)
+        .bind(after)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(receipts)
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn rewrite_receipt(&self, uuid: &str, from: &str, payload: &[u8], encoding: &str, blob: Option<&str>, size: i64) -> Result<()> {
+        sqlx::query("UPDATE receipts SET receipt = ?, encoding = ?, blob_hash = ?, size = ? WHERE uuid = ? AND encoding = ? AND blob_hash IS NULL")
+            .bind(payload)
+            .bind(encoding)
+            .bind(blob)
+            .bind(size)
+            .bind(uuid)
+            .bind(from)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn blob_keys(&self) -> Result<Vec<String>> {
+        let keys = sqlx::query_scalar("SELECT DISTINCT blob_hash FROM receipts WHERE blob_hash IS NOT NULL")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(keys)
+    }
+
+    async fn receipt_count(&self) -> Result<i64> {
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM receipts").fetch_one(&self.pool).await?;
+        Ok(count)
+    }
+
+    async fn receipt_uuids(&self, prefix: Option<&str>) -> Result<Vec<String>> {
+        let uuids = sqlx::query_scalar("SELECT uuid FROM receipts WHERE uuid LIKE ? ORDER BY uuid")
+            .bind(format!("{}%", prefix.unwrap_or_default()))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(uuids)
+    }
+
+    async fn list_receipts(&self, listing: &receipts::Listing, limit: i64) -> Result<Vec<models::ReceiptSummary>> {
+        let mut query: QueryBuilder<Sqlite> = QueryBuilder::new(
+            "
+            SELECT uuid, circuit, image_id, NULLIF(lower(hex(journal)), '') AS journal, proof_kind, prover_node, submitter,
+                input_hash, created_at, proving_ms, cycles, size
+            FROM receipts
+            ",
+        );
+        receipts::push_filters(&mut query, listing, limit)?;
+        let receipts = query
+            .build_query_as::<models::ReceiptSummary>()
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(receipts)
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn insert_job(&self, job: &models::Job, inputs: &str) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT INTO jobs (uuid, circuit, inputs, status, error, submitter, created_at, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?)
+            ",
+        )
+        .bind(&job.uuid)
+        .bind(&job.circuit)
+        .bind(inputs)
+        .bind(&job.status)
+        .bind(&job.error)
+        .bind(&job.submitter)
+        .bind(job.created_at)
+        .bind(job.updated_at)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn job(&self, uuid: &str) -> Result<Option<models::Job>> {
+        let job = sqlx::query_as::<_, models::Job>(&format!("SELECT {} FROM jobs WHERE uuid = ?", JOB_COLUMNS))
+            .bind(uuid)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(job)
+    }
+
+    async fn queued_jobs(&self) -> Result<i64> {
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM jobs WHERE status = 'queued'").fetch_one(&self.pool).await?;
+        Ok(count)
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn claim_job(&self, now: i64) -> Result<Option<(models::Job, String)>> {
+        loop {
+            let next: Option<(String, String)> = sqlx::query_as("SELECT uuid, inputs FROM jobs WHERE status = 'queued' ORDER BY created_at, uuid LIMIT 1")
+                .fetch_optional(&self.pool)
+                .await?;
+            let Some((uuid, inputs)) = next else {
+                return Ok(None);
+            };
+            // someone else may have claimed it in the meantime
+            let claimed = sqlx::query("UPDATE jobs SET status = 'running', updated_at = ? WHERE uuid = ? AND status = 'queued'")
+                .bind(now)
+                .bind(&uuid)
+                .execute(&self.pool)
+                .await?;
+            if claimed.rows_affected() > 0 {
+                return Ok(self.job(&uuid).await?.map(|job| (job, inputs)));
+            }
+        }
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn finish_job(&self, uuid: &str, status: &str, error: Option<&str>, now: i64) -> Result<()> {
+        sqlx::query("UPDATE jobs SET status = ?, error = ?, updated_at = ? WHERE uuid = ?")
+            .bind(status)
+            .bind(error)
+            .bind(now)
+            .bind(uuid)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn requeue_jobs(&self, now: i64) -> Result<u64> {
+        let result = sqlx::query("UPDATE jobs SET status = 'queued', updated_at = ? WHERE status = 'running'")
+            .bind(now)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    #[cfg(feature="risc0")]
+    async fn insert_elf(&self, image_id: &str, circuit: &str, elf: &[u8]) -> Result<()> {
+        sqlx::query("INSERT INTO elfs (image_id, circuit, elf) VALUES (?, ?, ?) ON CONFLICT (image_id) DO NOTHING")
+            .bind(image_id)
+            .bind(circuit)
+            .bind(elf)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    #[cfg(feature="risc0")]
+    async fn elf(&self, image_id: &str) -> Result<Option<Vec<u8>>> {
+        let elf = sqlx::query_scalar("SELECT elf FROM elfs WHERE image_id = ?")
+            .bind(image_id)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(elf)
+    }
+
+    async fn circuits(&self) -> Result<Vec<Option<String>>> {
+        let circuits = sqlx::query_scalar("SELECT DISTINCT circuit FROM receipts")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(circuits)
+    }
+
+    async fn expired_receipts(&self, circuit: Option<&str>, before: Option<i64>, keep_last: i64, limit: i64) -> Result<Vec<String>> {
+        let uuids = sqlx::query_scalar(
+            "
+            SELECT uuid FROM receipts
+            WHERE circuit IS ?
+                AND (? IS NULL OR created_at < ?)
+                AND uuid NOT IN (SELECT uuid FROM pins)
+                AND uuid NOT IN (SELECT uuid FROM receipts WHERE circuit IS ? ORDER BY created_at DESC, uuid DESC LIMIT ?)
+            LIMIT ?
+            ",
+        )
+        .bind(circuit)
+        .bind(before)
+        .bind(before)
+        .bind(circuit)
+        .bind(keep_last)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(uuids)
+    }
+
+    async fn prune_receipt(&self, uuid: &str, pruned_at: i64) -> Result<bool> {
+        let mut tx = self.pool.begin().await?;
+        let tombstoned = sqlx::query(
+            "
+            INSERT INTO tombstones (uuid, circuit, pruned_at)
+            SELECT uuid, circuit, ? FROM receipts
+            WHERE uuid = ? AND uuid NOT IN (SELECT uuid FROM pins)
+            ",
+        )
+        .bind(pruned_at)
+        .bind(uuid)
+        .execute(&mut *tx)
+        .await?;
+        if tombstoned.rows_affected() == 0 {
+            return Ok(false);
+        }
+        for table in ["receipts", "attestations", "outbox", "outbox_payloads"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE uuid = ?", table))
+                .bind(uuid)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    async fn is_tombstoned(&self, uuid: &str) -> Result<bool> {
+        let tombstoned = sqlx::query_scalar("SELECT EXISTS(SELECT 1 FROM tombstones WHERE uuid = ?)")
+            .bind(uuid)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(tombstoned)
+    }
+
+    async fn tombstone_count(&self) -> Result<i64> {
+        let count = sqlx::query_scalar("SELECT COUNT(*) FROM tombstones").fetch_one(&self.pool).await?;
+        Ok(count)
+    }
+
+    async fn tombstoned_uuids(&self, prefix: &str) -> Result<Vec<String>> {
+        let uuids = sqlx::query_scalar("SELECT uuid FROM tombstones WHERE uuid LIKE ? ORDER BY uuid")
+            .bind(format!("{}%", prefix))
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(uuids)
+    }
+
+    async fn pin(&self, uuid: &str, created_at: i64) -> Result<bool> {
+        let result = sqlx::query("INSERT INTO pins (uuid, created_at) VALUES (?, ?) ON CONFLICT (uuid) DO NOTHING")
+            .bind(uuid)
+            .bind(created_at)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn unpin(&self, uuid: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM pins WHERE uuid = ?")
+            .bind(uuid)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    async fn pins(&self) -> Result<Vec<models::Pin>> {
+        let pins = sqlx::query_as::<_, models::Pin>("SELECT uuid, created_at FROM pins ORDER BY created_at")
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(pins)
+    }
+
+    async fn ledger_head(&self) -> Result<Option<models::LedgerEntry>> {
+        let head = sqlx::query_as::<_, models::LedgerEntry>(&format!("SELECT {} FROM ledger ORDER BY seq DESC LIMIT 1", LEDGER_COLUMNS))
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(head)
+    }
+
+    async fn ledger_entry(&self, uuid: &str) -> Result<Option<models::LedgerEntry>> {
+        let entry = sqlx::query_as::<_, models::LedgerEntry>(&format!("SELECT {} FROM ledger WHERE uuid = ?", LEDGER_COLUMNS))
+            .bind(uuid)
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(entry)
+    }
+
+    async fn ledger_entries(&self, seq: i64) -> Result<Vec<models::LedgerEntry>> {
+        let entries = sqlx::query_as::<_, models::LedgerEntry>(&format!("SELECT {} FROM ledger WHERE seq > ? ORDER BY seq", LEDGER_COLUMNS))
+            .bind(seq)
+            .fetch_all(&self.pool)
+            .await?;
+        Ok(entries)
+    }
+
+    async fn insert_attestation(&self, attestation: &models::Attestation) -> Result<()> {
+        sqlx::query(
+            "
+            INSERT OR REPLACE INTO attestations (uuid, node, image_id, journal_hash, public_key, signature, created_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?)
+            ",
+        )
+        .bind(&attestation.uuid)
+        .bind(&attestation.node)
+        .bind(&attestation.image_id)
+        .bind(&attestation.journal_hash)
+        .bind(&attestation.public_key)
+        .bind(&attestation.signature)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn attestations(&self, uuid: &str) -> Result<Vec<models::Attestation>> {
+        let attestations = sqlx::query_as::<_, models::Attestation>(
+            "
+            SELECT uuid, node, image_id, journal_hash, public_key, signature
+            FROM attestations
+            WHERE uuid = ?
+            ORDER BY node
+            ",
+        )
+        .bind(uuid)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(attestations)
+    }
+
+    async fn pin_peer_key(&self, node: &str, public_key: &str) -> Result<String> {
+        sqlx::query(
+            "
+            INSERT INTO peer_keys (node, public_key, created_at)
+            VALUES (?, ?, ?)
+            ON CONFLICT (node)
+            DO NOTHING
+            ",
+        )
+        .bind(node)
+        .bind(public_key)
+        .bind(chrono::Utc::now().timestamp())
+        .execute(&self.pool)
+        .await?;
+        let pinned = sqlx::query_scalar("SELECT public_key FROM peer_keys WHERE node = ?")
+            .bind(node)
+            .fetch_one(&self.pool)
+            .await?;
+        Ok(pinned)
+    }
+
+    async fn unpin_peer_key(&self, node: &str) -> Result<bool> {
+        let result = sqlx::query("DELETE FROM peer_keys WHERE node = ?")
+            .bind(node)
+            .execute(&self.pool)
+            .await?;
+        Ok(result.rows_affected() > 0)
+    }
+
+    #[cfg(any(feature="risc0", test))]
+    async fn enqueue_deliveries(&self, uuid: &str, path: &str, payload: &str, nodes: &[models::Node]) -> Result<()> {
+        let now = chrono::Utc::now().timestamp();
+        let mut tx = self.pool.begin().await?;
+
+        sqlx::query("INSERT OR REPLACE INTO outbox_payloads (uuid, path, payload) VALUES (?, ?, ?)")
+            .bind(uuid)
+            .bind(path)
+            .bind(payload)
+            .execute(&mut *tx)
+            .await?;
+
+        for node in nodes {
+            sqlx::query(
+                "
+                INSERT INTO outbox (uuid, address, port, next_retry_at, updated_at)
+                VALUES (?, ?, ?, ?, ?)
+                ON CONFLICT (uuid, address, port)
+                DO NOTHING
+                ",
+            )
+            .bind(uuid)
+            .bind(&node.address)
+            .bind(node.port)
+            .bind(now)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn deliveries(&self, uuid: &str) -> Result<Vec<models::Delivery>> {
+        let deliveries = sqlx::query_as::<_, models::Delivery>(
+            "
+            SELECT address, port, status, attempts, next_retry_at, last_error, updated_at
+            FROM outbox
+            WHERE uuid = ?
+            ORDER BY address, port
+            ",
+        )
+        .bind(uuid)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(deliveries)
+    }
+
+    async fn due_deliveries(&self, now: i64, limit: i64) -> Result<Vec<outbox::Due>> {
+        let due = sqlx::query_as::<_, outbox::Due>(
+            "
+            SELECT o.uuid, o.address, o.port, o.attempts, p.path, p.payload
+            FROM outbox o
+            JOIN outbox_payloads p ON p.uuid = o.uuid
+            WHERE o.status = 'pending' AND o.next_retry_at <= ?
+            ORDER BY o.next_retry_at
+            LIMIT ?
+            ",
+        )
+        .bind(now)
+        .bind(limit)
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(due)
+    }
+
+    async fn update_delivery(&self, due: &outbox::Due, update: &outbox::Update) -> Result<()> {
+        sqlx::query(
+            "
+            UPDATE outbox
+            SET status = ?, attempts = ?, next_retry_at = ?, last_error = ?, updated_at = ?
+            WHERE uuid = ? AND address = ? AND port = ?
+            ",
+        )
+        .bind(update.status)
+        .bind(update.attempts)
+        .bind(update.next_retry_at)
+        .bind(&update.last_error)
+        .bind(update.updated_at)
+        .bind(&due.uuid)
+        .bind(&due.address)
+        .bind(due.port)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn prune_payloads(&self) -> Result<()> {
+        // the delivery rows are kept for the status API
+        sqlx::query(
+            "
+            DELETE FROM outbox_payloads
+            WHERE NOT EXISTS (
+                SELECT 1 FROM outbox o WHERE o.uuid = outbox_payloads.uuid AND o.status = 'pending'
+            )
+            ",
+        )
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn consensus_state(&self) -> Result<Option<(i64, Option<String>, i64)>> {
+        let state = sqlx::query_as("SELECT term, voted_for, commit_index FROM consensus_state WHERE id = 1")
+            .fetch_optional(&self.pool)
+            .await?;
+        Ok(state)
+    }
+
+    async fn save_consensus_state(&self, term: i64, voted_for: Option<&str>, commit_index: i64) -> Result<()> {
+        sqlx::query("INSERT OR REPLACE INTO consensus_state (id, term, voted_for, commit_index) VALUES (1, ?, ?, ?)")
+            .bind(term)
+            .bind(voted_for)
+            .bind(commit_index)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+
+    async fn consensus_log(&self) -> Result<Vec<consensus::Entry>> {
+        let log = sqlx::query_as::<_, consensus::Entry>(
+            "SELECT log_index, term, uuid, image_id, journal_hash, submitter FROM consensus_log ORDER BY log_index"
+        )
+        .fetch_all(&self.pool)
+        .await?;
+        Ok(log)
+    }
+
+    async fn append_consensus_log(&self, entries: &[consensus::Entry]) -> Result<()> {
+        let mut tx = self.pool.begin().await?;
+        for entry in entries {
+            sqlx::query(
+                "
+                INSERT INTO consensus_log (log_index, term, uuid, image_id, journal_hash, submitter)
+                VALUES (?, ?, ?, ?, ?, ?)
+                ",
+            )
+            .bind(entry.index)
+            .bind(entry.term)
+            .bind(&entry.uuid)
+            .bind(&entry.image_id)
+            .bind(&entry.journal_hash)
+            .bind(&entry.submitter)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    async fn truncate_consensus_log(&self, from: i64) -> Result<()> {
+        sqlx::query("DELETE FROM consensus_log WHERE log_index >= ?")
+            .bind(from)
+            .execute(&self.pool)
+            .await?;
+        Ok(())
+    }
+}
+
+async fn insert_ledger_entry(conn: &mut sqlx::SqliteConnection, entry: &models::LedgerEntry) -> Result<()> {
+    sqlx::query(
+        "
+        INSERT INTO ledger (seq, uuid, receipt_hash, prev_hash, entry_hash, created_at)
+        VALUES (?, ?, ?, ?, ?, ?)
+        ",
+    )
+    .bind(entry.seq)
+    .bind(&entry.uuid)
+    .bind(&entry.receipt_hash)
+    .bind(&entry.prev_hash)
+    .bind(&entry.entry_hash)
+    .bind(entry.created_at)
+    .execute(&mut *conn)
+    .await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FIRST: &str = r#"{"journal":"first"}"#;
+    const SECOND: &str = r#"{"journal":"second"}"#;
+
+    fn temp_dir() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("contradiction-sqlite-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn db_config(dir: &std::path::Path) -> config::DB {
+        config::DB { path: dir.join("node.db").display().to_string(), ..config::Config::default().db }
+    }
+
+    /// A database as nodes left it before migrations and the ledger existed.
+    async fn unmigrated(cfg: &config::DB) {
+        Sqlite::create_database(&cfg.path).await.unwrap();
+        let pool = SqlitePool::connect(&cfg.path).await.unwrap();
+        sqlx::query("CREATE TABLE receipts (uuid TEXT PRIMARY KEY, receipt TEXT NOT NULL)").execute(&pool).await.unwrap();
+        for (uuid, receipt) in [("a", FIRST), ("b", SECOND)] {
+            sqlx::query("INSERT INTO receipts (uuid, receipt) VALUES (?, ?)").bind(uuid).bind(receipt).execute(&pool).await.unwrap();
+        }
+        pool.close().await;
+    }
+
+    #[tokio::test]
+    async fn migrating_in_the_ledger_enters_existing_receipts() {
+        let dir = temp_dir();
+        let cfg = db_config(&dir);
+        unmigrated(&cfg).await;
+
+        let storage = SqliteStorage::open(&cfg, None).await.unwrap();
+        let hashes: Vec<String> = storage.ledger_entries(0).await.unwrap().into_iter().map(|e| e.receipt_hash).collect();
+        assert_eq!(hashes, [ledger::receipt_hash(FIRST.as_bytes()), ledger::receipt_hash(SECOND.as_bytes())]);
+        assert_eq!(ledger::verify(&storage).await.unwrap(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_wiped_ledger_is_not_filled_again() {
+        let dir = temp_dir();
+        let cfg = db_config(&dir);
+        unmigrated(&cfg).await;
+        let storage = SqliteStorage::open(&cfg, None).await.unwrap();
+        sqlx::query("DELETE FROM ledger").execute(&storage.pool).await.unwrap();
+        storage.pool.close().await;
+
+        let storage = SqliteStorage::open(&cfg, None).await.unwrap();
+        assert!(storage.ledger_entries(0).await.unwrap().is_empty());
+        let err = ledger::verify(&storage).await.unwrap_err().to_string();
+        assert!(err.contains("ledger is empty"), "{}", err);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn the_backfill_hashes_receipts_in_the_blob_store() {
+        let dir = temp_dir();
+        let blobs: Arc<dyn BlobStore> = Arc::new(crate::blobs::fs::FsBlobStore::open(&dir.join("blobs").display().to_string()).unwrap());
+        let storage = SqliteStorage::open(&db_config(&dir), Some(blobs.clone())).await.unwrap();
+
+        let key = crate::blobs::key(FIRST.as_bytes());
+        blobs.put(&key, FIRST.as_bytes()).await.unwrap();
+        sqlx::query("INSERT INTO receipts (uuid, receipt, encoding, blob_hash) VALUES ('a', x'', 'json', ?)")
+            .bind(&key)
+            .execute(&storage.pool)
+            .await
+            .unwrap();
+
+        assert_eq!(storage.backfill_ledger().await.unwrap(), 1);
+        let entry = storage.ledger_entry("a").await.unwrap().unwrap();
+        assert_eq!(entry.receipt_hash, ledger::receipt_hash(FIRST.as_bytes()));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn a_batch_with_a_failing_receipt_stores_none() {
+        let dir = temp_dir();
+        let storage = SqliteStorage::open(&db_config(&dir), None).await.unwrap();
+        let meta = models::ReceiptMetadata::default();
+        let receipt = |uuid, payload: &'static str| NewReceipt {
+            uuid,
+            payload: payload.as_bytes(),
+            encoding: "json",
+            blob: None,
+            size: payload.len() as i64,
+            hash: "",
+            meta: &meta,
+        };
+
+        let err = storage.insert_receipts(&[receipt("a", FIRST), receipt("a", SECOND)]).await;
+        assert!(err.is_err());
+        assert!(!storage.receipt_exists("a").await.unwrap());
+        assert!(storage.ledger_entries(0).await.unwrap().is_empty());
+
+        storage.insert_receipts(&[receipt("a", FIRST), receipt("b", SECOND)]).await.unwrap();
+        assert_eq!(storage.ledger_entries(0).await.unwrap().len(), 2);
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn jobs_are_claimed_oldest_first_and_requeued_after_a_restart() {
+        let dir = temp_dir();
+        let storage = SqliteStorage::open(&db_config(&dir), None).await.unwrap();
+        for (uuid, created_at) in [("b", 2), ("a", 1), ("c", 3)] {
+            let job = models::Job {
+                uuid: uuid.to_string(),
+                circuit: "hypotenuse".to_string(),
+                status: "queued".to_string(),
+                error: None,
+                submitter: None,
+                created_at,
+                updated_at: created_at,
+            };
+            storage.insert_job(&job, "{}").await.unwrap();
+        }
+
+        let mut claimed = vec![];
+        while let Some((job, _)) = storage.claim_job(10).await.unwrap() {
+            assert_eq!(job.status, "running");
+            claimed.push(job.uuid);
+        }
+        assert_eq!(claimed, ["a", "b", "c"]);
+
+        storage.finish_job("a", "failed", Some("bad input"), 11).await.unwrap();
+        assert_eq!(storage.requeue_jobs(12).await.unwrap(), 2);
+        assert_eq!(storage.queued_jobs().await.unwrap(), 2);
+        assert_eq!(storage.job("a").await.unwrap().unwrap().error.as_deref(), Some("bad input"));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[cfg(not(feature="risc0"))]
+    #[tokio::test]
+    async fn binary_receipts_do_not_stop_verification_without_risc0() {
+        let dir = temp_dir();
+        let storage = SqliteStorage::open(&db_config(&dir), None).await.unwrap();
+        let meta = models::ReceiptMetadata::default();
+        let hash = ledger::receipt_hash(FIRST.as_bytes());
+        let receipt = |uuid, payload: &'static [u8], encoding| NewReceipt {
+            uuid,
+            payload,
+            encoding,
+            blob: None,
+            size: payload.len() as i64,
+            hash: &hash,
+            meta: &meta,
+        };
+        // the hash of a binary receipt is taken over its JSON, which this build can't get to
+        storage.insert_receipts(&[receipt("a", FIRST.as_bytes(), db::JSON), receipt("b", b"\x01\x02", db::BINCODE_ZSTD)]).await.unwrap();
+        assert_eq!(ledger::verify(&storage).await.unwrap(), 2);
+
+        // JSON receipts are still checked
+        sqlx::query("UPDATE receipts SET receipt = ? WHERE uuid = 'a'").bind(SECOND.as_bytes()).execute(&storage.pool).await.unwrap();
+        assert!(ledger::verify(&storage).await.is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn pragmas_are_checked_and_applied() {
+        let dir = temp_dir();
+        let with = |pragma: &str| config::DB { pragma: Some(pragma.to_string()), ..db_config(&dir) };
+        for malformed in ["journal_mode=SIDEWAYS", "synchronous=SOMETIMES", "busy_timeout=5s", "foreign_keys=maybe", "cache_size"] {
+            assert!(connect_options(&with(malformed)).is_err(), "{} was accepted", malformed);
+        }
+
+        let storage = SqliteStorage::open(&with("journal_mode=delete;synchronous=FULL;busy_timeout=1234;foreign_keys=off;cache_size=-4000"), None)
+            .await
+            .unwrap();
+        let pragma = |name: &'static str| {
+            let pool = storage.pool.clone();
+            async move { sqlx::query_scalar::<_, String>(&format!("SELECT CAST((SELECT * FROM pragma_{}) AS TEXT)", name)).fetch_one(&pool).await.unwrap() }
+        };
+        assert_eq!(pragma("journal_mode").await, "delete");
+        // FULL
+        assert_eq!(pragma("synchronous").await, "2");
+        assert_eq!(pragma("busy_timeout").await, "1234");
+        assert_eq!(pragma("foreign_keys").await, "0");
+        assert_eq!(pragma("cache_size").await, "-4000");
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
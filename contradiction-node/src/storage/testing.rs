@@ -0,0 +1,57 @@
+//! Throwaway stores for tests.
+use std::path::PathBuf;
+use super::{sqlite::SqliteStorage, NewReceipt, Storage};
+use crate::{config, ledger, models};
+
+/// A SQLite store in a directory of its own, removed when it is dropped.
+pub struct TempStore {
+    pub db: SqliteStorage,
+    pub dir: PathBuf,
+}
+
+impl std::ops::Deref for TempStore {
+    type Target = SqliteStorage;
+
+    fn deref(&self) -> &SqliteStorage {
+        &self.db
+    }
+}
+
+impl Drop for TempStore {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.dir);
+    }
+}
+
+pub fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("contradiction-{}-{}", name, uuid::Uuid::new_v4()));
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+pub fn db_config(dir: &std::path::Path) -> config::DB {
+    config::DB { path: dir.join("node.db").display().to_string(), ..config::Config::default().db }
+}
+
+pub async fn sqlite() -> TempStore {
+    let dir = temp_dir("store");
+    let db = SqliteStorage::open(&db_config(&dir), None).await.unwrap();
+    TempStore { db, dir }
+}
+
+/// Store a JSON receipt with the given metadata, its payload is the UUID.
+pub async fn insert_receipt(db: &dyn Storage, uuid: &str, meta: &models::ReceiptMetadata) {
+    let payload = serde_json::to_vec(uuid).unwrap();
+    let hash = ledger::receipt_hash(&payload);
+    db.insert_receipts(&[NewReceipt {
+        uuid,
+        payload: &payload,
+        encoding: crate::db::JSON,
+        blob: None,
+        size: payload.len() as i64,
+        hash: &hash,
+        meta,
+    }])
+    .await
+    .unwrap();
+}
@@ -0,0 +1,120 @@
+use sha2::{Digest, Sha256};
+use anyhow::Result;
+use crate::{error::ApiError, models, storage::Storage};
+
+/// A range is every UUID starting with its prefix, it splits into one range per next hex digit.
+const DIGITS: &str = "0123456789abcdef";
+/// Ranges holding more receipts than this are compared digit by digit rather than by listing their UUIDs.
+#[cfg(any(feature="risc0", test))]
+pub const MAX_LISTED: usize = 256;
+/// Prefixes stop short of the first `-` of a UUID.
+pub const MAX_PREFIX: usize = 8;
+
+fn hash_range(uuids: &[String]) -> String {
+    let mut hasher = Sha256::new();
+    for uuid in uuids {
+        hasher.update(uuid.as_bytes());
+        hasher.update(b"\n");
+    }
+    hex::encode(hasher.finalize())
+}
+
+fn is_prefix(prefix: &str) -> bool {
+    prefix.len() <= MAX_PREFIX && prefix.chars().all(|c| DIGITS.contains(c))
+}
+
+/// Sorted UUIDs of every receipt stored locally whose UUID starts with `prefix`.
+pub async fn range_uuids(db: &dyn Storage, prefix: &str) -> Result<Vec<String>> {
+    if prefix.is_empty() || !is_prefix(prefix) {
+        return Err(ApiError::Validation(format!("Invalid sync range prefix, expected 1 to {} lowercase hex digits", MAX_PREFIX)).into());
+    }
+    db.receipt_uuids(Some(prefix)).await
+}
+
+/// Compact description of the local receipts starting with `prefix`, one hash per range one digit longer.
+/// Pruned receipts count as held, so a range pruned here still matches peers that kept it.
+pub async fn summary(db: &dyn Storage, prefix: &str) -> Result<Vec<models::SyncRange>> {
+    if prefix.len() >= MAX_PREFIX || !is_prefix(prefix) {
+        return Err(ApiError::Validation(format!("Invalid sync range prefix, expected at most {} lowercase hex digits", MAX_PREFIX - 1)).into());
+    }
+    let mut uuids = db.receipt_uuids(Some(prefix)).await?;
+    uuids.extend(db.tombstoned_uuids(prefix).await?);
+    uuids.sort();
+    uuids.dedup();
+
+    let summary = DIGITS
+        .chars()
+        .map(|digit| {
+            let prefix = format!("{}{}", prefix, digit);
+            let range: Vec<String> = uuids.iter().filter(|u| u.starts_with(&prefix)).cloned().collect();
+            models::SyncRange { count: range.len(), hash: hash_range(&range), prefix }
+        })
+        .collect();
+    Ok(summary)
+}
+
+/// Remote ranges holding receipts where the remote summary differs from the local one.
+#[cfg(any(feature="risc0", test))]
+pub fn differing_ranges<'a>(local: &[models::SyncRange], remote: &'a [models::SyncRange]) -> Vec<&'a models::SyncRange> {
+    remote
+        .iter()
+        .filter(|r| r.count > 0)
+        .filter(|r| !local.iter().any(|l| l.prefix == r.prefix && l.hash == r.hash))
+        .collect()
+}
+
+/// Whether a differing range is split further instead of exchanging its UUIDs.
+#[cfg(any(feature="risc0", test))]
+pub fn subdivide(range: &models::SyncRange) -> bool {
+    range.count > MAX_LISTED && range.prefix.len() < MAX_PREFIX
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::storage::testing;
+
+    fn range(prefix: &str, count: usize, hash: &str) -> models::SyncRange {
+        models::SyncRange { prefix: prefix.to_string(), count, hash: hash.to_string() }
+    }
+
+    #[test]
+    fn only_differing_ranges_with_receipts_are_compared() {
+        let local = vec![range("0", 1, "a"), range("1", 1, "b"), range("2", 0, "e")];
+        let remote = vec![range("0", 1, "a"), range("1", 2, "c"), range("2", 0, "e"), range("3", 1, "d"), range("4", 0, "e")];
+        let differing: Vec<&str> = differing_ranges(&local, &remote).iter().map(|r| r.prefix.as_str()).collect();
+        assert_eq!(differing, ["1", "3"]);
+    }
+
+    #[test]
+    fn large_ranges_are_subdivided_up_to_the_longest_prefix() {
+        assert!(!subdivide(&range("a", MAX_LISTED, "")));
+        assert!(subdivide(&range("a", MAX_LISTED + 1, "")));
+        assert!(!subdivide(&range("abcdef01", MAX_LISTED + 1, "")));
+    }
+
+    #[tokio::test]
+    async fn pruned_receipts_still_count_towards_the_summary() {
+        let (kept, pruned) = (testing::sqlite().await, testing::sqlite().await);
+        let meta = models::ReceiptMetadata::default();
+        for uuid in ["a1", "a2", "b1"] {
+            testing::insert_receipt(&*kept, uuid, &meta).await;
+            testing::insert_receipt(&*pruned, uuid, &meta).await;
+        }
+        assert!(pruned.prune_receipt("a2", 1).await.unwrap());
+
+        let hashes = |summary: Vec<models::SyncRange>| summary.into_iter().map(|r| (r.prefix, r.count, r.hash)).collect::<Vec<_>>();
+        assert_eq!(hashes(summary(&*kept, "").await.unwrap()), hashes(summary(&*pruned, "").await.unwrap()));
+        // yet only what is stored is listed
+        assert_eq!(range_uuids(&*pruned, "a").await.unwrap(), ["a1"]);
+    }
+
+    #[test]
+    fn prefixes_are_lowercase_hex_before_the_first_dash() {
+        assert!(is_prefix(""));
+        assert!(is_prefix("0af39"));
+        assert!(!is_prefix("0A"));
+        assert!(!is_prefix("0af39b2c-"));
+        assert!(!is_prefix("0af39b2c1"));
+    }
+}
@@ -0,0 +1,168 @@
+use std::path::Path;
+use std::sync::RwLock;
+use anyhow::Result;
+use tracing_appender::{non_blocking::WorkerGuard, rolling};
+use tracing_subscriber::{filter::LevelFilter, fmt::MakeWriter, layer::SubscriberExt, reload, util::SubscriberInitExt, Layer, Registry};
+use crate::config;
+
+/// Header carrying the UUID of a job, which is also its receipt's, from node to node.
+pub const JOB_HEADER: &str = "X-Job-Id";
+
+type Output = Box<dyn Layer<Registry> + Send + Sync>;
+
+lazy_static! {
+    static ref LEVEL: RwLock<Option<reload::Handle<LevelFilter, Registry>>> = RwLock::new(None);
+}
+
+fn level(level: log::LevelFilter) -> LevelFilter {
+    match level {
+        log::LevelFilter::Off => LevelFilter::OFF,
+        log::LevelFilter::Error => LevelFilter::ERROR,
+        log::LevelFilter::Warn => LevelFilter::WARN,
+        log::LevelFilter::Info => LevelFilter::INFO,
+        log::LevelFilter::Debug => LevelFilter::DEBUG,
+        log::LevelFilter::Trace => LevelFilter::TRACE,
+    }
+}
+
+fn output<W>(format: config::LogFormat, writer: W) -> Output
+where
+    W: for<'w> MakeWriter<'w> + Send + Sync + 'static,
+{
+    let layer = tracing_subscriber::fmt::layer().with_writer(writer).with_ansi(false);
+    match format {
+        config::LogFormat::Text => layer.boxed(),
+        config::LogFormat::Json => layer.json().with_current_span(true).with_span_list(true).boxed(),
+    }
+}
+
+/// The log file, rotated ones are named after it with the date and time appended.
+fn appender(cfg: &config::Log) -> Result<rolling::RollingFileAppender> {
+    let path = Path::new(&cfg.file_output);
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow::anyhow!("log.file_output {} is not a file", cfg.file_output))?;
+    let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty()).unwrap_or(Path::new("."));
+    let rotation = match cfg.rotation {
+        config::Rotation::Never => rolling::Rotation::NEVER,
+        config::Rotation::Minutely => rolling::Rotation::MINUTELY,
+        config::Rotation::Hourly => rolling::Rotation::HOURLY,
+        config::Rotation::Daily => rolling::Rotation::DAILY,
+    };
+    let mut builder = rolling::Builder::new().rotation(rotation).filename_prefix(name.to_string_lossy());
+    if let Some(max) = cfg.max_files {
+        builder = builder.max_log_files(max);
+    }
+    Ok(builder.build(dir)?)
+}
+
+/// Send logs and spans to the log file and stdout in the configured format, and to the OTLP collector if
+/// there is one. The `log` macros end up there too, in the span they were called in. Keep the guard until
+/// exit, dropping it flushes the file.
+pub fn init(cfg: &config::Config) -> Result<WorkerGuard> {
+    let (file, guard) = tracing_appender::non_blocking(appender(&cfg.log)?);
+    let mut outputs: Vec<Output> = vec![output(cfg.log.format, file)];
+    if cfg.log.stdout {
+        outputs.push(output(cfg.log.format, std::io::stdout));
+    }
+    #[cfg(feature = "otlp")]
+    if let Some(endpoint) = &cfg.tracing.otlp_endpoint {
+        outputs.push(otlp::layer(endpoint, &cfg.tracing.service_name)?);
+    }
+
+    // reloadable, unlike the max level of the log crate it is set next to
+    let (filter, handle) = reload::Layer::new(level(cfg.log.level));
+    tracing_subscriber::registry().with(outputs.with_filter(filter)).try_init()?;
+    log::set_max_level(cfg.log.level);
+    *LEVEL.write().unwrap_or_else(|e| e.into_inner()) = Some(handle);
+    Ok(guard)
+}
+
+/// Change the level of logs and spans.
+pub fn set_level(new: log::LevelFilter) {
+    log::set_max_level(new);
+    if let Some(handle) = LEVEL.read().unwrap_or_else(|e| e.into_inner()).as_ref() {
+        if let Err(e) = handle.reload(level(new)) {
+            log::error!("Failed to change the tracing level: {}", e);
+        }
+    }
+}
+
+/// Export the spans still buffered.
+pub fn shutdown() {
+    #[cfg(feature = "otlp")]
+    opentelemetry::global::shutdown_tracer_provider();
+}
+
+/// A peer's client passing the job and the current trace on, so what the peer does about it can be
+/// found with the rest.
+pub fn propagate(client: contradiction_api::Client, job: &str) -> contradiction_api::Client {
+    let mut headers = reqwest::header::HeaderMap::new();
+    if let Ok(value) = reqwest::header::HeaderValue::from_str(job) {
+        headers.insert(JOB_HEADER, value);
+    }
+    #[cfg(feature = "otlp")]
+    otlp::inject(&mut headers);
+    client.headers(headers)
+}
+
+/// Span of an incoming request, continuing the caller's trace if it sent one.
+pub fn request_span(method: &str, route: &str, headers: &hyper::HeaderMap) -> tracing::Span {
+    let job = headers.get(JOB_HEADER).and_then(|v| v.to_str().ok());
+    let span = tracing::info_span!("request", method, route, job);
+    #[cfg(feature = "otlp")]
+    otlp::set_parent(&span, headers);
+    span
+}
+
+#[cfg(feature = "otlp")]
+mod otlp {
+    use opentelemetry::{global, propagation::{Extractor, Injector}, KeyValue};
+    use opentelemetry_otlp::WithExportConfig;
+    use opentelemetry_sdk::{propagation::TraceContextPropagator, runtime, trace, Resource};
+    use tracing_opentelemetry::OpenTelemetrySpanExt;
+    use tracing_subscriber::Layer;
+
+    /// Batches spans to the collector over gRPC, trace context travels in the W3C `traceparent` header.
+    pub fn layer(endpoint: &str, service_name: &str) -> anyhow::Result<super::Output> {
+        global::set_text_map_propagator(TraceContextPropagator::new());
+        let tracer = opentelemetry_otlp::new_pipeline()
+            .tracing()
+            .with_exporter(opentelemetry_otlp::new_exporter().tonic().with_endpoint(endpoint))
+            .with_trace_config(trace::config().with_resource(Resource::new([KeyValue::new("service.name", service_name.to_string())])))
+            .install_batch(runtime::Tokio)?;
+        Ok(tracing_opentelemetry::layer().with_tracer(tracer).boxed())
+    }
+
+    struct Outgoing<'a>(&'a mut reqwest::header::HeaderMap);
+
+    impl Injector for Outgoing<'_> {
+        fn set(&mut self, key: &str, value: String) {
+            if let (Ok(name), Ok(value)) = (reqwest::header::HeaderName::from_bytes(key.as_bytes()), reqwest::header::HeaderValue::from_str(&value)) {
+                self.0.insert(name, value);
+            }
+        }
+    }
+
+    struct Incoming<'a>(&'a hyper::HeaderMap);
+
+    impl Extractor for Incoming<'_> {
+        fn get(&self, key: &str) -> Option<&str> {
+            self.0.get(key).and_then(|v| v.to_str().ok())
+        }
+
+        fn keys(&self) -> Vec<&str> {
+            self.0.keys().map(|k| k.as_str()).collect()
+        }
+    }
+
+    pub fn inject(headers: &mut reqwest::header::HeaderMap) {
+        let context = tracing::Span::current().context();
+        global::get_text_map_propagator(|propagator| propagator.inject_context(&context, &mut Outgoing(headers)));
+    }
+
+    pub fn set_parent(span: &tracing::Span, headers: &hyper::HeaderMap) {
+        let parent = global::get_text_map_propagator(|propagator| propagator.extract(&Incoming(headers)));
+        span.set_parent(parent);
+    }
+}
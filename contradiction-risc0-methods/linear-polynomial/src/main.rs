@@ -1,21 +1,17 @@
 #![no_main]
-// If you want to try std support, also update the guest Cargo.toml file
-#![no_std]  // std support is experimental
-
+#![no_std]
 
 use risc0_zkvm::guest::env;
 
 risc0_zkvm::guest::entry!(main);
 
-
 fn main() {
-    // TODO: Implement your guest code here
-
-    // read the input
-    let input: u32 = env::read();
+    let a: u32 = env::read();
+    let b: u32 = env::read();
+    let x: u32 = env::read();
 
-    // TODO: do something with the input
+    // overflowing inputs have no value to prove, the guest panics and no receipt is made
+    let value = a.checked_mul(x).and_then(|ax| ax.checked_add(b)).expect("a * x + b overflows u32");
 
-    // write public output to the journal
-    env::commit(&input);
+    env::commit(&value);
 }